@@ -43,7 +43,7 @@ public function handle(RustDaemonClient $daemon): void
         $domains = WebDomain::all();
         foreach ($domains as $domain) {
             try {
-                $size = $daemon->call('get_directory_size', ['path' => $domain->root_path]);
+                $size = $daemon->runJob('get_directory_size', ['path' => $domain->root_path]);
                 if (is_numeric($size)) {
                     $domain->update(['size_bytes' => (int) $size]);
                 }
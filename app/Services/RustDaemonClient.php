@@ -29,6 +29,11 @@ class RustDaemonClient
         'toggle_firewall',
     ];
 
+    /**
+     * Seconds between get_job polls while waiting for a job
+     */
+    private int $jobPollInterval = 1;
+
     public function __construct(?string $socketPath = null, int $timeout = 30)
     {
         $this->socketPath = $socketPath ?? storage_path('framework/sockets/super-daemon.sock');
@@ -214,6 +219,69 @@ private function parseResponse(string $response): mixed
         throw new DaemonException('Invalid JSON-RPC response: missing result or error', -8);
     }
 
+    /**
+     * Submit a long-running method as a daemon job and return its id
+     *
+     * @throws DaemonException
+     */
+    public function submitJob(string $method, array $params = []): string
+    {
+        $result = $this->call($method, $params);
+        if (! is_array($result) || ! isset($result['job_id'])) {
+            throw new DaemonException("Daemon method '{$method}' did not start a job");
+        }
+
+        return (string) $result['job_id'];
+    }
+
+    /**
+     * Get a daemon job's status, progress, logs and result
+     */
+    public function getJob(string $id): array
+    {
+        return (array) $this->call('get_job', ['id' => $id]);
+    }
+
+    /**
+     * Cancel a daemon job
+     */
+    public function cancelJob(string $id): string
+    {
+        return (string) $this->call('cancel_job', ['id' => $id]);
+    }
+
+    /**
+     * Run a long-running method as a daemon job and wait for its result
+     *
+     * Each poll is a short call, so the wait isn't bound by the socket timeout.
+     *
+     * @param  int  $maxWait  Seconds to wait before giving up; the job keeps running
+     *
+     * @throws DaemonException
+     */
+    public function runJob(string $method, array $params = [], int $maxWait = 3600): mixed
+    {
+        $id = $this->submitJob($method, $params);
+        $deadline = time() + $maxWait;
+
+        while (true) {
+            $job = $this->getJob($id);
+            switch ($job['status'] ?? null) {
+                case 'completed':
+                    return $job['result'] ?? null;
+                case 'failed':
+                case 'cancelled':
+                case 'interrupted':
+                    throw new DaemonException($job['error'] ?? "Daemon job {$id} ended as {$job['status']}");
+            }
+
+            if (time() >= $deadline) {
+                throw new DaemonException("Daemon job {$id} ({$method}) is still running after {$maxWait}s");
+            }
+            sleep($this->jobPollInterval);
+        }
+    }
+
     /**
      * Check if daemon is running
      */
@@ -421,7 +489,7 @@ public function deleteDnsZone(string $domain): string
      */
     public function requestSslCert(string $domain, string $email = 'admin@example.com'): string
     {
-        return (string) $this->call('request_ssl_cert', [
+        return (string) $this->runJob('request_ssl_cert', [
             'domain' => $domain,
             'email' => $email,
         ]);
@@ -452,7 +520,7 @@ public function deleteEmailAccount(string $email): string
      */
     public function createBackup(string $name, string $sourcePath): string
     {
-        return (string) $this->call('create_backup', [
+        return (string) $this->runJob('create_backup', [
             'name' => $name,
             'source_path' => $sourcePath,
         ]);
@@ -463,15 +531,17 @@ public function createBackup(string $name, string $sourcePath): string
      */
     public function createDbBackup(string $dbName): string
     {
-        return (string) $this->call('create_db_backup', ['db_name' => $dbName]);
+        return (string) $this->runJob('create_db_backup', ['db_name' => $dbName]);
     }
 
     /**
      * Restore backup
+     *
+     * Returns the daemon's summary: message, target_path, restored_paths and snapshot.
      */
-    public function restoreBackup(string $path, string $targetPath): string
+    public function restoreBackup(string $path, string $targetPath): array
     {
-        return (string) $this->call('restore_backup', [
+        return (array) $this->runJob('restore_backup', [
             'path' => $path,
             'target_path' => $targetPath,
         ]);
@@ -479,10 +549,12 @@ public function restoreBackup(string $path, string $targetPath): string
 
     /**
      * Restore database backup
+     *
+     * Returns the daemon's summary: message, db_name and snapshot.
      */
-    public function restoreDbBackup(string $path, string $dbName): string
+    public function restoreDbBackup(string $path, string $dbName): array
     {
-        return (string) $this->call('restore_db_backup', [
+        return (array) $this->runJob('restore_db_backup', [
             'path' => $path,
             'db_name' => $dbName,
         ]);
@@ -88,7 +88,7 @@ public function getCertificateExpiration(string $domain): ?string
      */
     private function getCertificatePath(string $domain): string
     {
-        $certDir = config('services.ssl.cert_dir') ?? '/etc/letsencrypt/live';
+        $certDir = config('services.ssl.cert_dir') ?? '/etc/supercp/ssl/certs';
 
         return "$certDir/$domain/fullchain.pem";
     }
@@ -98,7 +98,7 @@ private function getCertificatePath(string $domain): string
      */
     private function getPrivateKeyPath(string $domain): string
     {
-        $keyDir = config('services.ssl.key_dir') ?? '/etc/letsencrypt/live';
+        $keyDir = config('services.ssl.key_dir') ?? '/etc/supercp/ssl/certs';
 
         return "$keyDir/$domain/privkey.pem";
     }
@@ -39,8 +39,8 @@ public function requestLetsEncrypt(WebDomain $domain, User $user, string $valida
                 $this->daemon->requestSslCert($domain->domain, $user->email);
 
                 // Update certificate info after successful request
-                $certPath = "/etc/letsencrypt/live/{$domain->domain}/fullchain.pem";
-                $keyPath = "/etc/letsencrypt/live/{$domain->domain}/privkey.pem";
+                $certPath = "/etc/supercp/ssl/certs/{$domain->domain}/fullchain.pem";
+                $keyPath = "/etc/supercp/ssl/certs/{$domain->domain}/privkey.pem";
 
                 $certContent = null;
                 try {
@@ -153,8 +153,8 @@ public function toggleSsl(WebDomain $domain, bool $enable): WebDomain
 
                 $domain->update([
                     'has_ssl' => true,
-                    'ssl_certificate_path' => "/etc/letsencrypt/live/{$domain->domain}/fullchain.pem",
-                    'ssl_key_path' => "/etc/letsencrypt/live/{$domain->domain}/privkey.pem",
+                    'ssl_certificate_path' => "/etc/supercp/ssl/certs/{$domain->domain}/fullchain.pem",
+                    'ssl_key_path' => "/etc/supercp/ssl/certs/{$domain->domain}/privkey.pem",
                     'ssl_expires_at' => now()->addYear(),
                 ]);
 
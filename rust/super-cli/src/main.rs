@@ -23,7 +23,11 @@ enum Commands {
     /// List all provisioned FTP users
     ListFtp,
     /// List all provisioned cron jobs
-    ListCron,
+    ListCron {
+        /// Show the jobs of a single system user instead of a per-user summary
+        #[arg(short, long)]
+        user: Option<String>,
+    },
     /// Get system and daemon status
     Status,
     /// Reload system services
@@ -111,12 +115,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("Error: {}", res["error"]["message"].as_str().unwrap_or("Unknown error"));
             }
         }
-        Commands::ListCron => {
-            let res = call_daemon("list_cron_jobs", json!({}))?;
+        Commands::ListCron { user } => {
+            let params = match user {
+                Some(user) => json!({ "user": user }),
+                None => json!({}),
+            };
+            let res = call_daemon("list_cron_jobs", params)?;
             if let Some(list) = res["result"].as_array() {
-                println!("Provisioned Cron Jobs (by user):");
-                for user in list {
-                    println!(" - {}", user.as_str().unwrap_or(""));
+                if let Some(user) = user {
+                    println!("Cron Jobs for {}:", user);
+                    for job in list {
+                        println!(
+                            " - [{}] {} {}{}",
+                            job["id"].as_str().unwrap_or(""),
+                            job["schedule"].as_str().unwrap_or(""),
+                            job["command"].as_str().unwrap_or(""),
+                            if job["enabled"].as_bool().unwrap_or(true) { "" } else { " (disabled)" }
+                        );
+                    }
+                } else {
+                    println!("Provisioned Cron Jobs (by user):");
+                    for entry in list {
+                        println!(
+                            " - {}: {} jobs ({} enabled)",
+                            entry["user"].as_str().unwrap_or(""),
+                            entry["jobs"].as_u64().unwrap_or(0),
+                            entry["enabled"].as_u64().unwrap_or(0)
+                        );
+                    }
                 }
             } else {
                 println!("Error: {}", res["error"]["message"].as_str().unwrap_or("Unknown error"));
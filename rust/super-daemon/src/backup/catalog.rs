@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tokio::sync::Mutex;
+
+use super::{open_dump, Compression, BACKUP_DIR};
+use crate::exec::Command;
+
+const CATALOG_PATH: &str = "/var/lib/supercp/backups/catalog.json";
+
+/// Serializes read-modify-write cycles on the catalog file between concurrent requests.
+static CATALOG_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CatalogEntry {
+    pub id: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub backup_type: String,
+    pub source: String,
+    pub account: Option<String>,
+    pub compression: String,
+    pub size: u64,
+    pub checksum: String,
+    pub created_at: u64,
+    /// Free-form marker such as "pre-restore" for backups the daemon takes on its own.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub verified_at: Option<u64>,
+    #[serde(default)]
+    pub verified: Option<bool>,
+}
+
+impl CatalogEntry {
+    /// Builds a catalog entry for a freshly written archive, hashing it from disk.
+    pub fn for_archive(path: &str, backup_type: &str, source: &str, account: Option<&str>) -> std::io::Result<Self> {
+        let id = Path::new(path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| std::io::Error::other("Invalid backup path"))?
+            .to_string();
+        let compression = match Compression::detect(Path::new(path))? {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        };
+
+        Ok(CatalogEntry {
+            id,
+            path: path.to_string(),
+            backup_type: backup_type.to_string(),
+            source: source.to_string(),
+            account: account.map(|a| a.to_string()),
+            compression: compression.to_string(),
+            size: fs::metadata(path)?.len(),
+            checksum: sha256_file(Path::new(path))?,
+            created_at: now(),
+            label: None,
+            verified_at: None,
+            verified: None,
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("sha256:{}", hex::encode(hasher.finalize())))
+}
+
+fn load() -> Result<Vec<CatalogEntry>, Box<dyn std::error::Error>> {
+    if !Path::new(CATALOG_PATH).exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(CATALOG_PATH)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save(entries: &[CatalogEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(BACKUP_DIR)?;
+
+    // Write-then-rename so a crash mid-write never leaves a truncated catalog behind
+    let temp_path = format!("{}.tmp", CATALOG_PATH);
+    fs::write(&temp_path, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(&temp_path, CATALOG_PATH)?;
+
+    Ok(())
+}
+
+/// Adds an entry to the catalog, replacing any previous entry for the same archive path.
+pub async fn record(entry: CatalogEntry) -> Result<(), Box<dyn std::error::Error>> {
+    let _guard = CATALOG_LOCK.lock().await;
+    let mut entries = load()?;
+
+    entries.retain(|e| e.path != entry.path);
+    entries.push(entry);
+
+    save(&entries)
+}
+
+/// Looks up an entry by catalog id or by archive path.
+pub async fn find(id_or_path: &str) -> Result<Option<CatalogEntry>, Box<dyn std::error::Error>> {
+    let _guard = CATALOG_LOCK.lock().await;
+    Ok(load()?.into_iter().find(|e| e.id == id_or_path || e.path == id_or_path))
+}
+
+pub async fn list_backups(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let account = params["account"].as_str();
+    let backup_type = params["type"].as_str();
+
+    let mut entries = {
+        let _guard = CATALOG_LOCK.lock().await;
+        load()?
+    };
+
+    entries.retain(|e| {
+        account.is_none_or(|a| e.account.as_deref() == Some(a))
+            && backup_type.is_none_or(|t| e.backup_type == t)
+    });
+    entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+
+    Ok(serde_json::to_value(entries)?)
+}
+
+/// Reads a tar archive end to end, returning the number of members it lists.
+async fn check_archive(path: &str) -> Result<usize, String> {
+    let output = Command::new("tar")
+        .arg("-tzf")
+        .arg(path)
+        .no_timeout()
+        .run()
+        .await
+        .map_err(|e| format!("Archive is not readable: {}", e))?;
+
+    Ok(output.stdout.lines().count())
+}
+
+/// Decompresses a dump end to end and checks mysqldump's completion trailer is present,
+/// which catches both corrupt compression and dumps that were cut off mid-way.
+fn check_dump(path: &Path) -> Result<u64, String> {
+    let mut input = open_dump(path).map_err(|e| e.to_string())?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut tail: Vec<u8> = Vec::new();
+    let mut total: u64 = 0;
+
+    loop {
+        let read = input.read(&mut buffer).map_err(|e| format!("Dump is not readable: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        total += read as u64;
+        tail.extend_from_slice(&buffer[..read]);
+        if tail.len() > 512 {
+            tail.drain(..tail.len() - 512);
+        }
+    }
+
+    if !String::from_utf8_lossy(&tail).contains("-- Dump completed") {
+        return Err("Dump is incomplete: missing mysqldump completion marker".to_string());
+    }
+
+    Ok(total)
+}
+
+pub async fn verify_backup(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let id = params["id"].as_str().or(params["path"].as_str()).ok_or("Missing id")?;
+    let mut entry = find(id).await?.ok_or_else(|| format!("Backup {} is not in the catalog", id))?;
+
+    let check = entry.clone();
+    let (mut errors, mut details) = tokio::task::spawn_blocking(move || {
+        let path = Path::new(&check.path);
+        let mut errors: Vec<String> = Vec::new();
+        let mut details = serde_json::Map::new();
+
+        if !path.exists() {
+            errors.push("Backup file not found".to_string());
+            return (errors, details);
+        }
+
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() != check.size => {
+                errors.push(format!("Size mismatch: catalog has {} bytes, file has {}", check.size, meta.len()));
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e.to_string()),
+        }
+
+        match sha256_file(path) {
+            Ok(sum) if sum != check.checksum => errors.push("Checksum mismatch".to_string()),
+            Ok(_) => {}
+            Err(e) => errors.push(e.to_string()),
+        }
+
+        if check.backup_type == "database" {
+            match check_dump(path) {
+                Ok(bytes) => {
+                    details.insert("uncompressed_bytes".to_string(), json!(bytes));
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        (errors, details)
+    })
+    .await?;
+
+    if entry.backup_type != "database" && Path::new(&entry.path).exists() {
+        match check_archive(&entry.path).await {
+            Ok(count) => {
+                details.insert("entries".to_string(), json!(count));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    entry.verified = Some(errors.is_empty());
+    entry.verified_at = Some(now());
+    record(entry.clone()).await?;
+
+    Ok(json!({
+        "id": entry.id,
+        "path": entry.path,
+        "valid": errors.is_empty(),
+        "errors": errors,
+        "details": details,
+    }))
+}
@@ -0,0 +1,346 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::exec::{self, Command};
+use crate::jobs::{self, CANCELLED};
+use crate::{validate_db_name, DaemonState};
+
+pub mod catalog;
+pub mod restore;
+
+use catalog::CatalogEntry;
+
+pub const BACKUP_DIR: &str = "/var/lib/supercp/backups";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// How often (in bytes of raw dump output) progress is published to the daemon state.
+const PROGRESS_INTERVAL: u64 = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_param(value: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        match value.unwrap_or("gzip") {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            other => Err(format!("Unsupported compression: {}", other).into()),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "sql",
+            Compression::Gzip => "sql.gz",
+            Compression::Zstd => "sql.zst",
+        }
+    }
+
+    /// Sniffs the compression format from the first bytes of a file.
+    pub fn detect(path: &Path) -> std::io::Result<Self> {
+        let mut header = [0u8; 4];
+        let mut file = fs::File::open(path)?;
+        let read = file.read(&mut header)?;
+
+        if read >= 2 && header[..2] == GZIP_MAGIC {
+            Ok(Compression::Gzip)
+        } else if read >= 4 && header == ZSTD_MAGIC {
+            Ok(Compression::Zstd)
+        } else {
+            Ok(Compression::None)
+        }
+    }
+}
+
+/// Opens a dump for reading, transparently decompressing gzip or zstd input.
+pub fn open_dump(path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+    let file = fs::File::open(path)?;
+    Ok(match Compression::detect(path)? {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    })
+}
+
+/// Compressing sink for dump output. Kept as an enum rather than `Box<dyn Write>` so the
+/// encoder trailer can be written explicitly and its errors reported.
+enum DumpWriter {
+    Plain(fs::File),
+    Gzip(flate2::write::GzEncoder<fs::File>),
+    Zstd(zstd::stream::write::Encoder<'static, fs::File>),
+}
+
+impl DumpWriter {
+    fn new(file: fs::File, compression: Compression) -> std::io::Result<Self> {
+        Ok(match compression {
+            Compression::None => DumpWriter::Plain(file),
+            Compression::Gzip => DumpWriter::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default())),
+            Compression::Zstd => DumpWriter::Zstd(zstd::stream::write::Encoder::new(file, 3)?),
+        })
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            DumpWriter::Plain(file) => file.write_all(data),
+            DumpWriter::Gzip(encoder) => encoder.write_all(data),
+            DumpWriter::Zstd(encoder) => encoder.write_all(data),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        let file = match self {
+            DumpWriter::Plain(file) => file,
+            DumpWriter::Gzip(encoder) => encoder.finish()?,
+            DumpWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        file.sync_all()
+    }
+}
+
+/// Drains a child's stderr on a separate thread so a chatty process cannot fill the pipe and stall.
+pub(crate) fn drain_stderr(stderr: Option<std::process::ChildStderr>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+/// Hashes a finished archive off the async workers and records it in the backup catalog.
+async fn catalog_archive(path: &str, backup_type: &str, source: &str, account: Option<&str>, label: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let (path, backup_type, source) = (path.to_string(), backup_type.to_string(), source.to_string());
+    let account = account.map(|a| a.to_string());
+    let label = label.map(|l| l.to_string());
+
+    let mut entry = tokio::task::spawn_blocking(move || {
+        CatalogEntry::for_archive(&path, &backup_type, &source, account.as_deref())
+    })
+    .await??;
+    entry.label = label;
+
+    catalog::record(entry).await
+}
+
+pub async fn create_backup(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let name = params["name"].as_str().ok_or("Missing name")?;
+    let source_path = params["source_path"].as_str().ok_or("Missing source_path")?;
+    let account = params["account"].as_str();
+    let label = params["label"].as_str();
+    fs::create_dir_all(BACKUP_DIR)?;
+
+    let target_path = format!("{}/{}.tar.gz", BACKUP_DIR, name);
+    jobs::log(format!("Archiving {} to {}", source_path, target_path)).await;
+
+    // In a real system, we would use tar crate or Command::new("tar")
+    let output = Command::new("tar")
+        .arg("-czf")
+        .arg(&target_path)
+        .arg("-C")
+        .arg(Path::new(source_path).parent().unwrap_or(Path::new("/")))
+        .arg(Path::new(source_path).file_name().unwrap_or_default())
+        .no_timeout()
+        .output()
+        .await?;
+
+    if output.success() {
+        // Ensure the web server can read the backup for download
+        let _ = fs::set_permissions(&target_path, fs::Permissions::from_mode(0o644));
+        catalog_archive(&target_path, "files", source_path, account, label).await?;
+        Ok(target_path)
+    } else {
+        Err("Failed to create backup archive".into())
+    }
+}
+
+pub async fn create_db_backup(params: &Value, state: Arc<Mutex<DaemonState>>) -> Result<String, Box<dyn std::error::Error>> {
+    let db_name = params["db_name"].as_str().ok_or("Missing db_name")?;
+    // The name ends up in the archive path as well as in mysqldump's arguments
+    validate_db_name(db_name)?;
+    let account = params["account"].as_str();
+    let label = params["label"].as_str();
+    let compression = Compression::from_param(params["compression"].as_str())?;
+    fs::create_dir_all(BACKUP_DIR)?;
+    // Cancelling terminates mysqldump, so the partial dump is cleaned up below
+    let _shield = jobs::shield()?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let target_path = format!("{}/{}_{}.{}", BACKUP_DIR, db_name, timestamp, compression.extension());
+    let partial_path = format!("{}.partial", target_path);
+
+    // The on-disk size is only an estimate of the dump size, but good enough for a progress bar.
+    let estimated_bytes = crate::get_database_size(&json!({ "name": db_name })).await.unwrap_or(0);
+
+    {
+        let mut state = state.lock().await;
+        state.backup_progress.insert(db_name.to_string(), json!({
+            "status": "running",
+            "path": target_path,
+            "bytes_dumped": 0,
+            "estimated_bytes": estimated_bytes,
+            "percent": 0,
+        }));
+    }
+
+    jobs::log(format!("Dumping database {} to {}", db_name, target_path)).await;
+
+    let db = db_name.to_string();
+    let partial = partial_path.clone();
+    let progress_state = Arc::clone(&state);
+    let job = jobs::current();
+
+    // --single-transaction gives a consistent InnoDB snapshot without locking tables,
+    // --quick streams rows instead of buffering whole tables in mysqldump itself.
+    let mut dump = Command::new("mysqldump")
+        .arg("--single-transaction")
+        .arg("--quick")
+        .arg("--routines")
+        .arg("--triggers")
+        .arg(db_name)
+        .std_command();
+
+    let _slot = exec::slot().await;
+    let result = tokio::task::spawn_blocking(move || -> Result<u64, String> {
+        let mut child = dump
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start mysqldump: {}", e))?;
+
+        if let Some(job) = &job {
+            job.watch_child(child.id());
+        }
+        let stderr = drain_stderr(child.stderr.take());
+        let mut stdout = child.stdout.take().ok_or("Failed to capture mysqldump output")?;
+
+        let copied = (|| -> std::io::Result<u64> {
+            let file = fs::File::create(&partial)?;
+            let mut writer = DumpWriter::new(file, compression)?;
+
+            let mut buffer = vec![0u8; 64 * 1024];
+            let mut total: u64 = 0;
+            let mut next_report = PROGRESS_INTERVAL;
+            loop {
+                let read = stdout.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                writer.write_all(&buffer[..read])?;
+                total += read as u64;
+
+                if total >= next_report {
+                    next_report = total + PROGRESS_INTERVAL;
+                    let percent = (total * 100).checked_div(estimated_bytes).unwrap_or(0).min(99);
+                    let mut state = progress_state.blocking_lock();
+                    if let Some(entry) = state.backup_progress.get_mut(&db) {
+                        entry["bytes_dumped"] = json!(total);
+                        entry["percent"] = json!(percent);
+                        if let Some(job) = &job {
+                            job.set_progress_blocking(entry.clone());
+                        }
+                    }
+                }
+            }
+            writer.finish()?;
+            Ok(total)
+        })();
+
+        let total = match copied {
+            Ok(total) => total,
+            Err(e) => {
+                // Don't leave mysqldump blocked on a pipe nobody reads anymore
+                let _ = child.kill();
+                let _ = child.wait();
+                if let Some(job) = &job {
+                    job.release_child();
+                }
+                return Err(format!("Failed to write database backup: {}", e));
+            }
+        };
+
+        let status = child.wait().map_err(|e| e.to_string());
+        if let Some(job) = &job {
+            job.release_child();
+            if job.is_cancelled() {
+                return Err(CANCELLED.to_string());
+            }
+        }
+        let status = status?;
+        let error = stderr.join().unwrap_or_default();
+        if !status.success() {
+            return Err(format!("Failed to create database backup: {}", error.trim()));
+        }
+
+        Ok(total)
+    })
+    .await?;
+
+    // Hash and catalog the archive before taking the state lock; that reads the whole file
+    let result = match result {
+        Ok(total) => finish_db_backup(&partial_path, &target_path, db_name, account, label).await.map(|_| total),
+        Err(e) => Err(e),
+    };
+
+    let mut state = state.lock().await;
+    match result {
+        Ok(total) => {
+            state.backup_progress.insert(db_name.to_string(), json!({
+                "status": "completed",
+                "path": target_path,
+                "bytes_dumped": total,
+                "estimated_bytes": estimated_bytes,
+                "percent": 100,
+            }));
+            Ok(target_path)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            state.backup_progress.insert(db_name.to_string(), json!({
+                "status": if e == CANCELLED { "cancelled" } else { "failed" },
+                "path": target_path,
+                "error": e,
+            }));
+            Err(e.into())
+        }
+    }
+}
+
+/// Moves a finished dump into place and records it in the catalog. A dump that can't be
+/// cataloged is removed again, so a failed backup never leaves an unlisted archive behind.
+async fn finish_db_backup(partial_path: &str, target_path: &str, db_name: &str, account: Option<&str>, label: Option<&str>) -> Result<(), String> {
+    fs::rename(partial_path, target_path).map_err(|e| format!("Failed to move {} into place: {}", target_path, e))?;
+    // Ensure the web server can read the backup for download
+    let _ = fs::set_permissions(target_path, fs::Permissions::from_mode(0o644));
+    if let Err(e) = catalog_archive(target_path, "database", db_name, account, label).await {
+        let _ = fs::remove_file(target_path);
+        return Err(format!("Failed to catalog {}: {}", target_path, e));
+    }
+    Ok(())
+}
+
+pub async fn get_backup_progress(params: &Value, state: Arc<Mutex<DaemonState>>) -> Result<Value, Box<dyn std::error::Error>> {
+    let db_name = params["db_name"].as_str().ok_or("Missing db_name")?;
+    let state = state.lock().await;
+
+    state
+        .backup_progress
+        .get(db_name)
+        .cloned()
+        .ok_or_else(|| format!("No backup in progress for {}", db_name).into())
+}
@@ -0,0 +1,346 @@
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::catalog::{self, CatalogEntry};
+use super::{catalog_archive, drain_stderr, open_dump, BACKUP_DIR};
+use crate::exec::{self, Command};
+use crate::jobs::{self, CANCELLED};
+use crate::{validate_db_name, DaemonState};
+
+const STAGING_DIR: &str = "/var/lib/supercp/restores";
+
+/// Resolves the `id` or `path` parameter to an archive on disk and its catalog entry, if any.
+async fn resolve_backup(params: &Value) -> Result<(String, Option<CatalogEntry>), Box<dyn std::error::Error>> {
+    let id = params["id"].as_str().or(params["path"].as_str()).ok_or("Missing path")?;
+    let entry = catalog::find(id).await?;
+    let path = entry.as_ref().map(|e| e.path.clone()).unwrap_or_else(|| id.to_string());
+
+    if !Path::new(&path).exists() {
+        return Err("Backup file not found".into());
+    }
+
+    Ok((path, entry))
+}
+
+fn is_dump(path: &str, entry: Option<&CatalogEntry>) -> bool {
+    match entry {
+        Some(entry) => entry.backup_type == "database",
+        None => [".sql", ".sql.gz", ".sql.zst"].iter().any(|ext| path.ends_with(ext)),
+    }
+}
+
+/// Rejects absolute paths and `..` so a selection can never point outside the restore target.
+fn validate_member(member: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let trimmed = member.trim_end_matches('/');
+    let path = Path::new(trimmed);
+
+    if trimmed.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("Invalid restore path: {}", member).into());
+    }
+
+    Ok(trimmed.trim_start_matches("./").to_string())
+}
+
+fn read_archive_entries(path: &Path) -> std::io::Result<Vec<Value>> {
+    let file = fs::File::open(path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut entries = Vec::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        let entry_type = if header.entry_type().is_dir() {
+            "directory"
+        } else if header.entry_type().is_symlink() {
+            "symlink"
+        } else {
+            "file"
+        };
+
+        entries.push(json!({
+            "path": entry.path()?.to_string_lossy().trim_end_matches('/'),
+            "type": entry_type,
+            "size": header.size().unwrap_or(0),
+            "modified": header.mtime().unwrap_or(0),
+            "permissions": format!("{:o}", header.mode().unwrap_or(0) & 0o777),
+        }));
+    }
+
+    Ok(entries)
+}
+
+fn read_dump_tables(path: &Path) -> std::io::Result<(Vec<String>, bool)> {
+    let reader = BufReader::new(open_dump(path)?);
+    let mut tables = Vec::new();
+    let mut complete = false;
+
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if let Some(rest) = line.strip_prefix(b"CREATE TABLE `") {
+            if let Some(end) = rest.iter().position(|b| *b == b'`') {
+                tables.push(String::from_utf8_lossy(&rest[..end]).to_string());
+            }
+        } else if line.starts_with(b"-- Dump completed") {
+            complete = true;
+        }
+    }
+
+    Ok((tables, complete))
+}
+
+pub async fn preview_backup(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let (path, entry) = resolve_backup(params).await?;
+    let dump = is_dump(&path, entry.as_ref());
+
+    let archive_path = path.clone();
+    if dump {
+        let (tables, complete) = tokio::task::spawn_blocking(move || read_dump_tables(Path::new(&archive_path))).await??;
+        Ok(json!({
+            "path": path,
+            "type": "database",
+            "tables": tables,
+            "complete": complete,
+        }))
+    } else {
+        let entries = tokio::task::spawn_blocking(move || read_archive_entries(Path::new(&archive_path))).await??;
+        let total_size: u64 = entries.iter().filter_map(|e| e["size"].as_u64()).sum();
+        Ok(json!({
+            "path": path,
+            "type": "files",
+            "total_size": total_size,
+            "count": entries.len(),
+            "entries": entries,
+        }))
+    }
+}
+
+/// Archives whatever currently exists at the given paths under `target` so a restore over them
+/// can be rolled back by restoring the snapshot. Returns `None` when there is nothing to save.
+async fn snapshot_paths(target: &Path, paths: &BTreeSet<String>, account: Option<&str>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let existing: Vec<&String> = paths.iter().filter(|p| target.join(p).exists()).collect();
+    if existing.is_empty() {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(BACKUP_DIR)?;
+    let name = target.file_name().and_then(|s| s.to_str()).unwrap_or("root");
+    let snapshot_path = format!("{}/pre-restore_{}_{}.tar.gz", BACKUP_DIR, name, catalog::now());
+
+    let result = Command::new("tar")
+        .arg("-czf")
+        .arg(&snapshot_path)
+        .arg("-C")
+        .arg(target)
+        .arg("--")
+        .args(&existing)
+        .no_timeout()
+        .run()
+        .await;
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&snapshot_path);
+        return Err(format!("Failed to snapshot current files before restore: {}", e).into());
+    }
+
+    catalog_archive(&snapshot_path, "files", &target.to_string_lossy(), account, Some("pre-restore")).await?;
+    Ok(Some(snapshot_path))
+}
+
+pub async fn restore_backup(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let (path, entry) = resolve_backup(params).await?;
+    let staging = params["staging"].as_bool().unwrap_or(false);
+    let take_snapshot = params["snapshot"].as_bool().unwrap_or(true);
+    let account = entry.as_ref().and_then(|e| e.account.clone());
+
+    if is_dump(&path, entry.as_ref()) {
+        return Err("Backup is a database dump; use restore_db_backup".into());
+    }
+
+    let target: PathBuf = if staging {
+        let stem = Path::new(&path).file_name().and_then(|s| s.to_str()).unwrap_or("backup");
+        Path::new(STAGING_DIR).join(format!("{}_{}", stem.trim_end_matches(".tar.gz"), catalog::now()))
+    } else {
+        PathBuf::from(params["target_path"].as_str().ok_or("Missing target_path")?)
+    };
+
+    let archive_path = path.clone();
+    let members: Vec<String> = tokio::task::spawn_blocking(move || read_archive_entries(Path::new(&archive_path)))
+        .await??
+        .iter()
+        .filter_map(|e| e["path"].as_str().map(|p| p.trim_start_matches("./").to_string()))
+        .collect();
+
+    // Restore either the requested subset or the whole archive
+    let mut selected: Vec<String> = Vec::new();
+    if let Some(paths) = params["paths"].as_array() {
+        for requested in paths {
+            let member = validate_member(requested.as_str().ok_or("Invalid restore path")?)?;
+            let prefix = format!("{}/", member);
+            if !members.iter().any(|m| *m == member || m.starts_with(&prefix)) {
+                return Err(format!("Path {} is not in the backup", member).into());
+            }
+            selected.push(member);
+        }
+    }
+
+    let overwritten: BTreeSet<String> = if selected.is_empty() {
+        members
+            .iter()
+            .filter_map(|m| m.split('/').next())
+            .filter(|m| !m.is_empty() && *m != ".")
+            .map(|m| m.to_string())
+            .collect()
+    } else {
+        selected.iter().cloned().collect()
+    };
+
+    let snapshot = if take_snapshot && !staging {
+        jobs::progress(json!({ "stage": "snapshot" })).await;
+        snapshot_paths(&target, &overwritten, account.as_deref()).await?
+    } else {
+        None
+    };
+
+    fs::create_dir_all(&target)?;
+    jobs::progress(json!({ "stage": "extract" })).await;
+    jobs::log(format!("Extracting {} into {}", path, target.display())).await;
+
+    Command::new("tar")
+        .arg("-xzf")
+        .arg(&path)
+        .arg("-C")
+        .arg(&target)
+        .arg("--")
+        .args(&selected)
+        .no_timeout()
+        .run()
+        .await
+        .map_err(|e| format!("Failed to restore backup archive: {}", e))?;
+
+    Ok(json!({
+        "message": format!("Backup restored to {}", target.display()),
+        "target_path": target,
+        "restored_paths": if selected.is_empty() { overwritten.into_iter().collect() } else { selected },
+        "snapshot": snapshot,
+    }))
+}
+
+async fn database_exists(db_name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let output = Command::new("mysql")
+        .arg("-N")
+        .arg("-s")
+        .arg("-e")
+        .arg(format!("SELECT SCHEMA_NAME FROM information_schema.SCHEMATA WHERE SCHEMA_NAME = '{}'", db_name))
+        .run()
+        .await
+        .map_err(|e| format!("Failed to query databases: {}", e))?;
+
+    Ok(!output.stdout.trim().is_empty())
+}
+
+pub async fn restore_db_backup(params: &Value, state: Arc<Mutex<DaemonState>>) -> Result<Value, Box<dyn std::error::Error>> {
+    let (path, entry) = resolve_backup(params).await?;
+    let target_db = params["target_db"].as_str().or(params["db_name"].as_str()).ok_or("Missing db_name")?;
+    let take_snapshot = params["snapshot"].as_bool().unwrap_or(true);
+
+    validate_db_name(target_db)?;
+    // Cancelling terminates the import rather than leaving it running on its own
+    let _shield = jobs::shield()?;
+
+    let created = !database_exists(target_db).await?;
+    let snapshot = if !created {
+        if take_snapshot {
+            jobs::progress(json!({ "stage": "snapshot" })).await;
+            let account = entry.as_ref().and_then(|e| e.account.clone());
+            let snapshot = super::create_db_backup(
+                &json!({ "db_name": target_db, "account": account, "label": "pre-restore" }),
+                Arc::clone(&state),
+            )
+            .await?;
+            Some(snapshot)
+        } else {
+            None
+        }
+    } else {
+        Command::new("mysql")
+            .arg("-e")
+            .arg(format!("CREATE DATABASE `{}`", target_db))
+            .run()
+            .await
+            .map_err(|e| format!("Failed to create database {}: {}", target_db, e))?;
+        None
+    };
+
+    jobs::progress(json!({ "stage": "import" })).await;
+    jobs::log(format!("Importing {} into database {}", path, target_db)).await;
+
+    let dump_path = path.clone();
+    let db = target_db.to_string();
+    let mut import = Command::new("mysql").arg(target_db).std_command();
+    let job = jobs::current();
+
+    let slot = exec::slot().await;
+    let imported = tokio::task::spawn_blocking(move || -> Result<(), String> {
+        // Decompress on the fly so compressed dumps never hit the disk uncompressed
+        let mut input = open_dump(Path::new(&dump_path)).map_err(|e| e.to_string())?;
+
+        let mut child = import
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start mysql: {}", e))?;
+
+        if let Some(job) = &job {
+            job.watch_child(child.id());
+        }
+        let stderr = drain_stderr(child.stderr.take());
+        let mut stdin = child.stdin.take().ok_or("Failed to open mysql input")?;
+        let copied = std::io::copy(&mut input, &mut stdin);
+        drop(stdin);
+
+        let status = child.wait().map_err(|e| e.to_string());
+        if let Some(job) = &job {
+            job.release_child();
+            if job.is_cancelled() {
+                return Err(CANCELLED.to_string());
+            }
+        }
+        let status = status?;
+        let error = stderr.join().unwrap_or_default();
+        if !status.success() {
+            return Err(format!("Failed to restore database {}: {}", db, error.trim()));
+        }
+        copied.map_err(|e| format!("Failed to read backup {}: {}", dump_path, e))?;
+
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    drop(slot);
+
+    if let Err(e) = imported {
+        if !created {
+            return Err(format!("{}; {} may be partly imported", e, target_db).into());
+        }
+        // A database this restore created holds nothing but the partial import
+        let dropped = Command::new("mysql").arg("-e").arg(format!("DROP DATABASE `{}`", target_db)).run().await;
+        return Err(match dropped {
+            Ok(_) => format!("{}; the new database {} was dropped again", e, target_db),
+            Err(drop_error) => format!("{}; dropping the partly imported database {} failed: {}", e, target_db, drop_error),
+        }
+        .into());
+    }
+
+    Ok(json!({
+        "message": format!("Database {} restored from {}", target_db, path),
+        "db_name": target_db,
+        "snapshot": snapshot,
+    }))
+}
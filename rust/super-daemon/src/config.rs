@@ -0,0 +1,212 @@
+//! Daemon settings from `/etc/supercp/daemon.json`.
+//!
+//! Every section and field is optional; a missing file gives the defaults below. The file is
+//! read on each use, so edits apply without restarting the daemon.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_PATH: &str = "/etc/supercp/daemon.json";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub dns: DnsConfig,
+    pub ssl: SslConfig,
+    pub mail: MailConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// Authoritative nameservers published in every zone's NS records; the first is the SOA MNAME.
+    /// Required before any zone is written.
+    pub nameservers: Vec<String>,
+    /// Responsible mailbox for the SOA, as an address (`admin@example.com`) or in zone form.
+    /// Required before any zone is written.
+    pub hostmaster: String,
+    pub default_ttl: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    /// Negative caching TTL
+    pub minimum: u32,
+    /// Directory the zone files are written to
+    pub zone_dir: String,
+    /// Nameserver zones are published to: `bind`, `powerdns` or `fake`
+    pub backend: String,
+    /// Where the `fake` backend copies the zones it "publishes"
+    pub fake_dir: String,
+    pub bind: BindConfig,
+    pub powerdns: PowerDnsConfig,
+    pub dnssec: DnssecConfig,
+    /// This server's public addresses, for the `{{IPV4}}` and `{{IPV6}}` template variables
+    pub server_ipv4: Option<String>,
+    pub server_ipv6: Option<String>,
+    /// Value of `{{MAIL_HOST}}`; may itself use `{{DOMAIN}}`
+    pub mail_host: String,
+    /// Zone templates by name, as record lists in `update_dns_zone` form. A `default`
+    /// template here replaces the built-in one.
+    pub templates: BTreeMap<String, Vec<Value>>,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            // No sensible defaults: zones would point at someone else's servers
+            nameservers: Vec::new(),
+            hostmaster: String::new(),
+            default_ttl: 3600,
+            refresh: 3600,
+            retry: 600,
+            expire: 1_209_600,
+            minimum: 3600,
+            zone_dir: "/etc/supercp/dns".to_string(),
+            backend: "bind".to_string(),
+            fake_dir: "/var/lib/supercp/dns-fake".to_string(),
+            bind: BindConfig::default(),
+            powerdns: PowerDnsConfig::default(),
+            dnssec: DnssecConfig::default(),
+            server_ipv4: None,
+            server_ipv6: None,
+            mail_host: "mail.{{DOMAIN}}".to_string(),
+            templates: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BindConfig {
+    /// File of `zone` statements maintained by the daemon; include it from `named.conf`
+    pub zones_conf: String,
+}
+
+impl Default for BindConfig {
+    fn default() -> Self {
+        BindConfig { zones_conf: "/etc/supercp/named.conf".to_string() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PowerDnsConfig {
+    pub api_url: String,
+    pub api_key: String,
+    pub server_id: String,
+}
+
+impl Default for PowerDnsConfig {
+    fn default() -> Self {
+        PowerDnsConfig {
+            api_url: "http://127.0.0.1:8081".to_string(),
+            api_key: String::new(),
+            server_id: "localhost".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DnssecConfig {
+    /// How long signatures are valid, in seconds
+    pub signature_validity: u32,
+    /// Zones are re-signed once their signatures expire within this many seconds
+    pub resign_before: u32,
+}
+
+impl Default for DnssecConfig {
+    fn default() -> Self {
+        DnssecConfig { signature_validity: 1_209_600, resign_before: 604_800 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SslConfig {
+    /// Where certificates, ACME accounts and the renewal state are kept
+    pub dir: String,
+    /// ACME directory certificates are ordered from
+    pub acme_directory: String,
+    /// Account contact address, unless the request gives one
+    pub email: Option<String>,
+    /// PEM file of extra CA certificates to trust when talking to the ACME server, for a
+    /// private or test CA
+    pub ca_bundle: Option<String>,
+    /// Certificates are renewed once they expire within this many days
+    pub renew_before_days: u32,
+    /// Program that publishes DNS-01 challenges for domains whose zones aren't hosted here,
+    /// run as `<hook> present|cleanup <record name> <value>`
+    pub dns_hook: Option<String>,
+    /// Servers checked for a DNS-01 record before the CA is asked to look (`host` or
+    /// `host:port`); by default the zone's own nameservers
+    pub dns_resolvers: Vec<String>,
+    /// How long to wait for a DNS-01 record to appear on those servers, in seconds; 0 skips
+    /// the check
+    pub dns_propagation_timeout: u64,
+}
+
+impl Default for SslConfig {
+    fn default() -> Self {
+        SslConfig {
+            dir: "/etc/supercp/ssl".to_string(),
+            acme_directory: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            email: None,
+            ca_bundle: None,
+            renew_before_days: 30,
+            dns_hook: None,
+            dns_resolvers: Vec::new(),
+            dns_propagation_timeout: 120,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    /// Directory every mail file below is written under, so the output can be tried out in
+    /// a scratch directory; `/` on a real server
+    pub root: String,
+    /// Base of the mailboxes, as `<domain>/<user>/Maildir`
+    pub vmail_dir: String,
+    /// Owner of the mailboxes, which Postfix and Dovecot deliver as
+    pub vmail_uid: u32,
+    pub vmail_gid: u32,
+    /// Where the `virtual_mailbox_*` lookup tables are written
+    pub postfix_dir: String,
+    /// Dovecot `passwd-file` of the mailbox users
+    pub dovecot_users: String,
+    /// OpenDKIM directory holding `KeyTable`, `SigningTable` (read as `refile:`) and the
+    /// private keys under `keys/<domain>/`
+    pub opendkim_dir: String,
+    /// Rebuild the Postfix tables and reload Postfix and Dovecot after a change; off, only the
+    /// files are written
+    pub apply: bool,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            root: "/".to_string(),
+            vmail_dir: "/var/mail/vhosts".to_string(),
+            vmail_uid: 5000,
+            vmail_gid: 5000,
+            postfix_dir: "/etc/postfix".to_string(),
+            dovecot_users: "/etc/dovecot/users".to_string(),
+            opendkim_dir: "/etc/opendkim".to_string(),
+            apply: true,
+        }
+    }
+}
+
+pub fn load() -> Result<Config, Box<dyn std::error::Error>> {
+    if !Path::new(CONFIG_PATH).exists() {
+        return Ok(Config::default());
+    }
+
+    let content = fs::read_to_string(CONFIG_PATH)?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid daemon config {}: {}", CONFIG_PATH, e).into())
+}
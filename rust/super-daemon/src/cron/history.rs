@@ -0,0 +1,154 @@
+//! Execution records for installed cron jobs.
+//!
+//! Every run goes through the [`runner`](super::runner), which writes one JSON file per run
+//! to `/var/lib/supercp/cron-history/<user>/<job-id>/`. The per-user directory belongs to the
+//! user because cron runs their jobs without privileges; the daemon only reads and prunes it.
+
+use nix::unistd::{chown, User};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use super::{validate_job_id, validate_user};
+
+pub const HISTORY_DIR: &str = "/var/lib/supercp/cron-history";
+
+/// Runs kept per job; older records are deleted by the runner after each run.
+pub const MAX_RUNS_PER_JOB: usize = 50;
+
+/// Captured output kept per stream; anything before the last 16 KiB is dropped.
+pub const MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RunRecord {
+    #[serde(default)]
+    pub run_id: String,
+    pub job_id: String,
+    pub user: String,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    pub exit_code: Option<i32>,
+    /// Set instead of `exit_code` when the job was killed by a signal
+    pub signal: Option<i32>,
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    /// True when either stream was longer than [`MAX_OUTPUT_BYTES`]
+    pub truncated: bool,
+    /// `schedule` for runs started by cron, `manual` for `run_cron_job_now`
+    #[serde(default = "default_trigger")]
+    pub trigger: String,
+    #[serde(default)]
+    pub timed_out: bool,
+    /// True when the run was skipped because the previous one was still going
+    #[serde(default)]
+    pub skipped: bool,
+}
+
+fn default_trigger() -> String {
+    "schedule".to_string()
+}
+
+fn user_dir(user: &str) -> PathBuf {
+    Path::new(HISTORY_DIR).join(user)
+}
+
+pub fn job_dir(user: &str, job_id: &str) -> PathBuf {
+    user_dir(user).join(job_id)
+}
+
+/// Creates the user's history directory, owned by them so their jobs can record runs.
+pub fn prepare_user_dir(user: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let account = User::from_name(user)?.ok_or_else(|| format!("System user {} does not exist", user))?;
+
+    fs::create_dir_all(HISTORY_DIR)?;
+    let dir = user_dir(user);
+    fs::create_dir_all(&dir)?;
+    chown(&dir, Some(account.uid), Some(account.gid))?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+    Ok(())
+}
+
+/// Deletes the history of jobs that are no longer installed.
+pub fn remove_stale(user: &str, job_ids: &[&str]) {
+    if let Ok(entries) = fs::read_dir(user_dir(user)) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !job_ids.iter().any(|id| name == *id) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+}
+
+/// Run files sorted oldest first; their names start with the zero-padded start time.
+fn run_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("json"))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+pub fn write(record: &RunRecord) -> std::io::Result<()> {
+    let dir = job_dir(&record.user, &record.job_id);
+    fs::create_dir_all(&dir)?;
+
+    let name = format!("{:013}-{}.json", record.started_at, std::process::id());
+    fs::write(dir.join(name), serde_json::to_string(record)?)?;
+
+    let files = run_files(&dir);
+    for old in files.iter().take(files.len().saturating_sub(MAX_RUNS_PER_JOB)) {
+        let _ = fs::remove_file(old);
+    }
+
+    Ok(())
+}
+
+/// Runs of one job, or of all the user's jobs, newest first.
+pub fn load(user: &str, job_id: Option<&str>, limit: usize) -> Vec<RunRecord> {
+    let dirs: Vec<PathBuf> = match job_id {
+        Some(id) => vec![job_dir(user, id)],
+        None => fs::read_dir(user_dir(user))
+            .map(|entries| entries.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default(),
+    };
+
+    let mut records: Vec<RunRecord> = dirs
+        .iter()
+        .flat_map(|dir| run_files(dir))
+        .filter_map(|path| serde_json::from_str(&fs::read_to_string(path).ok()?).ok())
+        .collect();
+
+    records.sort_by_key(|r| std::cmp::Reverse(r.started_at));
+    records.truncate(limit);
+    records
+}
+
+pub async fn get_cron_history(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let user = params["user"].as_str().ok_or("Missing user")?;
+    validate_user(user)?;
+    let job_id = params["job_id"].as_str();
+    let limit = params["limit"].as_u64().unwrap_or(20) as usize;
+
+    if let Some(id) = job_id {
+        validate_job_id(id)?;
+    }
+
+    let user = user.to_string();
+    let job_id = job_id.map(|s| s.to_string());
+    let records = tokio::task::spawn_blocking(move || load(&user, job_id.as_deref(), limit)).await?;
+
+    Ok(json!(records))
+}
@@ -0,0 +1,636 @@
+//! Per-user cron jobs managed by the panel.
+//!
+//! The structured job list in `/var/spool/supercp/cron/<user>.json` is the source of truth;
+//! the `<user>.cron` crontab next to it is generated from it and installed with `crontab -u`.
+//! Each crontab line runs the job through the daemon binary's [`runner`] so its runs are
+//! recorded in [`history`].
+
+use chrono::{LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use nix::unistd::User;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::exec::Command;
+use crate::jobs::{self, CANCELLED};
+
+pub mod history;
+pub mod runner;
+pub mod schedule;
+
+pub const CRON_DIR: &str = "/var/spool/supercp/cron";
+
+/// Default and maximum runtime for `run_cron_job_now`, in seconds.
+const DEFAULT_RUN_TIMEOUT: u64 = 300;
+const MAX_RUN_TIMEOUT: u64 = 3600;
+
+/// Cron's own default search path, used for jobs run on demand.
+const CRON_PATH: &str = "/usr/bin:/bin";
+
+/// Upper bound on the fire times `describe_cron_schedule` will compute in one call.
+const MAX_PREVIEW_RUNS: u64 = 50;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CronJob {
+    pub id: String,
+    pub schedule: String,
+    pub command: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Shell command run after a failed run, with details in `SUPERCP_*` variables
+    #[serde(default)]
+    pub on_failure: Option<String>,
+    /// Skip a run while the previous one is still going
+    #[serde(default)]
+    pub no_overlap: bool,
+    /// Seconds after which the run and everything it started is killed
+    #[serde(default)]
+    pub max_runtime: Option<u64>,
+    /// CPU seconds the command may use (`RLIMIT_CPU`)
+    #[serde(default)]
+    pub cpu_limit: Option<u64>,
+    /// Address space limit in MB (`RLIMIT_AS`)
+    #[serde(default)]
+    pub memory_limit: Option<u64>,
+    /// Scheduling priority, 0 (normal) to 19 (lowest)
+    #[serde(default)]
+    pub nice: Option<i32>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn store_path(user: &str) -> String {
+    format!("{}/{}.json", CRON_DIR, user)
+}
+
+fn crontab_path(user: &str) -> String {
+    format!("{}/{}.cron", CRON_DIR, user)
+}
+
+/// System user names end up in file paths and `crontab -u`, so only allow what useradd would.
+pub fn validate_user(user: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let valid = !user.is_empty()
+        && user.len() <= 32
+        && user.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && user.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid user name '{}'", user).into())
+    }
+}
+
+/// Job ids become directory names and crontab arguments, and must not look like runner options.
+pub fn validate_job_id(id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let valid = id.len() <= 64
+        && id.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid job id '{}'", id).into());
+    }
+    Ok(())
+}
+
+fn validate_env_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes a value for `sh`, which is what cron runs each line through.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Like [`shell_quote`], but leaves plain words such as flags and ids readable.
+fn shell_word(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        shell_quote(value)
+    }
+}
+
+/// Arguments for the [`runner`] that runs a job, after the path of the daemon binary.
+/// `options` are extra runner flags placed before the job itself.
+fn runner_args(job: &CronJob, options: &[String]) -> Vec<String> {
+    let mut args = vec![runner::COMMAND.to_string()];
+    let mut option = |flag: &str, value: Option<String>| {
+        if let Some(value) = value {
+            args.push(flag.to_string());
+            args.push(value);
+        }
+    };
+    option("--on-failure", job.on_failure.clone());
+    option("--timeout", job.max_runtime.map(|v| v.to_string()));
+    option("--cpu-limit", job.cpu_limit.map(|v| v.to_string()));
+    option("--memory-limit", job.memory_limit.map(|v| v.to_string()));
+    option("--nice", job.nice.map(|v| v.to_string()));
+    if job.no_overlap {
+        args.push("--no-overlap".to_string());
+    }
+    args.extend(options.iter().cloned());
+    args.push(job.id.clone());
+    args.push(job.command.clone());
+    args
+}
+
+/// Loads a user's jobs, migrating a crontab written before jobs were stored as JSON.
+pub fn load_jobs(user: &str) -> Result<Vec<CronJob>, Box<dyn std::error::Error>> {
+    let store = store_path(user);
+    if Path::new(&store).exists() {
+        return Ok(serde_json::from_str(&fs::read_to_string(&store)?)?);
+    }
+
+    let legacy = crontab_path(user);
+    if !Path::new(&legacy).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut jobs = Vec::new();
+    for line in fs::read_to_string(&legacy)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let field_count = if line.starts_with('@') { 1 } else { 5 };
+        let parts: Vec<&str> = line.splitn(field_count + 1, char::is_whitespace).collect();
+        if parts.len() <= field_count {
+            continue;
+        }
+
+        jobs.push(CronJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            schedule: parts[..field_count].join(" "),
+            command: parts[field_count].trim().to_string(),
+            enabled: true,
+            comment: None,
+            env: BTreeMap::new(),
+            on_failure: None,
+            no_overlap: false,
+            max_runtime: None,
+            cpu_limit: None,
+            memory_limit: None,
+            nice: None,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Builds a validated job from a request object, keeping the id of the existing job it updates.
+fn parse_job(index: usize, job: &Value, existing: &[CronJob]) -> Result<CronJob, Box<dyn std::error::Error>> {
+    let context = |msg: String| format!("Job {}: {}", index + 1, msg);
+
+    let command = job["command"].as_str().unwrap_or("").trim();
+    if command.is_empty() {
+        return Err(context("Missing command".to_string()).into());
+    }
+    // A newline would let a command smuggle extra crontab lines in
+    if command.contains('\n') || command.contains('\r') {
+        return Err(context("Command must be a single line".to_string()).into());
+    }
+
+    let schedule = schedule::normalize(job["schedule"].as_str().unwrap_or("")).map_err(|e| context(e.to_string()))?;
+
+    let mut env = BTreeMap::new();
+    if let Some(vars) = job["env"].as_object() {
+        for (name, value) in vars {
+            if !validate_env_name(name) {
+                return Err(context(format!("Invalid environment variable name '{}'", name)).into());
+            }
+            let value = value.as_str().ok_or_else(|| context(format!("Environment variable {} must be a string", name)))?;
+            if value.contains('\n') {
+                return Err(context(format!("Environment variable {} must be a single line", name)).into());
+            }
+            env.insert(name.clone(), value.to_string());
+        }
+    }
+
+    // The panel stores these as is_active/description; accept both spellings
+    let enabled = job["enabled"].as_bool().or(job["is_active"].as_bool()).unwrap_or(true);
+    let comment = job["comment"]
+        .as_str()
+        .or(job["description"].as_str())
+        .map(|c| c.lines().next().unwrap_or("").to_string())
+        .filter(|c| !c.is_empty());
+
+    let on_failure = match job["on_failure"].as_str().map(str::trim) {
+        Some(hook) if hook.contains('\n') || hook.contains('\r') => {
+            return Err(context("Failure hook must be a single line".to_string()).into());
+        }
+        Some(hook) if !hook.is_empty() => Some(hook.to_string()),
+        _ => None,
+    };
+
+    let limit = |name: &str, min: u64| -> Result<Option<u64>, String> {
+        match &job[name] {
+            Value::Null => Ok(None),
+            value => match value.as_u64() {
+                Some(n) if n >= min => Ok(Some(n)),
+                _ => Err(context(format!("{} must be a whole number of at least {}", name, min))),
+            },
+        }
+    };
+    let max_runtime = limit("max_runtime", 1)?;
+    let cpu_limit = limit("cpu_limit", 1)?;
+    let memory_limit = limit("memory_limit", 16)?;
+    let nice = match limit("nice", 0)? {
+        // Raising priority needs root, and jobs run as the user
+        Some(n) if n > 19 => return Err(context("nice must be between 0 and 19".to_string()).into()),
+        n => n.map(|n| n as i32),
+    };
+    let no_overlap = job["no_overlap"].as_bool().unwrap_or(false);
+
+    // Keep ids stable: use the one supplied, or match an unchanged job from the previous list
+    let id = match job["id"].as_str() {
+        Some(id) if !id.is_empty() => {
+            validate_job_id(id).map_err(|e| context(e.to_string()))?;
+            id.to_string()
+        }
+        _ => existing
+            .iter()
+            .find(|e| e.schedule == schedule && e.command == command)
+            .map(|e| e.id.clone())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+    };
+
+    Ok(CronJob {
+        id,
+        schedule,
+        command: command.to_string(),
+        enabled,
+        comment,
+        env,
+        on_failure,
+        no_overlap,
+        max_runtime,
+        cpu_limit,
+        memory_limit,
+        nice,
+    })
+}
+
+/// Renders the crontab installed for a user. Disabled jobs are kept as comments so the
+/// file still documents everything the panel manages.
+fn render_crontab(jobs: &[CronJob], runner: &str) -> String {
+    let mut content = String::from("# Managed by SuperCP. Manual edits are overwritten.\n");
+
+    for job in jobs {
+        content.push_str(&format!("# supercp-job: {}", job.id));
+        if let Some(comment) = &job.comment {
+            content.push_str(&format!(" {}", comment));
+        }
+        content.push('\n');
+
+        let mut line = String::new();
+        for (name, value) in &job.env {
+            line.push_str(&format!("{}={} ", name, shell_quote(value)));
+        }
+        line.push_str(&shell_word(runner));
+        for arg in runner_args(job, &[]) {
+            line.push(' ');
+            line.push_str(&shell_word(&arg));
+        }
+        // An unescaped % is turned into a newline by cron
+        let line = line.replace('%', r"\%");
+
+        if job.enabled {
+            content.push_str(&format!("{} {}\n", job.schedule, line));
+        } else {
+            content.push_str(&format!("# {} {}\n", job.schedule, line));
+        }
+    }
+
+    content
+}
+
+pub async fn update_cron_jobs(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let user = params["user"].as_str().ok_or("Missing user")?;
+    let jobs = params["jobs"].as_array().ok_or("Missing jobs")?;
+    validate_user(user)?;
+
+    // Validate everything before touching the crontab so one bad entry can't break the rest
+    let existing = load_jobs(user)?;
+    let mut parsed = Vec::new();
+    for (index, job) in jobs.iter().enumerate() {
+        let job = parse_job(index, job, &existing)?;
+        if parsed.iter().any(|p: &CronJob| p.id == job.id) {
+            return Err(format!("Job {}: duplicate id {}", index + 1, job.id).into());
+        }
+        parsed.push(job);
+    }
+
+    fs::create_dir_all(CRON_DIR)?;
+    history::prepare_user_dir(user)?;
+
+    // Jobs run through this binary, wherever it is installed
+    let runner = std::env::current_exe()?.to_string_lossy().to_string();
+
+    // Only replace the stored crontab once cron has accepted the new one
+    let cron_path = crontab_path(user);
+    let pending_path = format!("{}.new", cron_path);
+    fs::write(&pending_path, render_crontab(&parsed, &runner))?;
+
+    // Apply the crontab for the user
+    let applied = Command::new("crontab").arg("-u").arg(user).arg(&pending_path).run().await;
+    if let Err(e) = applied {
+        let _ = fs::remove_file(&pending_path);
+        return Err(format!("Failed to apply crontab for {}: {}", user, e).into());
+    }
+
+    fs::rename(&pending_path, &cron_path)?;
+    fs::write(store_path(user), serde_json::to_string_pretty(&parsed)?)?;
+    history::remove_stale(user, &parsed.iter().map(|j| j.id.as_str()).collect::<Vec<_>>());
+
+    Ok(format!("Cron jobs updated and applied for {}", user))
+}
+
+pub async fn list_cron_jobs(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    match params["user"].as_str() {
+        Some(user) => {
+            validate_user(user)?;
+            let jobs: Vec<Value> = load_jobs(user)?
+                .into_iter()
+                .map(|job| {
+                    let last_run = history::load(user, Some(&job.id), 1).into_iter().next().map(|run| {
+                        json!({
+                            "started_at": run.started_at,
+                            "duration_ms": run.duration_ms,
+                            "exit_code": run.exit_code,
+                            "success": run.success,
+                            "timed_out": run.timed_out,
+                            "skipped": run.skipped,
+                        })
+                    });
+                    let mut value = json!(job);
+                    value["last_run"] = json!(last_run);
+                    value
+                })
+                .collect();
+            Ok(json!(jobs))
+        }
+        None => {
+            // Without a user, summarize every user with managed jobs
+            let mut users = Vec::new();
+            if let Ok(entries) = fs::read_dir(CRON_DIR) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    let ext = path.extension().and_then(|s| s.to_str());
+                    if ext == Some("json") || ext == Some("cron") {
+                        if let Some(user) = path.file_stem().and_then(|s| s.to_str()) {
+                            if validate_user(user).is_ok() && !users.iter().any(|u: &Value| u["user"] == user) {
+                                let jobs = load_jobs(user)?;
+                                users.push(json!({
+                                    "user": user,
+                                    "jobs": jobs.len(),
+                                    "enabled": jobs.iter().filter(|j| j.enabled).count(),
+                                }));
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(json!(users))
+        }
+    }
+}
+
+/// The timezone cron itself runs in, from `/etc/timezone` or the `/etc/localtime` link.
+fn system_timezone() -> Tz {
+    let from_file = fs::read_to_string("/etc/timezone").ok().map(|s| s.trim().to_string());
+    let from_link = || {
+        fs::read_link("/etc/localtime")
+            .ok()
+            .and_then(|target| target.to_string_lossy().split("zoneinfo/").nth(1).map(|s| s.to_string()))
+    };
+
+    from_file.or_else(from_link).and_then(|name| name.parse().ok()).unwrap_or(Tz::UTC)
+}
+
+pub async fn describe_cron_schedule(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let text = params["schedule"].as_str().ok_or("Missing schedule")?;
+    let count = params["count"].as_u64().unwrap_or(5).min(MAX_PREVIEW_RUNS) as usize;
+    let tz: Tz = match params["timezone"].as_str() {
+        Some(name) => name.parse().map_err(|_| format!("Unknown timezone '{}'", name))?,
+        None => system_timezone(),
+    };
+
+    let parsed = schedule::Schedule::parse(text)?;
+    let description = schedule::describe(text)?;
+
+    let next_runs = next_runs(&parsed, tz, Utc::now().with_timezone(&tz).naive_local(), count);
+
+    Ok(json!({
+        "schedule": schedule::normalize(text)?,
+        "timezone": tz.name(),
+        "description": description,
+        "next_runs": next_runs,
+    }))
+}
+
+/// The next `count` fire times after the wall-clock time `after`, as RFC 3339 timestamps in `tz`.
+fn next_runs(parsed: &schedule::Schedule, tz: Tz, after: NaiveDateTime, count: usize) -> Vec<String> {
+    // Walk the schedule in the zone's wall-clock time, the way cron does
+    let mut next_runs = Vec::new();
+    let mut cursor = after;
+    while next_runs.len() < count {
+        let Some(local) = parsed.next_after(cursor) else {
+            break;
+        };
+        cursor = local;
+
+        match tz.from_local_datetime(&local) {
+            // A time repeated when the clocks go back runs once, at its first occurrence
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => next_runs.push(time.to_rfc3339()),
+            // Wall-clock times skipped by a DST change never occur
+            LocalResult::None => {}
+        }
+    }
+    next_runs
+}
+
+pub async fn run_cron_job_now(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let user = params["user"].as_str().ok_or("Missing user")?;
+    let job_id = params["job_id"].as_str().ok_or("Missing job_id")?;
+    validate_user(user)?;
+
+    let job = load_jobs(user)?
+        .into_iter()
+        .find(|j| j.id == job_id)
+        .ok_or_else(|| format!("Cron job {} not found for {}", job_id, user))?;
+    // An explicit timeout wins over the job's own max_runtime for test runs
+    let timeout = params["timeout"]
+        .as_u64()
+        .or(job.max_runtime)
+        .unwrap_or(DEFAULT_RUN_TIMEOUT)
+        .clamp(1, MAX_RUN_TIMEOUT);
+    let account = User::from_name(user)?.ok_or_else(|| format!("System user {} does not exist", user))?;
+    history::prepare_user_dir(user)?;
+
+    let run_id = uuid::Uuid::new_v4().to_string();
+    let options = [
+        "--timeout".to_string(),
+        timeout.to_string(),
+        "--trigger".to_string(),
+        "manual".to_string(),
+        "--run-id".to_string(),
+        run_id.clone(),
+    ];
+    let home = if account.dir.is_dir() { account.dir.clone() } else { "/".into() };
+
+    // Cancelling kills the runner, which takes the job's process group down and records the run;
+    // aborting would only drop the runner and leave the job running unsupervised
+    let _shield = jobs::shield()?;
+
+    // Same environment cron would give the job: a clean one with the user's basics
+    let mut command = Command::new(std::env::current_exe()?)
+        .args(runner_args(&job, &options))
+        .env_clear()
+        .env("HOME", &account.dir)
+        .env("USER", user)
+        .env("LOGNAME", user)
+        .env("SHELL", &account.shell)
+        .env("PATH", CRON_PATH)
+        .current_dir(home)
+        .run_as(account.uid.as_raw(), account.gid.as_raw())
+        // The runner enforces the limit itself; this only guards against it hanging
+        .timeout(Duration::from_secs(timeout + 30))
+        .cancellable();
+    for (name, value) in &job.env {
+        command = command.env(name, value);
+    }
+
+    let output = command.output().await.map_err(|e| format!("Failed to run cron job {}: {}", job_id, e))?;
+    if jobs::current().is_some_and(|job| job.is_cancelled()) {
+        return Err(CANCELLED.into());
+    }
+
+    // The runner records the run; fall back to the raw output if it could not
+    let record = history::load(user, Some(job_id), history::MAX_RUNS_PER_JOB)
+        .into_iter()
+        .find(|r| r.run_id == run_id);
+    match record {
+        Some(record) => Ok(json!(record)),
+        None => Ok(json!({
+            "job_id": job_id,
+            "user": user,
+            "exit_code": output.code,
+            "success": output.success(),
+            "stdout": output.stdout,
+            "stderr": output.stderr,
+            "trigger": "manual",
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runs(text: &str, tz: &str, after: &str, count: usize) -> Vec<String> {
+        let parsed = schedule::Schedule::parse(text).unwrap();
+        let after = NaiveDateTime::parse_from_str(after, "%Y-%m-%d %H:%M").unwrap();
+        next_runs(&parsed, tz.parse().unwrap(), after, count)
+    }
+
+    fn job(id: &str, command: &str) -> CronJob {
+        CronJob {
+            id: id.to_string(),
+            schedule: "*/5 * * * *".to_string(),
+            command: command.to_string(),
+            enabled: true,
+            comment: None,
+            env: BTreeMap::new(),
+            on_failure: None,
+            no_overlap: false,
+            max_runtime: None,
+            cpu_limit: None,
+            memory_limit: None,
+            nice: None,
+        }
+    }
+
+    #[test]
+    fn crontab_lines_escape_percent_signs_and_quotes() {
+        let mut first = job("backup", r#"tar czf "/backups/$(date +%F).tgz" ~/site && echo 'done'"#);
+        first.comment = Some("Nightly backup".to_string());
+        first.env.insert("GREETING".to_string(), "it's 100%".to_string());
+        first.on_failure = Some("mail -s 'backup failed' root".to_string());
+        let mut second = job("disabled", "echo 50%");
+        second.enabled = false;
+
+        let crontab = render_crontab(&[first, second], "/usr/local/bin/super-daemon");
+        let lines: Vec<&str> = crontab.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "# Managed by SuperCP. Manual edits are overwritten.",
+                "# supercp-job: backup Nightly backup",
+                r#"*/5 * * * * GREETING='it'\''s 100\%' /usr/local/bin/super-daemon cron-run --on-failure 'mail -s '\''backup failed'\'' root' backup 'tar czf "/backups/$(date +\%F).tgz" ~/site && echo '\''done'\'''"#,
+                "# supercp-job: disabled",
+                r"# */5 * * * * /usr/local/bin/super-daemon cron-run disabled 'echo 50\%'",
+            ]
+        );
+    }
+
+    #[test]
+    fn crontab_lines_give_the_runner_its_arguments_back() {
+        let mut job = job("quoting", r#"printf '%s\n' "a  b" \$HOME; echo `id -u` % done"#);
+        job.on_failure = Some(r#"logger "it's failing""#.to_string());
+        job.nice = Some(-5);
+        let crontab = render_crontab(std::slice::from_ref(&job), "printf");
+
+        // What cron hands to sh: the command after the schedule, with \% turned back into %
+        let line = crontab.lines().last().unwrap();
+        let command = line.strip_prefix("*/5 * * * * printf ").unwrap().replace(r"\%", "%");
+        let output = std::process::Command::new("sh").arg("-c").arg(format!(r"printf '%s\0' {}", command)).output().unwrap();
+        let args: Vec<String> = String::from_utf8(output.stdout).unwrap().split_terminator('\0').map(String::from).collect();
+        assert_eq!(args, runner_args(&job, &[]));
+    }
+
+    #[test]
+    fn next_runs_skip_times_lost_to_dst() {
+        // Berlin moves from 02:00 to 03:00 on 2024-03-31
+        assert_eq!(
+            runs("30 2 * * *", "Europe/Berlin", "2024-03-29 12:00", 3),
+            ["2024-03-30T02:30:00+01:00", "2024-04-01T02:30:00+02:00", "2024-04-02T02:30:00+02:00"]
+        );
+        assert_eq!(
+            runs("*/30 * * * *", "Europe/Berlin", "2024-03-31 01:00", 3),
+            ["2024-03-31T01:30:00+01:00", "2024-03-31T03:00:00+02:00", "2024-03-31T03:30:00+02:00"]
+        );
+    }
+
+    #[test]
+    fn next_runs_fire_once_in_repeated_hours() {
+        // Berlin goes back from 03:00 to 02:00 on 2024-10-27; the first 02:30 is still summer time
+        assert_eq!(
+            runs("30 2 * * *", "Europe/Berlin", "2024-10-26 12:00", 2),
+            ["2024-10-27T02:30:00+02:00", "2024-10-28T02:30:00+01:00"]
+        );
+        assert_eq!(
+            runs("0 * * * *", "Europe/Berlin", "2024-10-27 01:30", 3),
+            ["2024-10-27T02:00:00+02:00", "2024-10-27T03:00:00+01:00", "2024-10-27T04:00:00+01:00"]
+        );
+    }
+
+    #[test]
+    fn next_runs_stop_for_schedules_that_never_fire() {
+        assert!(runs("0 0 31 2 *", "UTC", "2024-01-01 00:00", 5).is_empty());
+        assert!(runs("@reboot", "UTC", "2024-01-01 00:00", 5).is_empty());
+        assert_eq!(runs("0 0 29 2 *", "UTC", "2024-01-01 00:00", 2), ["2024-02-29T00:00:00+00:00", "2028-02-29T00:00:00+00:00"]);
+    }
+}
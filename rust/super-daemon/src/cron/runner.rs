@@ -0,0 +1,423 @@
+//! The wrapper cron runs each installed job through: `super-daemon cron-run [options] <job-id> <command>`.
+//!
+//! It runs the command with `sh -c` as whatever user cron started it as, passes the output
+//! through (so cron's usual mail still works), records the run in the job's history and calls
+//! the job's failure hook when the command does not succeed.
+//!
+//! Options carry the job's limits: `--timeout` kills the command and everything it started
+//! once the limit is reached, `--no-overlap` skips a run while the previous one still holds
+//! the job's lock, and `--cpu-limit`/`--memory-limit`/`--nice` are applied to the command.
+//!
+//! A SIGTERM sent to the runner (by `cancel_job` on a manual run, or at shutdown) is passed on
+//! to the command's process group, and the run is still recorded.
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{getuid, Pid, User};
+use std::fs::{self, File};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
+
+use super::history::{self, RunRecord, MAX_OUTPUT_BYTES};
+use crate::exec::Command;
+
+/// First argument that switches the daemon binary into runner mode.
+pub const COMMAND: &str = "cron-run";
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Exit status used when the runner itself fails before the job could run.
+const RUNNER_FAILED: i32 = 126;
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Takes the job's lock, or returns `None` while another run holds it.
+fn try_lock(user: &str, job_id: &str) -> std::io::Result<Option<Flock<File>>> {
+    let dir = history::job_dir(user, job_id);
+    fs::create_dir_all(&dir)?;
+    let file = File::options().create(true).truncate(false).write(true).open(dir.join(".lock"))?;
+
+    match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+        Ok(lock) => Ok(Some(lock)),
+        Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+        Err((_, errno)) => Err(errno.into()),
+    }
+}
+
+fn parse_option<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("supercp: invalid value '{}' for {}", value, flag))
+}
+
+/// Reads a child's stream, keeping its last [`MAX_OUTPUT_BYTES`] and copying it to `to` if given.
+async fn capture(mut from: impl AsyncRead + Unpin, mut to: Option<impl AsyncWrite + Unpin>) -> (Vec<u8>, bool) {
+    let mut tail = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+
+    while let Ok(n) = from.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        if let Some(to) = to.as_mut() {
+            let _ = to.write_all(&buf[..n]).await;
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > MAX_OUTPUT_BYTES * 2 {
+            tail.drain(..tail.len() - MAX_OUTPUT_BYTES);
+            truncated = true;
+        }
+    }
+    if let Some(to) = to.as_mut() {
+        let _ = to.flush().await;
+    }
+
+    if tail.len() > MAX_OUTPUT_BYTES {
+        tail.drain(..tail.len() - MAX_OUTPUT_BYTES);
+        truncated = true;
+    }
+    (tail, truncated)
+}
+
+async fn notify_failure(hook: &str, record: &RunRecord) {
+    let exit = record.exit_code.map(|c| c.to_string()).unwrap_or_default();
+    let signal = record.signal.map(|s| s.to_string()).unwrap_or_default();
+
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("SUPERCP_JOB_ID", &record.job_id)
+        .env("SUPERCP_USER", &record.user)
+        .env("SUPERCP_EXIT_CODE", exit)
+        .env("SUPERCP_SIGNAL", signal)
+        .env("SUPERCP_DURATION_MS", record.duration_ms.to_string())
+        .env("SUPERCP_TIMED_OUT", if record.timed_out { "1" } else { "0" })
+        .env("SUPERCP_STDERR", &record.stderr)
+        .timeout(HOOK_TIMEOUT)
+        .run()
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("supercp: failure hook for job {} failed: {}", record.job_id, e);
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    on_failure: Option<String>,
+    run_id: Option<String>,
+    timeout: Option<Duration>,
+    trigger: Option<String>,
+    no_overlap: bool,
+    /// CPU seconds
+    cpu_limit: Option<u64>,
+    /// Address space in MB
+    memory_limit: Option<u64>,
+    nice: Option<i32>,
+}
+
+/// Splits the arguments into options and the `<job-id> <command>` pair. Job ids never start
+/// with `--`, so the first argument that doesn't ends the options.
+fn parse_args(args: &[String]) -> Result<(Options, &String, &String), String> {
+    let mut options = Options::default();
+    let mut rest = args;
+
+    loop {
+        match rest {
+            [flag, tail @ ..] if flag == "--no-overlap" => {
+                options.no_overlap = true;
+                rest = tail;
+            }
+            [flag, value, tail @ ..] if flag.starts_with("--") => {
+                match flag.as_str() {
+                    "--on-failure" => options.on_failure = Some(value.clone()),
+                    "--timeout" => options.timeout = Some(Duration::from_secs(parse_option(flag, value)?)),
+                    "--trigger" => options.trigger = Some(value.clone()),
+                    "--run-id" => options.run_id = Some(value.clone()),
+                    "--cpu-limit" => options.cpu_limit = Some(parse_option(flag, value)?),
+                    "--memory-limit" => options.memory_limit = Some(parse_option(flag, value)?),
+                    "--nice" => options.nice = Some(parse_option(flag, value)?),
+                    _ => return Err(format!("supercp: unknown option {}", flag)),
+                }
+                rest = tail;
+            }
+            [job_id, command] => return Ok((options, job_id, command)),
+            _ => {
+                return Err(format!(
+                    "usage: super-daemon {} [--on-failure <command>] [--timeout <secs>] [--no-overlap] \
+                     [--cpu-limit <secs>] [--memory-limit <mb>] [--nice <n>] [--trigger <name>] [--run-id <id>] <job-id> <command>",
+                    COMMAND
+                ))
+            }
+        }
+    }
+}
+
+/// Entry point for runner mode; returns the exit status for the process.
+pub async fn run(args: &[String]) -> i32 {
+    let (options, job_id, command) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}", message);
+            return RUNNER_FAILED;
+        }
+    };
+    let Options { on_failure, run_id, timeout, trigger, no_overlap, cpu_limit, memory_limit, nice } = options;
+    let trigger = trigger.unwrap_or_else(|| "schedule".to_string());
+    let run_id = run_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let user = match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => getuid().to_string(),
+    };
+
+    // Held until the run is recorded; the lock is released when the file is closed
+    let _lock = if no_overlap {
+        match try_lock(&user, job_id) {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => {
+                let now = now_millis();
+                let record = RunRecord {
+                    run_id,
+                    job_id: job_id.clone(),
+                    user,
+                    started_at: now,
+                    finished_at: now,
+                    duration_ms: 0,
+                    exit_code: None,
+                    signal: None,
+                    success: false,
+                    stdout: String::new(),
+                    stderr: "Skipped: the previous run is still in progress".to_string(),
+                    truncated: false,
+                    trigger,
+                    timed_out: false,
+                    skipped: true,
+                };
+                if let Err(e) = history::write(&record) {
+                    eprintln!("supercp: failed to record run of job {}: {}", job_id, e);
+                }
+                return 0;
+            }
+            Err(e) => {
+                // Better to risk an overlap than to never run the job
+                eprintln!("supercp: failed to lock job {}: {}", job_id, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Only scheduled runs pass output on to cron; manual runs are read from the history
+    let passthrough = trigger == "schedule";
+    let timed_out = Arc::new(AtomicBool::new(false));
+
+    let started_at = now_millis();
+    let mut child = match nice {
+        Some(nice) => {
+            let mut child = tokio::process::Command::new("nice");
+            child.arg("-n").arg(nice.to_string()).arg("sh");
+            child
+        }
+        None => tokio::process::Command::new("sh"),
+    };
+    child
+        .arg("-c")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so a timeout also kills whatever the job started
+        .process_group(0);
+
+    if cpu_limit.is_some() || memory_limit.is_some() {
+        // SAFETY: the closure runs between fork and exec and only calls setrlimit, which is
+        // async-signal-safe; it doesn't allocate or take locks.
+        unsafe {
+            child.pre_exec(move || {
+                if let Some(secs) = cpu_limit {
+                    setrlimit(Resource::RLIMIT_CPU, secs, secs)?;
+                }
+                if let Some(mb) = memory_limit {
+                    let bytes = mb * 1024 * 1024;
+                    setrlimit(Resource::RLIMIT_AS, bytes, bytes)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Installed before the job starts so a SIGTERM can't slip in and kill the runner alone
+    let terminate = signal(SignalKind::terminate());
+    let child = child.spawn();
+
+    let (status, stdout, stderr) = match child {
+        Ok(mut child) => {
+            let killer = match (timeout, child.id()) {
+                (Some(limit), Some(pid)) => {
+                    let timed_out = Arc::clone(&timed_out);
+                    Some(tokio::spawn(async move {
+                        tokio::time::sleep(limit).await;
+                        timed_out.store(true, Ordering::SeqCst);
+                        let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                    }))
+                }
+                _ => None,
+            };
+            // The job has its own process group, so it wouldn't see a SIGTERM aimed at us
+            let forwarder = match (terminate, child.id()) {
+                (Ok(mut terminate), Some(pid)) => Some(tokio::spawn(async move {
+                    while terminate.recv().await.is_some() {
+                        let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+                    }
+                })),
+                (Err(e), _) => {
+                    eprintln!("supercp: failed to watch for SIGTERM: {}", e);
+                    None
+                }
+                _ => None,
+            };
+
+            let stdout = capture(child.stdout.take().expect("piped stdout"), passthrough.then(tokio::io::stdout));
+            let stderr = capture(child.stderr.take().expect("piped stderr"), passthrough.then(tokio::io::stderr));
+            let (stdout, stderr) = tokio::join!(stdout, stderr);
+            let status = child.wait().await.ok();
+
+            if let Some(killer) = killer {
+                killer.abort();
+            }
+            if let Some(forwarder) = forwarder {
+                forwarder.abort();
+            }
+            (status, stdout, stderr)
+        }
+        Err(e) => {
+            eprintln!("supercp: failed to start job {}: {}", job_id, e);
+            (None, (Vec::new(), false), (e.to_string().into_bytes(), false))
+        }
+    };
+    let finished_at = now_millis();
+
+    let record = RunRecord {
+        run_id,
+        job_id: job_id.clone(),
+        user,
+        started_at,
+        finished_at,
+        duration_ms: finished_at.saturating_sub(started_at),
+        exit_code: status.and_then(|s| s.code()),
+        signal: status.and_then(|s| s.signal()),
+        success: status.map(|s| s.success()).unwrap_or(false),
+        stdout: String::from_utf8_lossy(&stdout.0).to_string(),
+        stderr: String::from_utf8_lossy(&stderr.0).to_string(),
+        truncated: stdout.1 || stderr.1,
+        trigger,
+        timed_out: timed_out.load(Ordering::SeqCst),
+        skipped: false,
+    };
+
+    if let Err(e) = history::write(&record) {
+        eprintln!("supercp: failed to record run of job {}: {}", job_id, e);
+    }
+
+    if !record.success {
+        if let Some(hook) = on_failure {
+            notify_failure(&hook, &record).await;
+        }
+    }
+
+    match (record.exit_code, record.signal) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => RUNNER_FAILED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{runner_args, CronJob};
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn job() -> CronJob {
+        CronJob {
+            id: "nightly-report".to_string(),
+            schedule: "0 3 * * *".to_string(),
+            command: "--not-an-option 'quoted' \"too\" 50%".to_string(),
+            enabled: true,
+            comment: None,
+            env: BTreeMap::new(),
+            on_failure: Some("--mail admin@example.com".to_string()),
+            no_overlap: true,
+            max_runtime: Some(600),
+            cpu_limit: Some(120),
+            memory_limit: Some(512),
+            nice: Some(-5),
+        }
+    }
+
+    #[test]
+    fn parses_every_option_runner_args_writes() {
+        let job = job();
+        let args = runner_args(&job, &[]);
+        assert_eq!(args[0], COMMAND);
+
+        let (options, job_id, command) = parse_args(&args[1..]).unwrap();
+        assert_eq!(job_id, &job.id);
+        assert_eq!(command, &job.command);
+        assert_eq!(options.on_failure, job.on_failure);
+        assert_eq!(options.timeout, Some(Duration::from_secs(600)));
+        assert!(options.no_overlap);
+        assert_eq!(options.cpu_limit, job.cpu_limit);
+        assert_eq!(options.memory_limit, job.memory_limit);
+        assert_eq!(options.nice, job.nice);
+        assert_eq!(options.trigger, None);
+        assert_eq!(options.run_id, None);
+    }
+
+    #[test]
+    fn extra_options_override_the_jobs_own() {
+        let extra = ["--timeout", "30", "--trigger", "manual", "--run-id", "run-1"].map(String::from);
+        let args = runner_args(&job(), &extra);
+
+        let (options, job_id, _) = parse_args(&args[1..]).unwrap();
+        assert_eq!(job_id, "nightly-report");
+        assert_eq!(options.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(options.trigger.as_deref(), Some("manual"));
+        assert_eq!(options.run_id.as_deref(), Some("run-1"));
+    }
+
+    #[test]
+    fn parses_a_job_without_options() {
+        let mut job = job();
+        job.on_failure = None;
+        job.no_overlap = false;
+        job.max_runtime = None;
+        job.cpu_limit = None;
+        job.memory_limit = None;
+        job.nice = None;
+        let args = runner_args(&job, &[]);
+        assert_eq!(args.len(), 3);
+
+        let (options, job_id, command) = parse_args(&args[1..]).unwrap();
+        assert_eq!((job_id.as_str(), command.as_str()), ("nightly-report", job.command.as_str()));
+        assert!(options.on_failure.is_none() && options.timeout.is_none() && !options.no_overlap);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(&args(&["--bogus", "1", "job", "true"])).is_err());
+        assert!(parse_args(&args(&["--timeout", "soon", "job", "true"])).is_err());
+        assert!(parse_args(&args(&["job"])).is_err());
+        assert!(parse_args(&args(&["job", "true", "extra"])).is_err());
+    }
+}
@@ -0,0 +1,583 @@
+//! Parsing and validation of cron schedules, in the dialect Vixie/cronie cron accepts.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use std::fmt;
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Debug)]
+pub struct ScheduleError(String);
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// One of the five time fields of a cron expression.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Field {
+    Minute,
+    Hour,
+    DayOfMonth,
+    Month,
+    DayOfWeek,
+}
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Minute => "minute",
+            Field::Hour => "hour",
+            Field::DayOfMonth => "day of month",
+            Field::Month => "month",
+            Field::DayOfWeek => "day of week",
+        }
+    }
+
+    pub fn range(&self) -> (u32, u32) {
+        match self {
+            Field::Minute => (0, 59),
+            Field::Hour => (0, 23),
+            Field::DayOfMonth => (1, 31),
+            Field::Month => (1, 12),
+            // 7 is accepted as an alias for Sunday and folded into 0
+            Field::DayOfWeek => (0, 7),
+        }
+    }
+
+    fn names(&self) -> &'static [&'static str] {
+        match self {
+            Field::Month => &MONTH_NAMES,
+            Field::DayOfWeek => &DAY_NAMES,
+            _ => &[],
+        }
+    }
+
+    fn parse_value(&self, value: &str) -> Result<u32, ScheduleError> {
+        let lower = value.to_ascii_lowercase();
+        if let Some(pos) = self.names().iter().position(|n| *n == lower) {
+            // Month names are 1-based, day names 0-based
+            return Ok(if *self == Field::Month { pos as u32 + 1 } else { pos as u32 });
+        }
+
+        let number: u32 = value
+            .parse()
+            .map_err(|_| ScheduleError(format!("Invalid {} value '{}'", self.name(), value)))?;
+        let (min, max) = self.range();
+        if number < min || number > max {
+            return Err(ScheduleError(format!(
+                "{} value {} is out of range ({}-{})",
+                capitalize(self.name()),
+                number,
+                min,
+                max
+            )));
+        }
+
+        Ok(number)
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// The set of values a single field matches, as a bitmask over the field's range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FieldSet {
+    bits: u64,
+    /// True when the field starts with `*`. Cron only ORs day-of-month and day-of-week
+    /// together when neither of them does.
+    pub wildcard: bool,
+}
+
+impl FieldSet {
+    pub fn contains(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+
+    fn parse(field: Field, text: &str) -> Result<Self, ScheduleError> {
+        let (min, max) = field.range();
+        let mut bits = 0u64;
+
+        for part in text.split(',') {
+            if part.is_empty() {
+                return Err(ScheduleError(format!("Empty list item in {} field '{}'", field.name(), text)));
+            }
+
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .map_err(|_| ScheduleError(format!("Invalid step '{}' in {} field", step, field.name())))?;
+                    if step == 0 || step > max {
+                        return Err(ScheduleError(format!("Step {} is out of range in {} field", step, field.name())));
+                    }
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+
+            let (start, end) = if range == "*" {
+                (min, if field == Field::DayOfWeek { 6 } else { max })
+            } else if let Some((a, b)) = range.split_once('-') {
+                let (a, b) = (field.parse_value(a)?, field.parse_value(b)?);
+                if a > b {
+                    return Err(ScheduleError(format!("Range {} is backwards in {} field", range, field.name())));
+                }
+                (a, b)
+            } else {
+                let value = field.parse_value(range)?;
+                // "5/15" means "from 5 to the end, every 15"
+                if part.contains('/') { (value, max) } else { (value, value) }
+            };
+
+            for value in (start..=end).step_by(step as usize) {
+                let value = if field == Field::DayOfWeek && value == 7 { 0 } else { value };
+                bits |= 1 << value;
+            }
+        }
+
+        Ok(FieldSet { bits, wildcard: text.starts_with('*') })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Schedule {
+    /// `@reboot`: runs once when cron starts, never on a timetable.
+    Reboot,
+    Periodic {
+        minute: FieldSet,
+        hour: FieldSet,
+        day_of_month: FieldSet,
+        month: FieldSet,
+        day_of_week: FieldSet,
+    },
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> Result<Self, ScheduleError> {
+        let text = text.trim();
+        let expanded = match text.to_ascii_lowercase().as_str() {
+            "@reboot" => return Ok(Schedule::Reboot),
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other if other.starts_with('@') => {
+                return Err(ScheduleError(format!("Unknown schedule macro '{}'", text)));
+            }
+            _ => text,
+        };
+
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ScheduleError(format!(
+                "Schedule '{}' must have 5 fields (minute hour day-of-month month day-of-week), found {}",
+                text,
+                fields.len()
+            )));
+        }
+
+        Ok(Schedule::Periodic {
+            minute: FieldSet::parse(Field::Minute, fields[0])?,
+            hour: FieldSet::parse(Field::Hour, fields[1])?,
+            day_of_month: FieldSet::parse(Field::DayOfMonth, fields[2])?,
+            month: FieldSet::parse(Field::Month, fields[3])?,
+            day_of_week: FieldSet::parse(Field::DayOfWeek, fields[4])?,
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let Schedule::Periodic { day_of_month, day_of_week, .. } = self else {
+            return false;
+        };
+
+        let dom = day_of_month.contains(date.day());
+        let dow = day_of_week.contains(date.weekday().num_days_from_sunday());
+        // Like cron: when both day fields are restricted, either one matching is enough
+        if day_of_month.wildcard || day_of_week.wildcard {
+            dom && dow
+        } else {
+            dom || dow
+        }
+    }
+
+    /// The first wall-clock minute strictly after `after` on which the schedule fires, or `None`
+    /// for `@reboot` and for schedules that can never match (such as `0 0 31 2 *`).
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let Schedule::Periodic { minute, hour, month, .. } = self else {
+            return None;
+        };
+
+        let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0);
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Feb 29 on a given weekday repeats every 28 years; nothing valid takes longer
+        let limit = time + Duration::days(366 * 29);
+
+        while time <= limit {
+            if !month.contains(time.month()) {
+                let (year, next_month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+                time = midnight(NaiveDate::from_ymd_opt(year, next_month, 1)?)?;
+            } else if !self.matches_day(time.date()) {
+                time = midnight(time.date().succ_opt()?)?;
+            } else if !hour.contains(time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !minute.contains(time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+}
+
+/// Validates a schedule and returns it in the canonical form written to the crontab:
+/// macros lower-cased, fields separated by single spaces.
+pub fn normalize(text: &str) -> Result<String, ScheduleError> {
+    Schedule::parse(text)?;
+
+    let text = text.trim();
+    if text.starts_with('@') {
+        Ok(text.to_ascii_lowercase())
+    } else {
+        Ok(text.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+fn label(field: Field, value: u32) -> String {
+    const MONTHS: [&str; 12] = [
+        "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December",
+    ];
+    const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+    match field {
+        Field::Month => MONTHS[(value as usize + 11) % 12].to_string(),
+        Field::DayOfWeek => DAYS[value as usize % 7].to_string(),
+        _ => value.to_string(),
+    }
+}
+
+fn join_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// Describes one field of an already validated schedule, e.g. "every 15 minutes",
+/// "hours 9 through 17" or "Monday and Friday".
+fn describe_field(field: Field, text: &str) -> String {
+    let unit = match field {
+        Field::Minute => "minute",
+        Field::Hour => "hour",
+        Field::DayOfMonth => "day",
+        Field::Month => "month",
+        Field::DayOfWeek => "day of the week",
+    };
+    let named = matches!(field, Field::Month | Field::DayOfWeek);
+    let value = |v: &str| field.parse_value(v).map(|v| label(field, v)).unwrap_or_else(|_| v.to_string());
+
+    let mut values = Vec::new();
+    let mut phrases = Vec::new();
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+        let bounds = range.split_once('-');
+
+        match (step, bounds) {
+            (None, None) if range == "*" => phrases.push(format!("every {}", unit)),
+            (None, None) => values.push(value(range)),
+            (None, Some((a, b))) if named => phrases.push(format!("{} through {}", value(a), value(b))),
+            (None, Some((a, b))) => phrases.push(format!("{}s {} through {}", unit, value(a), value(b))),
+            (Some(step), _) => {
+                let every = if step == "1" { format!("every {}", unit) } else { format!("every {} {}s", step, unit) };
+                phrases.push(match bounds {
+                    Some((a, b)) => format!("{} from {} through {}", every, value(a), value(b)),
+                    None if range == "*" => every,
+                    None if named => format!("{} from {}", every, value(range)),
+                    None => format!("{} from {} {}", every, unit, value(range)),
+                });
+            }
+        }
+    }
+
+    if !values.is_empty() {
+        let list = join_list(&values);
+        phrases.insert(
+            0,
+            match (named, values.len()) {
+                (true, _) => list,
+                (false, 1) => format!("{} {}", unit, list),
+                (false, _) => format!("{}s {}", unit, list),
+            },
+        );
+    }
+
+    join_list(&phrases)
+}
+
+fn is_plain_number(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Renders a schedule as a short English sentence, e.g. "Every 15 minutes past hours 9
+/// through 17, on Monday through Friday".
+pub fn describe(text: &str) -> Result<String, ScheduleError> {
+    Schedule::parse(text)?;
+
+    let normalized = normalize(text)?;
+    let expanded = match normalized.as_str() {
+        "@reboot" => return Ok("At system startup".to_string()),
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        "@monthly" => "0 0 1 * *",
+        "@weekly" => "0 0 * * 0",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@hourly" => "0 * * * *",
+        other => other,
+    };
+    let fields: Vec<&str> = expanded.split(' ').collect();
+    let (minute, hour, dom, month, dow) = (fields[0], fields[1], fields[2], fields[3], fields[4]);
+
+    let mut parts = Vec::new();
+
+    // Fixed times read best as clock times; anything else is described field by field
+    if is_plain_number(minute) && hour.split(',').all(is_plain_number) {
+        let times: Vec<String> = hour
+            .split(',')
+            .map(|h| format!("{:02}:{:02}", h.parse::<u32>().unwrap_or(0), minute.parse::<u32>().unwrap_or(0)))
+            .collect();
+        parts.push(format!("At {}", join_list(&times)));
+    } else {
+        let mut time = describe_field(Field::Minute, minute);
+        if hour != "*" {
+            time = format!("{} past {}", time, describe_field(Field::Hour, hour));
+        } else if !time.starts_with("every") {
+            time.push_str(" past every hour");
+        }
+        parts.push(if time.starts_with("every") { capitalize(&time) } else { format!("At {}", time) });
+    }
+
+    let with_on = |phrase: String| if phrase.starts_with("every") { phrase } else { format!("on {}", phrase) };
+    let days: Vec<String> = [(Field::DayOfMonth, dom), (Field::DayOfWeek, dow)]
+        .iter()
+        .filter(|(_, text)| *text != "*")
+        .map(|(field, text)| with_on(describe_field(*field, text)))
+        .collect();
+    if !days.is_empty() {
+        let either = !dom.starts_with('*') && !dow.starts_with('*');
+        parts.push(days.join(if either { " or " } else { " and " }));
+    }
+
+    if month != "*" {
+        let phrase = describe_field(Field::Month, month);
+        parts.push(if phrase.starts_with("every") { phrase } else { format!("in {}", phrase) });
+    }
+
+    Ok(parts.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(text: &str) -> [FieldSet; 5] {
+        match Schedule::parse(text).unwrap() {
+            Schedule::Periodic { minute, hour, day_of_month, month, day_of_week } => [minute, hour, day_of_month, month, day_of_week],
+            Schedule::Reboot => panic!("{} parsed as @reboot", text),
+        }
+    }
+
+    fn values(set: FieldSet) -> Vec<u32> {
+        (0..=59).filter(|v| set.contains(*v)).collect()
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parses_lists_ranges_and_steps() {
+        let [minute, hour, day_of_month, month, day_of_week] = fields("0,30 9-17/4 */10 * 1-5");
+        assert_eq!(values(minute), [0, 30]);
+        assert_eq!(values(hour), [9, 13, 17]);
+        assert_eq!(values(day_of_month), [1, 11, 21, 31]);
+        assert_eq!(values(month), (1..=12).collect::<Vec<_>>());
+        assert_eq!(values(day_of_week), [1, 2, 3, 4, 5]);
+
+        // A step after a single value runs to the end of the field
+        let [minute, ..] = fields("5/15 * * * *");
+        assert_eq!(values(minute), [5, 20, 35, 50]);
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let [.., day_of_week] = fields("0 0 * * 7");
+        assert_eq!(values(day_of_week), [0]);
+
+        let [.., day_of_week] = fields("0 0 * * 5-7");
+        assert_eq!(values(day_of_week), [0, 5, 6]);
+
+        // `*` covers the week once, without an extra bit for 7
+        let [.., day_of_week] = fields("0 0 * * */2");
+        assert_eq!(values(day_of_week), [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn accepts_month_and_day_names() {
+        let [.., month, day_of_week] = fields("0 0 * JAN-mar,Dec Mon,wed-FRI");
+        assert_eq!(values(month), [1, 2, 3, 12]);
+        assert_eq!(values(day_of_week), [1, 3, 4, 5]);
+
+        assert!(Schedule::parse("0 0 * * monday").is_err());
+        assert!(Schedule::parse("0 0 jan * *").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        for text in [
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "* * * fri-mon *",
+            "*/0 * * * *",
+            "*/61 * * * *",
+            "1,,2 * * * *",
+            "* * * *",
+            "* * * * * *",
+            "@sometimes",
+        ] {
+            assert!(Schedule::parse(text).is_err(), "{} was accepted", text);
+        }
+
+        let error = Schedule::parse("5-1 * * * *").unwrap_err().to_string();
+        assert_eq!(error, "Range 5-1 is backwards in minute field");
+    }
+
+    #[test]
+    fn tracks_wildcard_fields() {
+        let [minute, hour, day_of_month, _, day_of_week] = fields("*/15 * 1-7 * 1");
+        assert!(minute.wildcard);
+        assert!(hour.wildcard);
+        assert!(!day_of_month.wildcard);
+        assert!(!day_of_week.wildcard);
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // 2024-09-13 is a Friday, 2024-09-06 a Friday and 2024-10-13 a Sunday
+        let either = Schedule::parse("0 0 13 * 5").unwrap();
+        assert!(either.matches_day(date(2024, 9, 13)));
+        assert!(either.matches_day(date(2024, 9, 6)));
+        assert!(either.matches_day(date(2024, 10, 13)));
+        assert!(!either.matches_day(date(2024, 9, 7)));
+
+        // A `*` in either field, even with a step, makes both have to match
+        let both = Schedule::parse("0 0 13 * */1").unwrap();
+        assert!(both.matches_day(date(2024, 10, 13)));
+        assert!(!both.matches_day(date(2024, 9, 6)));
+
+        let both = Schedule::parse("0 0 */2 * 5").unwrap();
+        assert!(both.matches_day(date(2024, 9, 13)));
+        assert!(!both.matches_day(date(2024, 9, 6)));
+        assert!(!both.matches_day(date(2024, 9, 7)));
+    }
+
+    #[test]
+    fn normalizes_schedules() {
+        assert_eq!(normalize(" 0   9 * *  1-5 ").unwrap(), "0 9 * * 1-5");
+        assert_eq!(normalize("@Daily").unwrap(), "@daily");
+        assert_eq!(normalize(" @REBOOT ").unwrap(), "@reboot");
+        assert_eq!(normalize("@annually").unwrap(), "@annually");
+        assert!(normalize("@fortnightly").is_err());
+        assert!(normalize("61 * * * *").is_err());
+
+        assert_eq!(Schedule::parse("@weekly").unwrap(), Schedule::parse("0 0 * * 0").unwrap());
+        assert_eq!(Schedule::parse("@reboot").unwrap(), Schedule::Reboot);
+    }
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn next(schedule: &str, after: &str) -> Option<NaiveDateTime> {
+        Schedule::parse(schedule).unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn next_after_is_strictly_later() {
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:14:59"), Some(at("2024-05-01 10:15:00")));
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:15:00"), Some(at("2024-05-01 10:30:00")));
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:15:30"), Some(at("2024-05-01 10:30:00")));
+        assert_eq!(next("0 9-17/4 * * *", "2024-05-01 13:00:00"), Some(at("2024-05-01 17:00:00")));
+        assert_eq!(next("0 9-17/4 * * *", "2024-05-01 17:00:00"), Some(at("2024-05-02 09:00:00")));
+        assert_eq!(next("30 23 31 12 *", "2024-12-31 23:30:00"), Some(at("2025-12-31 23:30:00")));
+    }
+
+    #[test]
+    fn next_after_skips_short_months() {
+        assert_eq!(next("0 0 31 * *", "2024-01-31 00:00:00"), Some(at("2024-03-31 00:00:00")));
+        assert_eq!(next("0 0 31 * *", "2024-03-31 00:00:00"), Some(at("2024-05-31 00:00:00")));
+        assert_eq!(next("0 6 30 * *", "2024-01-30 06:00:00"), Some(at("2024-03-30 06:00:00")));
+    }
+
+    #[test]
+    fn next_after_finds_leap_days() {
+        assert_eq!(next("0 12 29 2 *", "2024-02-29 12:00:00"), Some(at("2028-02-29 12:00:00")));
+        assert_eq!(next("0 12 29 2 *", "2023-06-01 00:00:00"), Some(at("2024-02-29 12:00:00")));
+        // 2100 is not a leap year
+        assert_eq!(next("0 0 29 2 *", "2096-03-01 00:00:00"), Some(at("2104-02-29 00:00:00")));
+    }
+
+    #[test]
+    fn next_after_gives_up_on_impossible_schedules() {
+        assert_eq!(next("0 0 31 2 *", "2024-01-01 00:00:00"), None);
+        assert_eq!(next("0 0 30 2 *", "2024-01-01 00:00:00"), None);
+        assert_eq!(next("0 0 31 4,6,9,11 *", "2024-01-01 00:00:00"), None);
+        assert_eq!(Schedule::Reboot.next_after(at("2024-01-01 00:00:00")), None);
+
+        // With a day of week as well, cron still fires on that weekday (2024-02-02 is a Friday)
+        assert_eq!(next("0 0 31 2 5", "2024-01-01 00:00:00"), Some(at("2024-02-02 00:00:00")));
+    }
+
+    #[test]
+    fn describes_schedules() {
+        let cases = [
+            ("*/15 * * * *", "Every 15 minutes"),
+            ("0 9 * * 1-5", "At 09:00, on Monday through Friday"),
+            ("30 8,20 * * *", "At 08:30 and 20:30"),
+            ("0 */2 1,15 * *", "At minute 0 past every 2 hours, on days 1 and 15"),
+            ("0 0 13 * 5", "At 00:00, on day 13 or on Friday"),
+            ("0 12 1-7 * mon", "At 12:00, on days 1 through 7 or on Monday"),
+            ("15 * */3 2 *", "At minute 15 past every hour, every 3 days, in February"),
+            (
+                "5/15 9-17 * jan-mar,dec *",
+                "Every 15 minutes from minute 5 past hours 9 through 17, in December and January through March",
+            ),
+            ("@Weekly", "At 00:00, on Sunday"),
+            ("@reboot", "At system startup"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(describe(text).unwrap(), expected, "{}", text);
+        }
+
+        assert!(describe("0 0 L * *").is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Nameservers that zones are published to once their files are written.
+//!
+//! The backend is chosen by `dns.backend` in the daemon config: `bind` loads the zone files
+//! through a daemon-maintained `named.conf` include and `rndc`, `powerdns` pushes records over
+//! the PowerDNS HTTP API, and `fake` only copies zones to a local directory for development
+//! and testing.
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use super::Zone;
+use crate::config::{BindConfig, DnsConfig, PowerDnsConfig};
+use crate::exec::Command;
+
+const API_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub trait DnsBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Makes the nameserver serve `zone`, whose file has already been written.
+    fn publish<'a>(&'a self, zone: &'a Zone) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Stops serving `domain`. Removing a zone the nameserver doesn't have is not an error.
+    fn remove<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<(), String>>;
+}
+
+pub fn from_config(dns: &DnsConfig) -> Result<Box<dyn DnsBackend>, String> {
+    match dns.backend.as_str() {
+        "bind" => Ok(Box::new(Bind::new(&dns.bind))),
+        "powerdns" => Ok(Box::new(PowerDns::new(&dns.powerdns)?)),
+        "fake" => Ok(Box::new(Fake { dir: PathBuf::from(&dns.fake_dir) })),
+        other => Err(format!("Unknown DNS backend '{}'", other)),
+    }
+}
+
+/// Serializes edits of the BIND include file.
+static BIND_CONF_LOCK: Mutex<()> = Mutex::const_new(());
+
+pub struct Bind {
+    zones_conf: String,
+}
+
+impl Bind {
+    pub fn new(config: &BindConfig) -> Self {
+        Bind { zones_conf: config.zones_conf.clone() }
+    }
+
+    fn zone_statement(domain: &str, path: &str) -> String {
+        format!("zone \"{}\" {{ type master; file \"{}\"; }};", domain, path)
+    }
+
+    fn is_statement_for(line: &str, domain: &str) -> bool {
+        line.starts_with(&format!("zone \"{}\" ", domain))
+    }
+
+    /// Adds or drops the domain's `zone` statement and reports whether the file changed.
+    fn update_conf(&self, domain: &str, statement: Option<String>) -> std::io::Result<bool> {
+        let current = fs::read_to_string(&self.zones_conf).unwrap_or_default();
+        let mut lines: Vec<String> = current
+            .lines()
+            .filter(|l| !Self::is_statement_for(l, domain))
+            .map(|l| l.to_string())
+            .collect();
+        if let Some(statement) = statement {
+            lines.push(statement);
+        }
+
+        let mut updated = lines.join("\n");
+        if !updated.is_empty() {
+            updated.push('\n');
+        }
+        if updated == current {
+            return Ok(false);
+        }
+
+        if let Some(dir) = Path::new(&self.zones_conf).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let pending = format!("{}.new", self.zones_conf);
+        fs::write(&pending, updated)?;
+        fs::rename(&pending, &self.zones_conf)?;
+        Ok(true)
+    }
+
+    async fn rndc(args: &[&str]) -> Result<(), String> {
+        Command::sudo("rndc").args(args).run().await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+impl DnsBackend for Bind {
+    fn name(&self) -> &'static str {
+        "bind"
+    }
+
+    fn publish<'a>(&'a self, zone: &'a Zone) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let added = {
+                let _guard = BIND_CONF_LOCK.lock().await;
+                self.update_conf(&zone.domain, Some(Self::zone_statement(&zone.domain, &zone.path)))
+                    .map_err(|e| format!("Failed to update {}: {}", self.zones_conf, e))?
+            };
+
+            // A new zone needs named to re-read its config; an existing one only a reload.
+            // A failed reload may mean an earlier reconfig never happened, so fall back to one.
+            if added || Self::rndc(&["reload", &zone.domain]).await.is_err() {
+                Self::rndc(&["reconfig"]).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let removed = {
+                let _guard = BIND_CONF_LOCK.lock().await;
+                self.update_conf(domain, None).map_err(|e| format!("Failed to update {}: {}", self.zones_conf, e))?
+            };
+
+            if removed {
+                Self::rndc(&["reconfig"]).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+pub struct PowerDns {
+    zones_url: String,
+    api_key: String,
+}
+
+impl PowerDns {
+    pub fn new(config: &PowerDnsConfig) -> Result<Self, String> {
+        if config.api_key.is_empty() {
+            return Err("dns.powerdns.api_key is not set in the daemon config".to_string());
+        }
+        Ok(PowerDns {
+            zones_url: format!("{}/api/v1/servers/{}/zones", config.api_url.trim_end_matches('/'), config.server_id),
+            api_key: config.api_key.clone(),
+        })
+    }
+
+    /// Groups the zone's records into PowerDNS rrsets, keyed by absolute name and type.
+    fn rrsets(zone: &Zone) -> BTreeMap<(String, String), Value> {
+        let mut rrsets: BTreeMap<(String, String), Value> = BTreeMap::new();
+        let apex = super::record::qualify("@", &zone.domain);
+        rrsets.insert(
+            (apex.clone(), "SOA".to_string()),
+            json!({ "name": apex, "type": "SOA", "ttl": zone.ttl, "records": [{ "content": zone.soa.render(), "disabled": false }] }),
+        );
+
+        for record in &zone.records {
+            let name = super::record::qualify(&record.name, &zone.domain);
+            let rtype = record.data.type_name().to_string();
+            let content = record.data.qualified(&zone.domain).render();
+            let rrset = rrsets
+                .entry((name.clone(), rtype.clone()))
+                .or_insert_with(|| json!({ "name": name, "type": rtype, "ttl": record.ttl, "records": [] }));
+            if let Some(records) = rrset["records"].as_array_mut() {
+                records.push(json!({ "content": content, "disabled": false }));
+            }
+        }
+
+        rrsets
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        ureq::request(method, url).set("X-API-Key", &self.api_key).timeout(API_TIMEOUT)
+    }
+
+    fn api_error(action: &str, error: ureq::Error) -> String {
+        match error {
+            ureq::Error::Status(code, response) => {
+                let body = response.into_string().unwrap_or_default();
+                format!("PowerDNS rejected {} ({}): {}", action, code, body.trim())
+            }
+            other => format!("PowerDNS API unreachable while trying to {}: {}", action, other),
+        }
+    }
+
+    fn publish_blocking(&self, zone: &Zone) -> Result<(), String> {
+        let zone_url = format!("{}/{}.", self.zones_url, zone.domain);
+        let rrsets = Self::rrsets(zone);
+
+        let existing: Option<Value> = match self.request("GET", &zone_url).call() {
+            Ok(response) => Some(response.into_json().map_err(|e| e.to_string())?),
+            Err(ureq::Error::Status(404, _)) => None,
+            Err(e) => return Err(Self::api_error("read the zone", e)),
+        };
+
+        match existing {
+            None => {
+                let body = json!({
+                    "name": format!("{}.", zone.domain),
+                    "kind": "Native",
+                    "nameservers": [],
+                    // The daemon owns the serial
+                    "soa_edit_api": "",
+                    "rrsets": rrsets.values().collect::<Vec<_>>(),
+                });
+                self.request("POST", &self.zones_url).send_json(body).map_err(|e| Self::api_error("create the zone", e))?;
+            }
+            Some(existing) => {
+                let mut changes: Vec<Value> = rrsets
+                    .values()
+                    .map(|rrset| {
+                        let mut rrset = rrset.clone();
+                        rrset["changetype"] = json!("REPLACE");
+                        rrset
+                    })
+                    .collect();
+
+                // Drop rrsets that are no longer in the zone
+                for old in existing["rrsets"].as_array().into_iter().flatten() {
+                    let key = (old["name"].as_str().unwrap_or("").to_string(), old["type"].as_str().unwrap_or("").to_string());
+                    if !rrsets.contains_key(&key) {
+                        changes.push(json!({ "name": key.0, "type": key.1, "changetype": "DELETE" }));
+                    }
+                }
+
+                self.request("PATCH", &zone_url)
+                    .send_json(json!({ "rrsets": changes }))
+                    .map_err(|e| Self::api_error("update the zone", e))?;
+            }
+        }
+
+        // Signed zones carry their own DNSSEC records, which PowerDNS serves as they are
+        let presigned_url = format!("{}/metadata/PRESIGNED", zone_url);
+        if zone.signed {
+            self.request("PUT", &presigned_url)
+                .send_json(json!({ "kind": "PRESIGNED", "metadata": ["1"] }))
+                .map_err(|e| Self::api_error("mark the zone as presigned", e))?;
+        } else {
+            match self.request("DELETE", &presigned_url).call() {
+                Ok(_) | Err(ureq::Error::Status(404, _)) => {}
+                Err(e) => return Err(Self::api_error("clear the zone's presigned flag", e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_blocking(&self, domain: &str) -> Result<(), String> {
+        let zone_url = format!("{}/{}.", self.zones_url, domain);
+        match self.request("DELETE", &zone_url).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(Self::api_error("delete the zone", e)),
+        }
+    }
+}
+
+impl DnsBackend for PowerDns {
+    fn name(&self) -> &'static str {
+        "powerdns"
+    }
+
+    fn publish<'a>(&'a self, zone: &'a Zone) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            // ureq is blocking; keep it off the async workers
+            let backend = PowerDns { zones_url: self.zones_url.clone(), api_key: self.api_key.clone() };
+            let zone = zone.clone();
+            tokio::task::spawn_blocking(move || backend.publish_blocking(&zone)).await.map_err(|e| e.to_string())?
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let backend = PowerDns { zones_url: self.zones_url.clone(), api_key: self.api_key.clone() };
+            let domain = domain.to_string();
+            tokio::task::spawn_blocking(move || backend.remove_blocking(&domain)).await.map_err(|e| e.to_string())?
+        }
+        .boxed()
+    }
+}
+
+/// Stand-in for a real nameserver: "publishing" copies the zone file into a directory, so
+/// the rest of the DNS code can be exercised on machines without BIND or PowerDNS.
+pub struct Fake {
+    dir: PathBuf,
+}
+
+impl DnsBackend for Fake {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn publish<'a>(&'a self, zone: &'a Zone) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+            fs::write(self.dir.join(format!("{}.zone", zone.domain)), &zone.content).map_err(|e| e.to_string())
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            match fs::remove_file(self.dir.join(format!("{}.zone", domain))) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                _ => Ok(()),
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,622 @@
+//! DNSSEC signing of hosted zones.
+//!
+//! A zone with DNSSEC enabled has a key-signing and a zone-signing key (ECDSA P-256,
+//! algorithm 13) in `/etc/supercp/dnssec/<domain>.json`. Whenever the zone is written it is
+//! also signed into `<domain>.zone.signed`, which is what the backend then serves: RRSIGs over
+//! every authoritative RRset, and NSEC3 without salt or extra iterations (RFC 9276) for
+//! authenticated denial. Signatures are valid for `dns.dnssec.signature_validity`; once less
+//! than `dns.dnssec.resign_before` of that is left, the zone is re-signed with a new serial.
+
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::record::{Record, RecordData};
+use super::{validate_domain, wire, zone_file, zonefile, Zone};
+use crate::config::{self, DnsConfig};
+
+pub const DNSSEC_DIR: &str = "/etc/supercp/dnssec";
+
+/// ECDSA P-256 with SHA-256
+const ALGORITHM: u8 = 13;
+const DIGEST_SHA256: u8 = 2;
+const NSEC3_SHA1: u8 = 1;
+
+const FLAGS_KSK: u16 = 257;
+const FLAGS_ZSK: u16 = 256;
+
+/// Signatures start this far in the past, for validators with slow clocks.
+const INCEPTION_OFFSET: u64 = 3600;
+
+const RESIGN_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Key {
+    /// `ksk` or `zsk`
+    role: String,
+    /// The private scalar, base64
+    private_key: String,
+    created_at: u64,
+}
+
+impl Key {
+    fn generate(role: &str) -> Self {
+        let key = SigningKey::random(&mut rand_core::OsRng);
+        Key { role: role.to_string(), private_key: BASE64.encode(&key.to_bytes()), created_at: now() }
+    }
+
+    fn is_ksk(&self) -> bool {
+        self.role == "ksk"
+    }
+
+    fn flags(&self) -> u16 {
+        if self.is_ksk() {
+            FLAGS_KSK
+        } else {
+            FLAGS_ZSK
+        }
+    }
+
+    fn signing_key(&self) -> Result<SigningKey, String> {
+        let bytes = BASE64.decode(self.private_key.as_bytes()).map_err(|e| format!("Invalid {} private key: {}", self.role, e))?;
+        SigningKey::from_slice(&bytes).map_err(|e| format!("Invalid {} private key: {}", self.role, e))
+    }
+
+    /// The public key as DNSKEY wants it: the curve point's coordinates without the SEC1 prefix.
+    fn public_key(&self) -> Result<Vec<u8>, String> {
+        let point = self.signing_key()?.verifying_key().to_encoded_point(false);
+        Ok(point.as_bytes()[1..].to_vec())
+    }
+
+    fn dnskey_rdata(&self) -> Result<Vec<u8>, String> {
+        let mut rdata = self.flags().to_be_bytes().to_vec();
+        rdata.push(3);
+        rdata.push(ALGORITHM);
+        rdata.extend(self.public_key()?);
+        Ok(rdata)
+    }
+
+    fn dnskey(&self) -> Result<String, String> {
+        Ok(format!("{} 3 {} {}", self.flags(), ALGORITHM, BASE64.encode(&self.public_key()?)))
+    }
+}
+
+/// The key tag of a DNSKEY (RFC 4034 appendix B).
+fn key_tag(rdata: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (i, byte) in rdata.iter().enumerate() {
+        sum += if i % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+    }
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct KeyStore {
+    keys: Vec<Key>,
+    /// Serial of the zone the signed file was made from
+    #[serde(default)]
+    signed_serial: Option<u32>,
+    /// When the earliest signature in the signed file expires
+    #[serde(default)]
+    expires_at: Option<u64>,
+}
+
+fn key_path(domain: &str) -> String {
+    format!("{}/{}.json", DNSSEC_DIR, domain)
+}
+
+pub fn signed_path(dns: &DnsConfig, domain: &str) -> String {
+    format!("{}.signed", zone_file(dns, domain))
+}
+
+impl KeyStore {
+    fn load(domain: &str) -> Result<Option<Self>, String> {
+        match fs::read_to_string(key_path(domain)) {
+            Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| format!("Invalid DNSSEC key file for {}: {}", domain, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn save(&self, domain: &str) -> Result<(), String> {
+        fs::create_dir_all(DNSSEC_DIR).map_err(|e| e.to_string())?;
+        fs::set_permissions(DNSSEC_DIR, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+
+        let path = key_path(domain);
+        let pending = format!("{}.new", path);
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&pending, content).map_err(|e| e.to_string())?;
+        fs::set_permissions(&pending, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+        fs::rename(&pending, &path).map_err(|e| e.to_string())
+    }
+
+    /// Whether the signatures are due to be renewed.
+    fn expiring(&self, dns: &DnsConfig) -> bool {
+        self.expires_at.is_none_or(|expires| expires <= now() + dns.dnssec.resign_before as u64)
+    }
+
+    /// Whether the signed file is missing, stale or close to expiring.
+    fn needs_signing(&self, domain: &str, serial: u32, dns: &DnsConfig) -> bool {
+        self.signed_serial != Some(serial) || self.expiring(dns) || fs::metadata(signed_path(dns, domain)).is_err()
+    }
+}
+
+pub fn is_enabled(domain: &str) -> bool {
+    fs::metadata(key_path(domain)).is_ok()
+}
+
+/// Removes the signed file, so the unsigned zone is served again.
+fn remove_signed(dns: &DnsConfig, domain: &str) -> std::io::Result<()> {
+    match fs::remove_file(signed_path(dns, domain)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes a zone's keys and signed file along with the zone.
+pub fn remove(dns: &DnsConfig, domain: &str) -> std::io::Result<()> {
+    remove_signed(dns, domain)?;
+    match fs::remove_file(key_path(domain)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The records of one owner name and type, in canonical wire form.
+struct RrSet {
+    /// Owner relative to the zone, as written in the zone file
+    name: String,
+    ttl: u32,
+    rdatas: Vec<Vec<u8>>,
+}
+
+fn add_rdata(rrsets: &mut BTreeMap<(String, u16), RrSet>, domain: &str, name: &str, rtype: u16, ttl: u32, rdata: Vec<u8>) {
+    let owner = super::record::qualify(name, domain);
+    // The first TTL seen wins for the whole RRset, as it does when BIND loads the zone
+    let rrset = rrsets.entry((owner, rtype)).or_insert_with(|| RrSet { name: name.to_string(), ttl, rdatas: Vec::new() });
+    if !rrset.rdatas.contains(&rdata) {
+        rrset.rdatas.push(rdata);
+    }
+}
+
+fn timestamp(secs: u32) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0).map(|t| t.format("%Y%m%d%H%M%S").to_string()).unwrap_or_default()
+}
+
+/// Validity window of a signature, as RRSIG inception and expiration times.
+struct Validity {
+    inception: u32,
+    expiration: u32,
+}
+
+/// The RRSIG labels field: the owner's labels, not counting a leading wildcard.
+fn label_count(owner: &str) -> Result<u8, String> {
+    let labels = wire::labels(owner)?;
+    Ok((labels.len() - usize::from(labels.first().is_some_and(|l| l == b"*"))) as u8)
+}
+
+/// The data an RRSIG signs (RFC 4034 section 3.1.8.1): the RRSIG fields before the
+/// signature, then the RRset in canonical order.
+fn signed_data(owner: &str, rtype: u16, rrset: &RrSet, tag: u16, apex: &str, validity: &Validity) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&rtype.to_be_bytes());
+    data.push(ALGORITHM);
+    data.push(label_count(owner)?);
+    data.extend_from_slice(&rrset.ttl.to_be_bytes());
+    data.extend_from_slice(&validity.expiration.to_be_bytes());
+    data.extend_from_slice(&validity.inception.to_be_bytes());
+    data.extend_from_slice(&tag.to_be_bytes());
+    data.extend(wire::name(apex)?);
+
+    let owner_wire = wire::name(owner)?;
+    let mut rdatas = rrset.rdatas.clone();
+    rdatas.sort();
+    for rdata in rdatas {
+        data.extend_from_slice(&owner_wire);
+        data.extend_from_slice(&rtype.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&rrset.ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+    Ok(data)
+}
+
+/// Signs an RRset, returning the RRSIG's data in zone-file syntax.
+fn rrsig(owner: &str, rtype: u16, rrset: &RrSet, key: &Key, apex: &str, validity: &Validity) -> Result<String, String> {
+    let tag = key_tag(&key.dnskey_rdata()?);
+    let data = signed_data(owner, rtype, rrset, tag, apex, validity)?;
+    let signature: Signature = key.signing_key()?.sign(&data);
+    Ok(format!(
+        "{} {} {} {} {} {} {} {} {}",
+        wire::type_name(rtype),
+        ALGORITHM,
+        label_count(owner)?,
+        rrset.ttl,
+        timestamp(validity.expiration),
+        timestamp(validity.inception),
+        tag,
+        apex,
+        BASE64.encode(&signature.to_bytes())
+    ))
+}
+
+/// The hashed owner name of an NSEC3 record (RFC 5155 section 5). Zones are signed without
+/// salt or extra iterations, as RFC 9276 recommends.
+fn nsec3_hash(owner: &str, salt: &[u8], iterations: u16) -> Result<Vec<u8>, String> {
+    let mut hash = Sha1::new().chain_update(wire::name(owner)?).chain_update(salt).finalize().to_vec();
+    for _ in 0..iterations {
+        hash = Sha1::new().chain_update(&hash).chain_update(salt).finalize().to_vec();
+    }
+    Ok(hash)
+}
+
+fn other(name: &str, ttl: u32, rtype: &str, rdata: String) -> Record {
+    Record { name: name.to_string(), ttl, data: RecordData::Other { rtype: rtype.to_string(), rdata } }
+}
+
+/// Builds the DNSSEC records for a zone: DNSKEY, NSEC3PARAM, NSEC3 and every RRSIG.
+fn sign_records(zone: &Zone, keys: &[Key], validity: &Validity) -> Result<Vec<Record>, String> {
+    let domain = zone.domain.as_str();
+    let apex = format!("{}.", domain);
+    let mut rrsets: BTreeMap<(String, u16), RrSet> = BTreeMap::new();
+    let mut output = Vec::new();
+
+    let mut soa = wire::name(&zone.soa.mname)?;
+    soa.extend(wire::name(&zone.soa.rname)?);
+    for value in [zone.soa.serial, zone.soa.refresh, zone.soa.retry, zone.soa.expire, zone.soa.minimum] {
+        soa.extend_from_slice(&value.to_be_bytes());
+    }
+    add_rdata(&mut rrsets, domain, "@", wire::TYPE_SOA, zone.ttl, soa);
+
+    for record in &zone.records {
+        let data = record.data.qualified(domain);
+        let rtype = wire::type_code(data.type_name()).ok_or_else(|| format!("{} records can't be signed", data.type_name()))?;
+        add_rdata(&mut rrsets, domain, &record.name, rtype, record.ttl, wire::rdata(&data)?);
+    }
+
+    for key in keys {
+        add_rdata(&mut rrsets, domain, "@", wire::TYPE_DNSKEY, zone.ttl, key.dnskey_rdata()?);
+        output.push(other("@", zone.ttl, "DNSKEY", key.dnskey()?));
+    }
+    // SHA-1, no flags, no extra iterations, no salt
+    add_rdata(&mut rrsets, domain, "@", wire::TYPE_NSEC3PARAM, 0, vec![NSEC3_SHA1, 0, 0, 0, 0]);
+    output.push(other("@", 0, "NSEC3PARAM", "1 0 0 -".to_string()));
+
+    // Below a delegation only glue remains, which is neither signed nor covered by NSEC3
+    let delegations: BTreeSet<String> =
+        rrsets.keys().filter(|(owner, rtype)| *rtype == wire::TYPE_NS && *owner != apex).map(|(owner, _)| owner.clone()).collect();
+    let occluded = |owner: &str| delegations.iter().any(|d| owner.ends_with(&format!(".{}", d)));
+
+    let mut names: BTreeMap<String, BTreeSet<u16>> = BTreeMap::new();
+    for (owner, rtype) in rrsets.keys() {
+        if !occluded(owner) {
+            names.entry(owner.clone()).or_default().insert(*rtype);
+        }
+    }
+    // Empty non-terminals, such as _tcp for _sip._tcp, need NSEC3 records too
+    for owner in names.keys().cloned().collect::<Vec<_>>() {
+        let mut current = owner.as_str();
+        while let Some((_, parent)) = current.split_once('.') {
+            if parent.len() <= apex.len() {
+                break;
+            }
+            names.entry(parent.to_string()).or_default();
+            current = parent;
+        }
+    }
+
+    let nsec3_ttl = zone.soa.minimum.min(zone.ttl);
+    let mut hashed: Vec<(Vec<u8>, BTreeSet<u16>)> = Vec::new();
+    for (owner, types) in &names {
+        let mut types = types.clone();
+        let insecure_delegation = delegations.contains(owner) && !types.contains(&wire::TYPE_DS);
+        if !types.is_empty() && !insecure_delegation {
+            types.insert(wire::TYPE_RRSIG);
+        }
+        hashed.push((nsec3_hash(owner, &[], 0)?, types));
+    }
+    hashed.sort();
+
+    for (i, (hash, types)) in hashed.iter().enumerate() {
+        let next = &hashed[(i + 1) % hashed.len()].0;
+        let types: Vec<u16> = types.iter().copied().collect();
+
+        let mut rdata = vec![NSEC3_SHA1, 0, 0, 0, 0, next.len() as u8];
+        rdata.extend_from_slice(next);
+        rdata.extend(wire::type_bitmap(&types));
+
+        let label = BASE32HEX_NOPAD.encode(hash).to_ascii_lowercase();
+        add_rdata(&mut rrsets, domain, &label, wire::TYPE_NSEC3, nsec3_ttl, rdata);
+
+        let mut presentation = format!("1 0 0 - {}", BASE32HEX_NOPAD.encode(next).to_ascii_lowercase());
+        for rtype in types {
+            presentation.push(' ');
+            presentation.push_str(&wire::type_name(rtype));
+        }
+        output.push(other(&label, nsec3_ttl, "NSEC3", presentation));
+    }
+
+    for ((owner, rtype), rrset) in &rrsets {
+        if occluded(owner) || (delegations.contains(owner) && *rtype != wire::TYPE_DS) {
+            continue;
+        }
+        // The key-signing key signs the DNSKEY RRset, the zone-signing key everything else
+        let ksk = *rtype == wire::TYPE_DNSKEY;
+        for key in keys.iter().filter(|k| k.is_ksk() == ksk) {
+            output.push(other(&rrset.name, rrset.ttl, "RRSIG", rrsig(owner, *rtype, rrset, key, &apex, validity)?));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Returns the zone to serve: the signed zone when DNSSEC is enabled, signing it first if
+/// the signed file is missing, stale or close to expiring, or else the zone as it is.
+pub async fn prepare(zone: Zone, dns: &DnsConfig) -> Result<Zone, Box<dyn std::error::Error>> {
+    let domain = zone.domain.clone();
+    let Some(mut keys) = KeyStore::load(&domain)? else {
+        remove_signed(dns, &domain)?;
+        return Ok(zone);
+    };
+    let path = signed_path(dns, &domain);
+
+    if !keys.needs_signing(&domain, zone.serial, dns) {
+        let content = fs::read_to_string(&path)?;
+        let records = zonefile::parse(&content, &domain).map_err(|e| format!("Failed to parse {}: {}", path, e))?.records;
+        return Ok(Zone { path, content, records, signed: true, ..zone });
+    }
+
+    let start = now().saturating_sub(INCEPTION_OFFSET);
+    let validity = Validity { inception: start as u32, expiration: (start + dns.dnssec.signature_validity as u64) as u32 };
+    let signatures = sign_records(&zone, &keys.keys, &validity)?;
+
+    let content = format!("{}{}", zone.content, super::render_body(&signatures));
+    super::write_checked(&domain, &path, &content).await?;
+
+    keys.signed_serial = Some(zone.serial);
+    keys.expires_at = Some(validity.expiration as u64);
+    keys.save(&domain)?;
+
+    let mut records = zone.records.clone();
+    records.extend(signatures);
+    Ok(Zone { path, content, records, signed: true, ..zone })
+}
+
+/// DS records for the registrar, one per key-signing key.
+fn ds_records(domain: &str, keys: &KeyStore) -> Result<Value, String> {
+    let apex = format!("{}.", domain);
+    let mut ds = Vec::new();
+    let mut dnskeys = Vec::new();
+
+    for key in &keys.keys {
+        let rdata = key.dnskey_rdata()?;
+        let tag = key_tag(&rdata);
+        dnskeys.push(json!({
+            "role": key.role,
+            "key_tag": tag,
+            "flags": key.flags(),
+            "algorithm": ALGORITHM,
+            "public_key": BASE64.encode(&key.public_key()?),
+            "created_at": key.created_at,
+            "record": format!("{} IN DNSKEY {}", apex, key.dnskey()?),
+        }));
+
+        if key.is_ksk() {
+            let mut digest_input = wire::name(&apex)?;
+            digest_input.extend(rdata);
+            let digest = HEXUPPER.encode(&Sha256::digest(&digest_input));
+            ds.push(json!({
+                "key_tag": tag,
+                "algorithm": ALGORITHM,
+                "digest_type": DIGEST_SHA256,
+                "digest": digest,
+                "record": format!("{} IN DS {} {} {} {}", apex, tag, ALGORITHM, DIGEST_SHA256, digest),
+            }));
+        }
+    }
+
+    Ok(json!({
+        "domain": domain,
+        "signed_serial": keys.signed_serial,
+        "expires_at": keys.expires_at,
+        "keys": dnskeys,
+        "ds": ds,
+    }))
+}
+
+/// DNSSEC state of a zone for `get_dns_zone`.
+pub fn status(domain: &str) -> Value {
+    match KeyStore::load(domain) {
+        Ok(Some(keys)) => json!({ "enabled": true, "signed_serial": keys.signed_serial, "expires_at": keys.expires_at }),
+        Ok(None) => json!({ "enabled": false }),
+        Err(e) => json!({ "enabled": true, "error": e }),
+    }
+}
+
+fn domain_param(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    Ok(domain)
+}
+
+/// Generates keys for a zone if it has none and signs it. Returns the DS records to hand to
+/// the registrar.
+pub async fn enable_dnssec(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+    if fs::metadata(zone_file(&config::load()?.dns, &domain)).is_err() {
+        return Err(format!("No DNS zone for {}", domain).into());
+    }
+
+    if KeyStore::load(&domain)?.is_none() {
+        let keys = KeyStore { keys: vec![Key::generate("ksk"), Key::generate("zsk")], ..Default::default() };
+        keys.save(&domain)?;
+    }
+    // A new serial, so secondaries pick up the keys and signatures
+    super::reissue_zone(&domain).await?;
+
+    let keys = KeyStore::load(&domain)?.ok_or("DNSSEC keys disappeared while signing")?;
+    Ok(ds_records(&domain, &keys)?)
+}
+
+/// Stops signing a zone and deletes its keys. The DS records at the registrar must be
+/// removed first, or validating resolvers will treat the zone as bogus.
+pub async fn disable_dnssec(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+    if KeyStore::load(&domain)?.is_none() {
+        return Ok(format!("DNSSEC is not enabled for {}", domain));
+    }
+
+    fs::remove_file(key_path(&domain))?;
+    super::reissue_zone(&domain).await?;
+    Ok(format!("DNSSEC disabled for {}", domain))
+}
+
+pub async fn get_dnssec_ds(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+    let keys = KeyStore::load(&domain)?.ok_or_else(|| format!("DNSSEC is not enabled for {}", domain))?;
+    Ok(ds_records(&domain, &keys)?)
+}
+
+/// Re-signs every signed zone whose signatures are close to expiring.
+async fn resign_due() -> Result<(), Box<dyn std::error::Error>> {
+    let dns = config::load()?.dns;
+    let entries = match fs::read_dir(DNSSEC_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(domain) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()) else {
+            continue;
+        };
+        if validate_domain(&domain).is_err() {
+            continue;
+        }
+
+        let due = match KeyStore::load(&domain) {
+            Ok(Some(keys)) => keys.expiring(&dns),
+            Ok(None) => false,
+            Err(e) => {
+                eprintln!("DNSSEC: {}", e);
+                false
+            }
+        };
+        if due {
+            match super::reissue_zone(&domain).await {
+                Ok(zone) => println!("DNSSEC: re-signed {} (serial {})", domain, zone.serial),
+                Err(e) => eprintln!("DNSSEC: failed to re-sign {}: {}", domain, e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Background task that keeps signatures fresh; spawned once at startup.
+pub async fn resign_loop() {
+    let mut interval = tokio::time::interval(RESIGN_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = resign_due().await {
+            eprintln!("DNSSEC: re-signing check failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+
+    /// The ECDSA P-256 key from RFC 6605 section 6.1.
+    fn rfc6605_key() -> Key {
+        Key { role: "ksk".to_string(), private_key: "GU6SnQ/Ou+xC5RumuIUIuJZteXT2z0O/ok1s38Et6mQ=".to_string(), created_at: 0 }
+    }
+
+    fn unix(timestamp: &str) -> u32 {
+        chrono::NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S").unwrap().and_utc().timestamp() as u32
+    }
+
+    #[test]
+    fn computes_the_key_tag_and_ds_of_a_known_key() {
+        let key = rfc6605_key();
+        assert_eq!(key.dnskey().unwrap(), "257 3 13 GojIhhXUN/u4v54ZQqGSnyhWJwaubCvTmeexv7bR6edbkrSqQpF64cYbcB7wNcP+e+MAnLr+Wi9xMWyQLc8NAA==");
+        assert_eq!(key_tag(&key.dnskey_rdata().unwrap()), 55648);
+
+        let keys = KeyStore { keys: vec![key], ..Default::default() };
+        let ds = ds_records("example.net", &keys).unwrap();
+        assert_eq!(ds["ds"][0]["key_tag"], 55648);
+        assert_eq!(ds["ds"][0]["digest"], "B4C8C1FE2E7477127B27115656AD6256F424625BF5C1E2770CE6D6E37DF61D17");
+        assert_eq!(
+            ds["ds"][0]["record"],
+            "example.net. IN DS 55648 13 2 B4C8C1FE2E7477127B27115656AD6256F424625BF5C1E2770CE6D6E37DF61D17"
+        );
+    }
+
+    #[test]
+    fn hashes_owner_names_like_rfc5155() {
+        // Appendix A: salt aabbccdd, 12 extra iterations
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        let cases = [
+            ("example.", "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"),
+            ("a.example.", "35mthgpgcu1qg68fab165klnsnk3dpvl"),
+            ("ns1.example.", "2t7b4g4vsa5smi47k61mv5bv1a22bojr"),
+            ("*.w.example.", "r53bq7cc2uvmubfu5ocmm6pers9tk9en"),
+            ("x.y.w.example.", "2vptu5timamqttgl4luu9kg21e0aor3s"),
+            ("xx.example.", "t644ebqk9bibcna874givr6joj62mlhv"),
+        ];
+        for (owner, expected) in cases {
+            let hash = nsec3_hash(owner, &salt, 12).unwrap();
+            assert_eq!(BASE32HEX_NOPAD.encode(&hash).to_ascii_lowercase(), expected, "{}", owner);
+        }
+
+        // Owner names are hashed in canonical (lower) case
+        assert_eq!(nsec3_hash("A.Example.", &salt, 12).unwrap(), nsec3_hash("a.example.", &salt, 12).unwrap());
+        assert_eq!(nsec3_hash("example.", &[], 0).unwrap(), Sha1::digest(wire::name("example.").unwrap()).to_vec());
+    }
+
+    #[test]
+    fn signs_what_rfc6605_signed() {
+        // The RRSIG over www.example.net A from RFC 6605 section 6.1 has to verify against the
+        // data we would sign for the same RRset
+        let rrset = RrSet { name: "www".to_string(), ttl: 3600, rdatas: vec![vec![192, 0, 2, 1]] };
+        let validity = Validity { inception: unix("20100812100439"), expiration: unix("20100909100439") };
+        let a = wire::type_code("A").unwrap();
+        let data = signed_data("www.example.net.", a, &rrset, 55648, "example.net.", &validity).unwrap();
+
+        let signature = BASE64.decode(b"qx6wLYqmh+l9oCKTN6qIc+bw6ya+KJ8oMz0YP107epXAyGmt+3SNruPFKG7tZoLBLlUzGGus7ZwmwWep666VCw==").unwrap();
+        let signature = Signature::from_slice(&signature).unwrap();
+        let verifying_key = *rfc6605_key().signing_key().unwrap().verifying_key();
+        assert!(verifying_key.verify(&data, &signature).is_ok());
+
+        let rrsig = rrsig("www.example.net.", a, &rrset, &rfc6605_key(), "example.net.", &validity).unwrap();
+        assert!(rrsig.starts_with("A 13 3 3600 20100909100439 20100812100439 55648 example.net. "), "{}", rrsig);
+    }
+
+    #[test]
+    fn wildcards_do_not_count_as_labels() {
+        assert_eq!(label_count("www.example.net.").unwrap(), 3);
+        assert_eq!(label_count("*.example.net.").unwrap(), 2);
+        assert_eq!(label_count("example.net.").unwrap(), 2);
+    }
+}
@@ -0,0 +1,575 @@
+//! Authoritative DNS zones for hosted domains.
+//!
+//! Zones are rendered from the panel's records into `<dns.zone_dir>/<domain>.zone`, with
+//! the SOA and apex NS records taken from the daemon config and a date-based serial that
+//! only moves when the zone's content changes, then signed when [`dnssec`] is enabled for
+//! the domain and published through the configured [`backend`]. Zones on disk are read back
+//! with [`zonefile`], so the panel can compare them with its own records.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tokio::sync::Mutex;
+
+use crate::config::{self, DnsConfig};
+use crate::exec::{Command, CommandError};
+
+pub mod backend;
+pub mod dnssec;
+pub mod query;
+pub mod record;
+pub mod template;
+mod wire;
+pub mod zonefile;
+
+use record::{Record, RecordData};
+use zonefile::Soa;
+
+/// A rendered zone as handed to the nameserver backend.
+#[derive(Clone)]
+pub struct Zone {
+    pub domain: String,
+    /// Path of the zone file
+    pub path: String,
+    /// The zone file's content
+    pub content: String,
+    pub serial: u32,
+    pub soa: Soa,
+    pub ttl: u32,
+    /// Every record apart from the SOA, including the apex NS records and, for a signed
+    /// zone, its DNSSEC records
+    pub records: Vec<Record>,
+    /// Whether the zone is DNSSEC-signed
+    pub signed: bool,
+}
+
+pub fn validate_domain(domain: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Also keeps the domain safe to use as a file name
+    if !record::is_hostname(domain, false) || !domain.contains('.') {
+        return Err(format!("Invalid domain '{}'", domain).into());
+    }
+    Ok(())
+}
+
+pub fn zone_file(dns: &DnsConfig, domain: &str) -> String {
+    format!("{}/{}.zone", dns.zone_dir.trim_end_matches('/'), domain)
+}
+
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Converts the configured hostmaster into SOA RNAME form: `admin@example.com` becomes
+/// `admin.example.com.`, with dots in the local part escaped.
+fn soa_mailbox(hostmaster: &str) -> String {
+    match hostmaster.split_once('@') {
+        Some((local, domain)) => format!("{}.{}", local.replace('.', "\\."), absolute(domain)),
+        None => absolute(hostmaster),
+    }
+}
+
+/// Reads the serial from a zone written by [`render_zone`].
+fn read_serial(content: &str) -> Option<u32> {
+    let soa = content.lines().find(|l| l.split_whitespace().any(|t| t == "SOA"))?;
+    let after = soa.split_once('(')?.1;
+    after.split_whitespace().next()?.parse().ok()
+}
+
+/// The next serial in `YYYYMMDDnn` form: today's first, or one more than the previous serial
+/// when that was already today's (or somehow ahead of it).
+fn next_serial(previous: Option<u32>) -> u32 {
+    let today: u32 = chrono::Utc::now().format("%Y%m%d").to_string().parse().unwrap_or(0);
+    let first = today.saturating_mul(100);
+    match previous {
+        Some(previous) if previous >= first => previous + 1,
+        _ => first,
+    }
+}
+
+/// Everything after the SOA line, which is what decides whether the zone changed.
+fn zone_body(content: &str) -> String {
+    let mut lines = content.lines().skip_while(|l| !l.split_whitespace().any(|t| t == "SOA"));
+    lines.next();
+    lines.map(|l| format!("{}\n", l)).collect()
+}
+
+fn render_body(records: &[Record]) -> String {
+    let mut body = String::new();
+    for record in records {
+        body.push_str(&record.render());
+        body.push('\n');
+    }
+    body
+}
+
+/// Zones are only rendered once the SOA and NS data they take from the config is set.
+fn check_configured(dns: &DnsConfig) -> Result<(), String> {
+    if dns.nameservers.is_empty() {
+        return Err("No nameservers configured for DNS zones (dns.nameservers)".to_string());
+    }
+    if dns.hostmaster.trim().is_empty() {
+        return Err("No hostmaster configured for DNS zones (dns.hostmaster)".to_string());
+    }
+    Ok(())
+}
+
+fn soa(serial: u32, dns: &DnsConfig) -> Soa {
+    Soa {
+        mname: absolute(&dns.nameservers[0]),
+        rname: soa_mailbox(&dns.hostmaster),
+        serial,
+        refresh: dns.refresh,
+        retry: dns.retry,
+        expire: dns.expire,
+        minimum: dns.minimum,
+    }
+}
+
+fn render_zone(domain: &str, soa: &Soa, body: &str, dns: &DnsConfig) -> String {
+    let mut content = format!("$ORIGIN {}\n", absolute(domain));
+    content.push_str(&format!("$TTL {}\n", dns.default_ttl));
+    content.push_str(&format!(
+        "@ IN SOA {} {} ( {} {} {} {} {} )\n",
+        soa.mname, soa.rname, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+    ));
+    content.push_str(body);
+    content
+}
+
+/// Runs `named-checkzone` on a rendered zone when BIND's tools are installed.
+async fn check_zone(domain: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match Command::new("named-checkzone").arg("-q").arg(domain).arg(path).run().await {
+        Ok(_) => Ok(()),
+        Err(CommandError::Spawn { source, .. }) if source.kind() == ErrorKind::NotFound => Ok(()),
+        Err(CommandError::Exit { .. }) => {
+            // -q keeps quiet on failure too, so run again for the reason
+            let output = Command::new("named-checkzone").arg(domain).arg(path).output().await?;
+            Err(format!("Zone check failed for {}: {}", domain, output.stdout.trim()).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes a zone file, checking the new zone before it replaces the live one.
+async fn write_checked(domain: &str, path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let pending_path = format!("{}.new", path);
+    fs::write(&pending_path, content)?;
+    if let Err(e) = check_zone(domain, &pending_path).await {
+        let _ = fs::remove_file(&pending_path);
+        return Err(e);
+    }
+    fs::rename(&pending_path, path)?;
+    Ok(())
+}
+
+/// Serializes zone writes, so a background re-sign or a record edit can't interleave with an update.
+static ZONE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Signs the zone if DNSSEC is enabled for it and publishes it through the configured backend.
+async fn deploy(zone: Zone, dns: &DnsConfig) -> Result<Zone, Box<dyn std::error::Error>> {
+    let backend = backend::from_config(dns)?;
+    let domain = zone.domain.clone();
+    let zone = dnssec::prepare(zone, dns).await?;
+    backend
+        .publish(&zone)
+        .await
+        .map_err(|e| format!("Zone for {} was written but could not be published via {}: {}", domain, backend.name(), e))?;
+    Ok(zone)
+}
+
+/// Rewrites a zone that is already on disk with the next serial, then signs and publishes
+/// it. Used when its DNSSEC state changes or its signatures need renewing.
+async fn reissue_zone(domain: &str) -> Result<Zone, Box<dyn std::error::Error>> {
+    let dns = config::load()?.dns;
+    check_configured(&dns)?;
+    let _guard = ZONE_LOCK.lock().await;
+
+    let zone_path = zone_file(&dns, domain);
+    let existing = match fs::read_to_string(&zone_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(format!("No DNS zone for {}", domain).into()),
+        Err(e) => return Err(e.into()),
+    };
+    let parsed = zonefile::parse(&existing, domain).map_err(|e| format!("Failed to parse {}: {}", zone_path, e))?;
+
+    let soa = soa(next_serial(parsed.soa.map(|soa| soa.serial)), &dns);
+    let content = render_zone(domain, &soa, &zone_body(&existing), &dns);
+    write_checked(domain, &zone_path, &content).await?;
+
+    let zone = Zone {
+        domain: domain.to_string(),
+        path: zone_path,
+        content,
+        serial: soa.serial,
+        soa,
+        ttl: dns.default_ttl,
+        records: parsed.records,
+        signed: false,
+    };
+    deploy(zone, &dns).await
+}
+
+pub async fn update_dns_zone(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let dns = config::load()?.dns;
+    update_zone(params, &dns).await
+}
+
+async fn update_zone(params: &Value, dns: &DnsConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let _guard = ZONE_LOCK.lock().await;
+    write_zone(params, dns).await
+}
+
+/// [`update_zone`] for callers that already hold [`ZONE_LOCK`].
+async fn write_zone(params: &Value, dns: &DnsConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    let records = params["records"].as_array().ok_or("Missing records")?;
+    validate_domain(&domain)?;
+
+    check_configured(dns)?;
+
+    let mut parsed: Vec<Record> = dns
+        .nameservers
+        .iter()
+        .map(|ns| Record { name: "@".to_string(), ttl: dns.default_ttl, data: RecordData::Ns(absolute(ns)) })
+        .collect();
+    for (index, value) in records.iter().enumerate() {
+        let record = Record::from_json(value, &domain, dns.default_ttl).map_err(|e| format!("Record {}: {}", index + 1, e))?;
+        // Apex NS records come from the config
+        if record.name == "@" && matches!(&record.data, RecordData::Ns(ns) if dns.nameservers.iter().any(|c| absolute(c) == *ns))
+        {
+            continue;
+        }
+        parsed.push(record);
+    }
+    record::validate_set(&parsed)?;
+
+    let zone_path = zone_file(dns, &domain);
+    let existing = fs::read_to_string(&zone_path).ok();
+    let body = render_body(&parsed);
+
+    let unchanged = existing.as_deref().map(zone_body).as_deref() == Some(body.as_str());
+    let previous_serial = existing.as_deref().and_then(read_serial);
+
+    let (content, soa) = match existing {
+        // Still published below, in case an earlier publish failed
+        Some(content) if unchanged => (content, soa(previous_serial.unwrap_or(0), dns)),
+        _ => {
+            let soa = soa(next_serial(previous_serial), dns);
+            let content = render_zone(&domain, &soa, &body, dns);
+            write_checked(&domain, &zone_path, &content).await?;
+            (content, soa)
+        }
+    };
+
+    let zone = Zone {
+        domain: domain.clone(),
+        path: zone_path,
+        content,
+        serial: soa.serial,
+        soa,
+        ttl: dns.default_ttl,
+        records: parsed,
+        signed: false,
+    };
+    let zone = deploy(zone, dns).await?;
+
+    if unchanged {
+        Ok(format!("DNS zone for {} is unchanged", domain))
+    } else {
+        Ok(format!("DNS zone updated for {} (serial {})", domain, zone.serial))
+    }
+}
+
+pub async fn delete_dns_zone(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let dns = config::load()?.dns;
+    delete_zone(params, &dns).await
+}
+
+async fn delete_zone(params: &Value, dns: &DnsConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    let zone_path = zone_file(dns, &domain);
+
+    // Stop serving the zone before its file goes away
+    let backend = backend::from_config(dns)?;
+    backend
+        .remove(&domain)
+        .await
+        .map_err(|e| format!("Failed to remove zone {} from {}: {}", domain, backend.name(), e))?;
+
+    let _guard = ZONE_LOCK.lock().await;
+    dnssec::remove(dns, &domain)?;
+    if Path::new(&zone_path).exists() {
+        fs::remove_file(&zone_path)?;
+    }
+
+    Ok(format!("DNS zone deleted for {}", domain))
+}
+
+/// The hosted zone a name belongs to: the deepest zone on disk that is the name or one of
+/// its parents.
+pub fn zone_for(name: &str) -> Option<String> {
+    let dns = config::load().ok()?.dns;
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let mut candidate = name.as_str();
+    loop {
+        if validate_domain(candidate).is_ok() && Path::new(&zone_file(&dns, candidate)).exists() {
+            return Some(candidate.to_string());
+        }
+        candidate = candidate.split_once('.')?.1;
+    }
+}
+
+/// Changes some records of a zone on disk and redeploys it, keeping the rest as they are.
+pub async fn edit_records(domain: &str, edit: impl FnOnce(&mut Vec<Value>)) -> Result<String, Box<dyn std::error::Error>> {
+    let dns = config::load()?.dns;
+    edit_zone(domain, &dns, false, edit).await
+}
+
+/// [`edit_records`] with the config passed in. With `create`, a domain without a zone starts
+/// from an empty one instead of failing.
+pub(super) async fn edit_zone(
+    domain: &str,
+    dns: &DnsConfig,
+    create: bool,
+    edit: impl FnOnce(&mut Vec<Value>),
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Held from the read to the write, so concurrent edits can't drop each other's records
+    let _guard = ZONE_LOCK.lock().await;
+    let zone_path = zone_file(dns, domain);
+    let mut records: Vec<Value> = match fs::read_to_string(&zone_path) {
+        Ok(content) => {
+            let zone = zonefile::parse(&content, domain).map_err(|e| format!("Failed to parse {}: {}", zone_path, e))?;
+            zone.records.iter().map(|r| r.to_json()).collect()
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound && create => Vec::new(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(format!("No DNS zone for {}", domain).into()),
+        Err(e) => return Err(e.into()),
+    };
+
+    edit(&mut records);
+    write_zone(&json!({ "domain": domain, "records": records }), dns).await
+}
+
+/// Returns the zone as deployed on disk, parsed back into records.
+pub async fn get_dns_zone(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    let zone_path = zone_file(&config::load()?.dns, &domain);
+
+    let content = match fs::read_to_string(&zone_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(format!("No DNS zone for {}", domain).into()),
+        Err(e) => return Err(e.into()),
+    };
+    let zone = zonefile::parse(&content, &domain).map_err(|e| format!("Failed to parse {}: {}", zone_path, e))?;
+
+    Ok(json!({
+        "domain": domain,
+        "path": zone_path,
+        "serial": zone.soa.as_ref().map(|soa| soa.serial),
+        "ttl": zone.default_ttl,
+        "soa": zone.soa,
+        "records": zone.records.iter().map(|r| r.to_json()).collect::<Vec<_>>(),
+        "dnssec": dnssec::status(&domain),
+    }))
+}
+
+/// Lists the zones on disk. A zone that fails to parse is listed with its error rather than
+/// failing the whole list.
+pub async fn list_dns_zones(_params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut zones = Vec::new();
+    let entries = match fs::read_dir(config::load()?.dns.zone_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(json!(zones)),
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("zone") {
+            continue;
+        }
+        let Some(domain) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()) else {
+            continue;
+        };
+        if validate_domain(&domain).is_err() {
+            continue;
+        }
+
+        let modified_at = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        let parsed = fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|c| zonefile::parse(&c, &domain));
+
+        zones.push(match parsed {
+            Ok(zone) => json!({
+                "domain": domain,
+                "serial": zone.soa.map(|soa| soa.serial),
+                "records": zone.records.len(),
+                "dnssec": dnssec::is_enabled(&domain),
+                "modified_at": modified_at,
+            }),
+            Err(e) => json!({
+                "domain": domain,
+                "modified_at": modified_at,
+                "error": e,
+            }),
+        });
+    }
+
+    zones.sort_by(|a, b| a["domain"].as_str().cmp(&b["domain"].as_str()));
+    Ok(json!(zones))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &Path) -> DnsConfig {
+        DnsConfig {
+            nameservers: vec!["ns1.example.net".to_string(), "ns2.example.net.".to_string()],
+            hostmaster: "hostmaster@example.net".to_string(),
+            zone_dir: dir.join("zones").display().to_string(),
+            backend: "fake".to_string(),
+            fake_dir: dir.join("published").display().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn update(domain: &str, records: Value) -> Value {
+        json!({ "domain": domain, "records": records })
+    }
+
+    #[tokio::test]
+    async fn serial_moves_only_when_the_zone_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dns = config(dir.path());
+        let path = zone_file(&dns, "serial.example");
+        let published = dir.path().join("published/serial.example.zone");
+        let records = json!([{ "name": "www", "type": "A", "value": "192.0.2.1" }]);
+
+        update_zone(&update("serial.example", records.clone()), &dns).await.unwrap();
+        let first = fs::read_to_string(&path).unwrap();
+        let first_serial = read_serial(&first).unwrap();
+        assert_eq!(first_serial, next_serial(None));
+        assert_eq!(fs::read_to_string(&published).unwrap(), first);
+
+        let message = update_zone(&update("serial.example", records), &dns).await.unwrap();
+        assert_eq!(message, "DNS zone for serial.example is unchanged");
+        assert_eq!(fs::read_to_string(&path).unwrap(), first);
+
+        let changed = json!([{ "name": "www", "type": "A", "value": "192.0.2.2" }]);
+        update_zone(&update("serial.example", changed), &dns).await.unwrap();
+        let second = fs::read_to_string(&path).unwrap();
+        assert_eq!(read_serial(&second), Some(first_serial + 1));
+        assert!(second.contains("www 3600 IN A 192.0.2.2\n"));
+        assert_eq!(fs::read_to_string(&published).unwrap(), second);
+    }
+
+    #[tokio::test]
+    async fn apex_nameservers_come_from_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let dns = config(dir.path());
+        let records = json!([
+            { "name": "@", "type": "NS", "value": "ns1.example.net" },
+            { "name": "@", "type": "NS", "value": "ns.other.example" },
+            { "name": "dev", "type": "NS", "value": "ns.dev.example" },
+        ]);
+
+        update_zone(&update("ns.example", records), &dns).await.unwrap();
+        let zone = zonefile::parse(&fs::read_to_string(zone_file(&dns, "ns.example")).unwrap(), "ns.example").unwrap();
+
+        let soa = zone.soa.unwrap();
+        assert_eq!(soa.mname, "ns1.example.net.");
+        assert_eq!(soa.rname, "hostmaster.example.net.");
+        let ns: Vec<(String, String)> = zone
+            .records
+            .iter()
+            .filter_map(|r| match &r.data {
+                RecordData::Ns(target) => Some((r.name.clone(), target.clone())),
+                _ => None,
+            })
+            .collect();
+        let pair = |name: &str, target: &str| (name.to_string(), target.to_string());
+        assert_eq!(
+            ns,
+            [pair("@", "ns1.example.net."), pair("@", "ns2.example.net."), pair("@", "ns.other.example."), pair("dev", "ns.dev.example.")]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_zones_are_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let dns = config(dir.path());
+
+        let cases = [
+            update("bad.example", json!([{ "name": "@", "type": "CNAME", "value": "other.example" }])),
+            update("bad.example", json!([{ "name": "www", "type": "A", "value": "192.0.2.1" }, { "name": "www", "type": "CNAME", "value": "other.example" }])),
+            update("bad.example", json!([{ "name": "www", "type": "A", "value": "not an address" }])),
+            update("bad.example", json!([{ "name": "www.other.example.", "type": "A", "value": "192.0.2.1" }])),
+            update("not_a_domain", json!([])),
+        ];
+        for params in cases {
+            assert!(update_zone(&params, &dns).await.is_err(), "{} was accepted", params);
+        }
+        assert!(!Path::new(&zone_file(&dns, "bad.example")).exists());
+
+        // Nothing is rendered until the SOA and NS data is configured
+        for unconfigured in [DnsConfig { nameservers: Vec::new(), ..config(dir.path()) }, DnsConfig { hostmaster: String::new(), ..config(dir.path()) }] {
+            assert!(update_zone(&update("bad.example", json!([])), &unconfigured).await.is_err());
+        }
+        assert!(!Path::new(&zone_file(&dns, "bad.example")).exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_edits_keep_each_others_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let dns = std::sync::Arc::new(config(dir.path()));
+        update_zone(&update("edits.example", json!([])), &dns).await.unwrap();
+
+        let edits: Vec<_> = (1..=16)
+            .map(|i| {
+                let dns = std::sync::Arc::clone(&dns);
+                let record = json!({ "name": format!("host{}", i), "type": "A", "value": format!("192.0.2.{}", i) });
+                tokio::spawn(async move { edit_zone("edits.example", &dns, false, |records| records.push(record)).await.map_err(|e| e.to_string()) })
+            })
+            .collect();
+        for edit in edits {
+            edit.await.unwrap().unwrap();
+        }
+
+        let zone = zonefile::parse(&fs::read_to_string(zone_file(&dns, "edits.example")).unwrap(), "edits.example").unwrap();
+        let mut hosts: Vec<String> = zone.records.iter().filter(|r| r.name.starts_with("host")).map(|r| r.name.clone()).collect();
+        hosts.sort();
+        let mut expected: Vec<String> = (1..=16).map(|i| format!("host{}", i)).collect();
+        expected.sort();
+        assert_eq!(hosts, expected);
+        assert_eq!(zone.soa.unwrap().serial, next_serial(None) + 16);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_zone_unpublishes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let dns = config(dir.path());
+        let published = dir.path().join("published/gone.example.zone");
+
+        update_zone(&update("gone.example", json!([])), &dns).await.unwrap();
+        assert!(published.exists());
+
+        delete_zone(&json!({ "domain": "gone.example" }), &dns).await.unwrap();
+        assert!(!published.exists());
+        assert!(!Path::new(&zone_file(&dns, "gone.example")).exists());
+
+        // Deleting again is not an error
+        delete_zone(&json!({ "domain": "gone.example" }), &dns).await.unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! A minimal DNS client: asks one server directly for TXT records, which is all checking
+//! record propagation needs.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use super::wire;
+
+const TYPE_TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves `server` (`host` or `host:port`) to an address, port 53 by default.
+async fn server_address(server: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    let target = if server.contains(':') { server.to_string() } else { format!("{}:53", server.trim_end_matches('.')) };
+    let mut addresses = tokio::net::lookup_host(target).await.map_err(|e| format!("Can't resolve nameserver {}: {}", server, e))?;
+    addresses.next().ok_or_else(|| format!("Can't resolve nameserver {}", server))
+}
+
+/// Skips a possibly compressed name, returning the offset after it.
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, String> {
+    loop {
+        let length = *message.get(offset).ok_or("Truncated DNS response")? as usize;
+        match length {
+            0 => return Ok(offset + 1),
+            l if l & 0xc0 == 0xc0 => return Ok(offset + 2),
+            l => offset += l + 1,
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Result<u16, String> {
+    message.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(|| "Truncated DNS response".to_string())
+}
+
+/// The TXT records `server` has for `name` (absolute), each with its strings joined.
+pub async fn txt(server: &str, name: &str) -> Result<Vec<String>, String> {
+    let address = server_address(server).await?;
+    let id: u16 = rand_core::RngCore::next_u32(&mut rand_core::OsRng) as u16;
+
+    let mut query = Vec::new();
+    query.extend_from_slice(&id.to_be_bytes());
+    // A plain query without recursion: the server is expected to be authoritative
+    query.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    query.extend(wire::name(name)?);
+    query.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    let bind = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    socket.send_to(&query, address).await.map_err(|e| format!("Failed to query {}: {}", server, e))?;
+
+    let mut message = vec![0u8; 4096];
+    let length = loop {
+        let (length, from) = tokio::time::timeout(QUERY_TIMEOUT, socket.recv_from(&mut message))
+            .await
+            .map_err(|_| format!("Timed out querying {}", server))?
+            .map_err(|e| format!("Failed to query {}: {}", server, e))?;
+        if from == address && length >= 12 && message[..2] == id.to_be_bytes() {
+            break length;
+        }
+    };
+    let message = &message[..length];
+
+    let rcode = message[3] & 0x0f;
+    // NXDOMAIN just means the record isn't there yet
+    if rcode == 3 {
+        return Ok(Vec::new());
+    }
+    if rcode != 0 {
+        return Err(format!("{} answered with error code {}", server, rcode));
+    }
+
+    let questions = read_u16(message, 4)?;
+    let answers = read_u16(message, 6)?;
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(message, offset)? + 4;
+    }
+
+    let mut values = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(message, offset)?;
+        let rtype = read_u16(message, offset)?;
+        let rdlength = read_u16(message, offset + 8)? as usize;
+        offset += 10;
+        let rdata = message.get(offset..offset + rdlength).ok_or("Truncated DNS response")?;
+        offset += rdlength;
+
+        if rtype != TYPE_TXT {
+            continue;
+        }
+        let mut text = Vec::new();
+        let mut position = 0;
+        while position < rdata.len() {
+            let length = rdata[position] as usize;
+            text.extend_from_slice(rdata.get(position + 1..position + 1 + length).ok_or("Truncated DNS response")?);
+            position += length + 1;
+        }
+        values.push(String::from_utf8_lossy(&text).into_owned());
+    }
+    Ok(values)
+}
@@ -0,0 +1,498 @@
+//! Resource records: validation of panel input and rendering in zone-file syntax.
+
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Panel limits on TTLs: one minute to one week.
+const MIN_TTL: u32 = 60;
+const MAX_TTL: u32 = 604_800;
+
+/// TXT data longer than this won't fit in a reasonable DNS response.
+const MAX_TXT_LENGTH: usize = 4000;
+
+/// A single character-string in TXT data is at most 255 bytes.
+const TXT_CHUNK: usize = 255;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Mx { priority: u16, exchange: String },
+    Txt(String),
+    Ns(String),
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    Caa { flags: u8, tag: String, value: String },
+    /// A type the panel doesn't manage, kept as read from a zone file
+    Other { rtype: String, rdata: String },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    /// Owner name relative to the zone origin, `@` for the apex
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+fn is_label(label: &str, allow_underscore: bool) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || (allow_underscore && c == '_'))
+}
+
+/// Checks a domain name without the trailing dot. Underscores are allowed for service and
+/// policy names such as `_dmarc` or `_sip._tcp`.
+pub fn is_hostname(name: &str, allow_underscore: bool) -> bool {
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(|l| is_label(l, allow_underscore))
+}
+
+/// Normalizes an owner name to the form used in the zone: relative to `origin`, or `@`.
+pub fn owner_name(name: &str, origin: &str) -> Result<String, String> {
+    let name = name.trim().to_ascii_lowercase();
+    let origin = origin.to_ascii_lowercase();
+    let bare = name.trim_end_matches('.');
+
+    if bare.is_empty() || bare == "@" || bare == origin {
+        return Ok("@".to_string());
+    }
+    let relative = match bare.strip_suffix(&format!(".{}", origin)) {
+        Some(prefix) => prefix,
+        None if name.ends_with('.') => return Err(format!("Name {} is outside the zone {}", name, origin)),
+        None => bare,
+    };
+
+    // A leading `*` label makes a wildcard
+    let rest = if relative == "*" { None } else { Some(relative.strip_prefix("*.").unwrap_or(relative)) };
+    if rest.is_some_and(|rest| !is_hostname(rest, true)) {
+        return Err(format!("Invalid record name '{}'", relative));
+    }
+
+    Ok(relative.to_string())
+}
+
+/// Turns a zone-relative name (`@`, `www`) into an absolute one; absolute names pass through.
+pub fn qualify(name: &str, origin: &str) -> String {
+    if name == "@" {
+        format!("{}.", origin)
+    } else if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.{}.", name, origin)
+    }
+}
+
+/// Normalizes a target host: `@` and single labels stay relative to the zone, anything with a
+/// dot is taken as fully qualified (panel users rarely write the trailing dot).
+fn target_name(value: &str, what: &str) -> Result<String, String> {
+    let value = value.trim().to_ascii_lowercase();
+    if value == "@" {
+        return Ok(value);
+    }
+
+    let bare = value.trim_end_matches('.');
+    if bare.parse::<Ipv4Addr>().is_ok() || bare.parse::<Ipv6Addr>().is_ok() {
+        return Err(format!("Invalid {} '{}': expected a host name, not an IP address", what, value));
+    }
+    if !is_hostname(bare, true) {
+        return Err(format!("Invalid {} '{}'", what, value));
+    }
+
+    Ok(if bare.contains('.') { format!("{}.", bare) } else { bare.to_string() })
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid {} '{}'", what, value))
+}
+
+/// Reads TXT input either as plain text or as one or more quoted strings (`"a" "b"`), which
+/// are joined the way resolvers join them.
+fn parse_txt(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if !value.starts_with('"') {
+        return Ok(value.to_string());
+    }
+
+    let mut text = String::new();
+    let mut chars = value.chars();
+    loop {
+        match chars.next() {
+            None => break,
+            Some(c) if c.is_whitespace() => continue,
+            Some('"') => loop {
+                match chars.next() {
+                    Some('\\') => text.push(chars.next().ok_or("Unterminated escape in TXT value")?),
+                    Some('"') => break,
+                    Some(c) => text.push(c),
+                    None => return Err("Unterminated quote in TXT value".to_string()),
+                }
+            },
+            Some(_) => return Err("Text between quoted strings in TXT value".to_string()),
+        }
+    }
+
+    Ok(text)
+}
+
+/// Splits TXT data into the character-strings it is published as: at most 255 bytes each,
+/// split on character boundaries.
+pub fn txt_chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for c in text.chars() {
+        if current.len() + c.len_utf8() > TXT_CHUNK {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Quotes one character-string for a zone file.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quotes TXT data as its character-strings.
+fn quote_txt(text: &str) -> String {
+    txt_chunks(text).iter().map(|chunk| quote(chunk)).collect::<Vec<_>>().join(" ")
+}
+
+impl RecordData {
+    pub fn type_name(&self) -> &str {
+        match self {
+            RecordData::A(_) => "A",
+            RecordData::Aaaa(_) => "AAAA",
+            RecordData::Cname(_) => "CNAME",
+            RecordData::Mx { .. } => "MX",
+            RecordData::Txt(_) => "TXT",
+            RecordData::Ns(_) => "NS",
+            RecordData::Srv { .. } => "SRV",
+            RecordData::Caa { .. } => "CAA",
+            RecordData::Other { rtype, .. } => rtype,
+        }
+    }
+
+    /// The same data with every target name made absolute, for backends that don't know the
+    /// zone origin.
+    pub fn qualified(&self, origin: &str) -> RecordData {
+        let q = |name: &String| if name == "." { name.clone() } else { qualify(name, origin) };
+        match self {
+            RecordData::Cname(target) => RecordData::Cname(q(target)),
+            RecordData::Ns(target) => RecordData::Ns(q(target)),
+            RecordData::Mx { priority, exchange } => RecordData::Mx { priority: *priority, exchange: q(exchange) },
+            RecordData::Srv { priority, weight, port, target } => {
+                RecordData::Srv { priority: *priority, weight: *weight, port: *port, target: q(target) }
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// The RDATA in zone-file syntax.
+    pub fn render(&self) -> String {
+        match self {
+            RecordData::A(addr) => addr.to_string(),
+            RecordData::Aaaa(addr) => addr.to_string(),
+            RecordData::Cname(target) | RecordData::Ns(target) => target.clone(),
+            RecordData::Mx { priority, exchange } => format!("{} {}", priority, exchange),
+            RecordData::Txt(text) => quote_txt(text),
+            RecordData::Srv { priority, weight, port, target } => format!("{} {} {} {}", priority, weight, port, target),
+            // The CAA value is a single string on the wire, never split like TXT data
+            RecordData::Caa { flags, tag, value } => format!("{} {} {}", flags, tag, quote(value)),
+            RecordData::Other { rdata, .. } => rdata.clone(),
+        }
+    }
+
+    /// Parses a record's value for the given type. `priority` is the panel's separate priority
+    /// column, used by MX and SRV when the value doesn't carry one.
+    pub fn parse(rtype: &str, value: &str, priority: Option<u64>) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Missing value".to_string());
+        }
+        if value.contains('\n') || value.contains('\r') {
+            return Err("Value must be a single line".to_string());
+        }
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let column_priority = || -> Result<u16, String> {
+            match priority {
+                Some(p) => u16::try_from(p).map_err(|_| format!("Priority {} is out of range (0-65535)", p)),
+                None => Ok(10),
+            }
+        };
+
+        match rtype.to_ascii_uppercase().as_str() {
+            "A" => value.parse().map(RecordData::A).map_err(|_| format!("Invalid IPv4 address '{}'", value)),
+            "AAAA" => value.parse().map(RecordData::Aaaa).map_err(|_| format!("Invalid IPv6 address '{}'", value)),
+            "CNAME" => Ok(RecordData::Cname(target_name(value, "CNAME target")?)),
+            "NS" => Ok(RecordData::Ns(target_name(value, "nameserver")?)),
+            "MX" => {
+                let (priority, exchange) = match fields.as_slice() {
+                    [exchange] => (column_priority()?, *exchange),
+                    [p, exchange] => (parse_number(p, "MX priority")?, *exchange),
+                    _ => return Err(format!("Invalid MX value '{}'", value)),
+                };
+                // "." is a null MX: the domain accepts no mail
+                let exchange = if exchange == "." { ".".to_string() } else { target_name(exchange, "mail exchanger")? };
+                Ok(RecordData::Mx { priority, exchange })
+            }
+            "TXT" => {
+                let text = parse_txt(value)?;
+                if text.len() > MAX_TXT_LENGTH {
+                    return Err(format!("TXT value is longer than {} bytes", MAX_TXT_LENGTH));
+                }
+                Ok(RecordData::Txt(text))
+            }
+            "SRV" => {
+                let (p, weight, port, target) = match fields.as_slice() {
+                    [weight, port, target] => (column_priority()?, *weight, *port, *target),
+                    [p, weight, port, target] => (parse_number(p, "SRV priority")?, *weight, *port, *target),
+                    _ => return Err(format!("SRV value '{}' must be '[priority] weight port target'", value)),
+                };
+                let target = if target == "." { ".".to_string() } else { target_name(target, "SRV target")? };
+                Ok(RecordData::Srv {
+                    priority: p,
+                    weight: parse_number(weight, "SRV weight")?,
+                    port: parse_number(port, "SRV port")?,
+                    target,
+                })
+            }
+            "CAA" => {
+                let (flags, rest) = match fields.first().map(|f| f.parse::<u8>()) {
+                    Some(Ok(flags)) => (flags, value.split_once(char::is_whitespace).map(|(_, r)| r.trim()).unwrap_or("")),
+                    _ => (0, value),
+                };
+                let (tag, tag_value) = rest.split_once(char::is_whitespace).ok_or_else(|| format!("CAA value '{}' must be '[flags] tag value'", value))?;
+                let tag = tag.to_ascii_lowercase();
+                if tag.is_empty() || tag.len() > 15 || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(format!("Invalid CAA tag '{}'", tag));
+                }
+                let tag_value = parse_txt(tag_value)?;
+                if tag_value.len() > TXT_CHUNK {
+                    return Err(format!("CAA value is {} bytes long, the limit is {}", tag_value.len(), TXT_CHUNK));
+                }
+                if tag == "iodef" && !(tag_value.starts_with("mailto:") || tag_value.starts_with("https://") || tag_value.starts_with("http://")) {
+                    return Err("CAA iodef value must be a mailto: or http(s) URL".to_string());
+                }
+                Ok(RecordData::Caa { flags, tag, value: tag_value })
+            }
+            other => Err(format!("Unsupported record type '{}'", other)),
+        }
+    }
+}
+
+impl Record {
+    /// Builds a record from a panel request object (`name`, `type`, `value`, `ttl`, `priority`).
+    pub fn from_json(record: &Value, origin: &str, default_ttl: u32) -> Result<Self, String> {
+        let rtype = record["type"].as_str().ok_or("Missing type")?;
+        let name = owner_name(record["name"].as_str().unwrap_or("@"), origin)?;
+        let value = record["value"].as_str().ok_or("Missing value")?;
+
+        let ttl = match record["ttl"].as_u64() {
+            Some(ttl) if (MIN_TTL as u64..=MAX_TTL as u64).contains(&ttl) => ttl as u32,
+            Some(ttl) => return Err(format!("TTL {} is out of range ({}-{})", ttl, MIN_TTL, MAX_TTL)),
+            None => default_ttl,
+        };
+
+        let data = RecordData::parse(rtype, value, record["priority"].as_u64())?;
+        if let RecordData::Srv { .. } = data {
+            // SRV owners are _service._proto[.name]
+            let labels: Vec<&str> = name.split('.').collect();
+            if labels.len() < 2 || !labels[0].starts_with('_') || !labels[1].starts_with('_') {
+                return Err(format!("SRV name '{}' must start with _service._proto", name));
+            }
+        }
+
+        Ok(Record { name, ttl, data })
+    }
+
+    /// The record in the panel's request form, which [`Record::from_json`] reads back. MX and
+    /// SRV priorities go in their own field, and TXT values are returned unquoted.
+    pub fn to_json(&self) -> Value {
+        let (value, priority) = match &self.data {
+            RecordData::Mx { priority, exchange } => (exchange.clone(), Some(*priority)),
+            RecordData::Srv { priority, weight, port, target } => (format!("{} {} {}", weight, port, target), Some(*priority)),
+            RecordData::Txt(text) => (text.clone(), None),
+            other => (other.render(), None),
+        };
+
+        json!({
+            "name": self.name,
+            "type": self.data.type_name(),
+            "value": value,
+            "ttl": self.ttl,
+            "priority": priority,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        format!("{} {} IN {} {}", self.name, self.ttl, self.data.type_name(), self.data.render())
+    }
+}
+
+/// Checks rules that span records: no exact duplicates, and a CNAME must be the only record
+/// at its name and can't sit at the zone apex.
+pub fn validate_set(records: &[Record]) -> Result<(), String> {
+    for (i, record) in records.iter().enumerate() {
+        if records[..i].iter().any(|r| r.name == record.name && r.data == record.data) {
+            return Err(format!("Duplicate {} record for {}", record.data.type_name(), record.name));
+        }
+
+        if let RecordData::Cname(_) = record.data {
+            if record.name == "@" {
+                return Err("A CNAME record can't be used at the zone apex".to_string());
+            }
+            if let Some(other) = records.iter().find(|r| r.name == record.name && !std::ptr::eq(*r, record)) {
+                return Err(format!(
+                    "{} has a CNAME record, so it can't have other records ({} found)",
+                    record.name,
+                    other.data.type_name()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, data: RecordData) -> Record {
+        Record { name: name.to_string(), ttl: 3600, data }
+    }
+
+    #[test]
+    fn parses_record_values() {
+        let cases: &[(&str, &str, Option<u64>, RecordData)] = &[
+            ("A", "192.0.2.10", None, RecordData::A("192.0.2.10".parse().unwrap())),
+            ("aaaa", "2001:db8::1", None, RecordData::Aaaa("2001:db8::1".parse().unwrap())),
+            ("CNAME", "Www.Example.com", None, RecordData::Cname("www.example.com.".to_string())),
+            ("CNAME", "web", None, RecordData::Cname("web".to_string())),
+            ("NS", "ns1.example.net.", None, RecordData::Ns("ns1.example.net.".to_string())),
+            ("MX", "mail.example.com", None, RecordData::Mx { priority: 10, exchange: "mail.example.com.".to_string() }),
+            ("MX", "mail.example.com", Some(5), RecordData::Mx { priority: 5, exchange: "mail.example.com.".to_string() }),
+            ("MX", "20 @", Some(5), RecordData::Mx { priority: 20, exchange: "@".to_string() }),
+            ("MX", ".", Some(0), RecordData::Mx { priority: 0, exchange: ".".to_string() }),
+            ("TXT", "v=spf1 mx -all", None, RecordData::Txt("v=spf1 mx -all".to_string())),
+            ("TXT", r#""v=DKIM1; " "p=abc\"d""#, None, RecordData::Txt("v=DKIM1; p=abc\"d".to_string())),
+            (
+                "SRV",
+                "5 5060 sip.example.com",
+                Some(10),
+                RecordData::Srv { priority: 10, weight: 5, port: 5060, target: "sip.example.com.".to_string() },
+            ),
+            (
+                "SRV",
+                "0 5 443 .",
+                None,
+                RecordData::Srv { priority: 0, weight: 5, port: 443, target: ".".to_string() },
+            ),
+            (
+                "CAA",
+                "0 issue \"letsencrypt.org\"",
+                None,
+                RecordData::Caa { flags: 0, tag: "issue".to_string(), value: "letsencrypt.org".to_string() },
+            ),
+            (
+                "CAA",
+                "Iodef mailto:security@example.com",
+                None,
+                RecordData::Caa { flags: 0, tag: "iodef".to_string(), value: "mailto:security@example.com".to_string() },
+            ),
+        ];
+
+        for (rtype, value, priority, expected) in cases {
+            assert_eq!(RecordData::parse(rtype, value, *priority).as_ref(), Ok(expected), "{} {}", rtype, value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_record_values() {
+        let cases: &[(&str, &str, Option<u64>)] = &[
+            ("A", "", None),
+            ("A", "192.0.2.300", None),
+            ("A", "2001:db8::1", None),
+            ("AAAA", "192.0.2.1", None),
+            ("CNAME", "192.0.2.1", None),
+            ("CNAME", "-bad-.example.com", None),
+            ("MX", "mail.example.com", Some(70000)),
+            ("MX", "10 20 mail.example.com", None),
+            ("TXT", "\"unterminated", None),
+            ("TXT", "\"a\" b", None),
+            ("TXT", "line\nbreak", None),
+            ("SRV", "5060 sip.example.com", None),
+            ("SRV", "0 5 99999 sip.example.com", None),
+            ("CAA", "0 issue", None),
+            ("CAA", "0 iodef \"ftp://example.com\"", None),
+            ("PTR", "host.example.com", None),
+        ];
+
+        for (rtype, value, priority) in cases {
+            assert!(RecordData::parse(rtype, value, *priority).is_err(), "{} {:?} was accepted", rtype, value);
+        }
+    }
+
+    #[test]
+    fn caa_values_stay_one_string() {
+        let value = format!("https://{}.example/report", "a".repeat(300));
+        let caa = RecordData::Caa { flags: 0, tag: "iodef".to_string(), value: value.clone() };
+        assert_eq!(caa.render(), format!("0 iodef \"{}\"", value));
+
+        let quoted = RecordData::Caa { flags: 0, tag: "issue".to_string(), value: r#"ca.example; note="a\b""#.to_string() };
+        assert_eq!(quoted.render(), r#"0 issue "ca.example; note=\"a\\b\"""#);
+
+        let at_limit = format!("0 issue \"{}\"", "a".repeat(255));
+        assert_eq!(RecordData::parse("CAA", &at_limit, None).unwrap().render(), at_limit);
+        assert!(RecordData::parse("CAA", &format!("0 issue \"{}\"", "a".repeat(256)), None).is_err());
+    }
+
+    #[test]
+    fn validates_record_sets() {
+        let a = || RecordData::A("192.0.2.10".parse().unwrap());
+        let cname = || RecordData::Cname("example.net.".to_string());
+        let txt = || RecordData::Txt("hello".to_string());
+
+        let cases: Vec<(&str, Vec<Record>, bool)> = vec![
+            ("distinct records", vec![record("@", a()), record("www", cname()), record("@", txt())], true),
+            ("CNAME at the apex", vec![record("@", cname())], false),
+            ("CNAME next to an A record", vec![record("www", a()), record("www", cname())], false),
+            ("CNAME next to a TXT record", vec![record("www", cname()), record("www", txt())], false),
+            ("two CNAMEs at one name", vec![record("www", cname()), record("www", RecordData::Cname("other.net.".to_string()))], false),
+            ("duplicate records", vec![record("www", a()), record("www", a())], false),
+            ("same data at other names", vec![record("www", a()), record("ftp", a())], true),
+        ];
+
+        for (what, records, valid) in cases {
+            assert_eq!(validate_set(&records).is_ok(), valid, "{}", what);
+        }
+    }
+
+    #[test]
+    fn normalizes_owner_names() {
+        let cases = [
+            ("@", Ok("@")),
+            ("", Ok("@")),
+            ("example.com.", Ok("@")),
+            ("WWW", Ok("www")),
+            ("www.example.com.", Ok("www")),
+            ("www.example.com", Ok("www")),
+            ("*", Ok("*")),
+            ("*.dev", Ok("*.dev")),
+            ("_dmarc", Ok("_dmarc")),
+            ("www.example.org.", Err(())),
+            ("bad name", Err(())),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(owner_name(name, "example.com").as_deref().map_err(|_| ()), expected, "{}", name);
+        }
+    }
+}
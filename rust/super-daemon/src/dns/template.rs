@@ -0,0 +1,143 @@
+//! Zone templates: record sets with `{{VARIABLE}}` placeholders that give a domain its usual
+//! records in one step.
+//!
+//! Templates come from `dns.templates` in the daemon config, next to a built-in `default`.
+//! The variables are `{{DOMAIN}}`, `{{IPV4}}`, `{{IPV6}}` and `{{MAIL_HOST}}`, plus any passed
+//! in the request's `variables`. A record that uses a variable without a value is left out,
+//! so a server without IPv6 simply gets no AAAA records.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use super::record::owner_name;
+use super::validate_domain;
+use crate::config::{self, DnsConfig};
+
+pub const DEFAULT_TEMPLATE: &str = "default";
+
+fn builtin_default() -> Vec<Value> {
+    vec![
+        json!({ "name": "@", "type": "A", "value": "{{IPV4}}" }),
+        json!({ "name": "@", "type": "AAAA", "value": "{{IPV6}}" }),
+        json!({ "name": "www", "type": "CNAME", "value": "@" }),
+        json!({ "name": "mail", "type": "A", "value": "{{IPV4}}" }),
+        json!({ "name": "mail", "type": "AAAA", "value": "{{IPV6}}" }),
+        json!({ "name": "@", "type": "MX", "value": "{{MAIL_HOST}}", "priority": 10 }),
+        json!({ "name": "@", "type": "TXT", "value": "v=spf1 a mx ~all" }),
+    ]
+}
+
+fn variables(domain: &str, dns: &DnsConfig, overrides: &Value) -> Result<BTreeMap<String, String>, String> {
+    let mut variables = BTreeMap::new();
+    variables.insert("DOMAIN".to_string(), domain.to_string());
+    variables.insert("IPV4".to_string(), dns.server_ipv4.clone().unwrap_or_default());
+    variables.insert("IPV6".to_string(), dns.server_ipv6.clone().unwrap_or_default());
+    variables.insert("MAIL_HOST".to_string(), dns.mail_host.clone());
+
+    if let Some(overrides) = overrides.as_object() {
+        for (name, value) in overrides {
+            let value = value.as_str().ok_or_else(|| format!("Template variable {} must be a string", name))?;
+            variables.insert(name.to_ascii_uppercase(), value.to_string());
+        }
+    }
+
+    // The mail host is usually derived from the domain
+    if let Some(mail_host) = variables.get("MAIL_HOST").map(|m| m.replace("{{DOMAIN}}", domain)) {
+        variables.insert("MAIL_HOST".to_string(), mail_host);
+    }
+    Ok(variables)
+}
+
+/// Fills in the placeholders in `text`, or returns `None` when one of them has no value.
+fn substitute(text: &str, variables: &BTreeMap<String, String>) -> Result<Option<String>, String> {
+    let mut output = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| format!("Unclosed placeholder in '{}'", text))?;
+        let name = after[..end].trim();
+        match variables.get(name) {
+            Some(value) if value.is_empty() => return Ok(None),
+            Some(value) => output.push_str(value),
+            None => return Err(format!("Unknown template variable {{{{{}}}}}", name)),
+        }
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(Some(output))
+}
+
+/// The template's records for `domain`, in `update_dns_zone` form.
+pub fn render(template: &str, domain: &str, dns: &DnsConfig, overrides: &Value) -> Result<Vec<Value>, String> {
+    let records = match dns.templates.get(template) {
+        Some(records) => records.clone(),
+        None if template == DEFAULT_TEMPLATE => builtin_default(),
+        None => return Err(format!("Unknown DNS template '{}'", template)),
+    };
+    let variables = variables(domain, dns, overrides)?;
+
+    let mut rendered = Vec::new();
+    'records: for (index, record) in records.into_iter().enumerate() {
+        let mut record = record;
+        for field in ["name", "value"] {
+            let Some(text) = record[field].as_str() else {
+                continue;
+            };
+            match substitute(text, &variables).map_err(|e| format!("Template {} record {}: {}", template, index + 1, e))? {
+                Some(value) => record[field] = json!(value),
+                None => continue 'records,
+            }
+        }
+        rendered.push(record);
+    }
+
+    Ok(rendered)
+}
+
+/// Whether two records in request form would clash: the same name and type, or a CNAME
+/// next to anything else.
+fn conflicts(a: &Value, b: &Value, domain: &str) -> bool {
+    let name = |r: &Value| owner_name(r["name"].as_str().unwrap_or("@"), domain).ok();
+    let rtype = |r: &Value| r["type"].as_str().unwrap_or("").to_ascii_uppercase();
+
+    name(a).is_some() && name(a) == name(b) && (rtype(a) == rtype(b) || rtype(a) == "CNAME" || rtype(b) == "CNAME")
+}
+
+/// Applies a template to a domain's zone. By default the template only adds records the
+/// zone doesn't have yet; with `replace` the zone becomes exactly the template.
+pub async fn apply_dns_template(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    let template = params["template"].as_str().unwrap_or(DEFAULT_TEMPLATE);
+    let replace = params["replace"].as_bool().unwrap_or(false);
+
+    let dns = config::load()?.dns;
+    let generated = render(template, &domain, &dns, &params["variables"])?;
+
+    let mut added = 0;
+    let mut applied = Vec::new();
+    let message = super::edit_zone(&domain, &dns, true, |records| {
+        if replace {
+            records.clear();
+        }
+        for record in generated {
+            if !records.iter().any(|existing| conflicts(existing, &record, &domain)) {
+                records.push(record);
+                added += 1;
+            }
+        }
+        applied = records.clone();
+    })
+    .await?;
+
+    Ok(json!({
+        "domain": domain,
+        "template": template,
+        "added": added,
+        "message": message,
+        "records": applied,
+    }))
+}
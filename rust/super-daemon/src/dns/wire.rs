@@ -0,0 +1,144 @@
+//! DNS wire format, as far as signing needs it: names and RDATA in the canonical form of
+//! RFC 4034 section 6 (lowercase, uncompressed).
+
+use super::record::{txt_chunks, RecordData};
+
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_DS: u16 = 43;
+pub const TYPE_RRSIG: u16 = 46;
+pub const TYPE_DNSKEY: u16 = 48;
+pub const TYPE_NSEC3: u16 = 50;
+pub const TYPE_NSEC3PARAM: u16 = 51;
+
+const TYPES: &[(&str, u16)] = &[
+    ("A", 1),
+    ("NS", TYPE_NS),
+    ("CNAME", 5),
+    ("SOA", TYPE_SOA),
+    ("PTR", 12),
+    ("MX", 15),
+    ("TXT", 16),
+    ("AAAA", 28),
+    ("SRV", 33),
+    ("DS", TYPE_DS),
+    ("RRSIG", TYPE_RRSIG),
+    ("DNSKEY", TYPE_DNSKEY),
+    ("NSEC3", TYPE_NSEC3),
+    ("NSEC3PARAM", TYPE_NSEC3PARAM),
+    ("CAA", 257),
+];
+
+pub fn type_code(name: &str) -> Option<u16> {
+    TYPES.iter().find(|(n, _)| *n == name).map(|(_, code)| *code)
+}
+
+pub fn type_name(code: u16) -> String {
+    match TYPES.iter().find(|(_, c)| *c == code) {
+        Some((name, _)) => name.to_string(),
+        None => format!("TYPE{}", code),
+    }
+}
+
+/// Splits an absolute name in presentation form into its labels, undoing `\.` and `\DDD`
+/// escapes. The root name has no labels.
+pub fn labels(name: &str) -> Result<Vec<Vec<u8>>, String> {
+    if name == "." {
+        return Ok(Vec::new());
+    }
+    let relative = name.strip_suffix('.').ok_or_else(|| format!("Name {} is not absolute", name))?;
+
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut chars = relative.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => labels.push(std::mem::take(&mut label)),
+            '\\' => {
+                let digits: String = std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_digit())).take(3).collect();
+                if digits.is_empty() {
+                    let escaped = chars.next().ok_or_else(|| format!("Invalid name {}", name))?;
+                    label.extend(escaped.to_string().bytes());
+                } else {
+                    label.push(digits.parse().map_err(|_| format!("Invalid name {}", name))?);
+                }
+            }
+            c => label.extend(c.to_string().bytes()),
+        }
+    }
+    labels.push(label);
+
+    if labels.iter().any(|l| l.is_empty() || l.len() > 63) {
+        return Err(format!("Invalid name {}", name));
+    }
+    Ok(labels)
+}
+
+/// An absolute name in canonical wire form.
+pub fn name(name: &str) -> Result<Vec<u8>, String> {
+    let mut wire = Vec::new();
+    for label in labels(name)? {
+        wire.push(label.len() as u8);
+        wire.extend(label.iter().map(|b| b.to_ascii_lowercase()));
+    }
+    wire.push(0);
+    Ok(wire)
+}
+
+fn character_string(wire: &mut Vec<u8>, text: &[u8]) {
+    wire.push(text.len() as u8);
+    wire.extend_from_slice(text);
+}
+
+/// The RDATA of a record whose names have all been made absolute.
+pub fn rdata(data: &RecordData) -> Result<Vec<u8>, String> {
+    let mut wire = Vec::new();
+    match data {
+        RecordData::A(addr) => wire.extend_from_slice(&addr.octets()),
+        RecordData::Aaaa(addr) => wire.extend_from_slice(&addr.octets()),
+        RecordData::Cname(target) | RecordData::Ns(target) => wire.extend(name(target)?),
+        RecordData::Mx { priority, exchange } => {
+            wire.extend_from_slice(&priority.to_be_bytes());
+            wire.extend(name(exchange)?);
+        }
+        RecordData::Txt(text) => {
+            for chunk in txt_chunks(text) {
+                character_string(&mut wire, chunk.as_bytes());
+            }
+        }
+        RecordData::Srv { priority, weight, port, target } => {
+            wire.extend_from_slice(&priority.to_be_bytes());
+            wire.extend_from_slice(&weight.to_be_bytes());
+            wire.extend_from_slice(&port.to_be_bytes());
+            wire.extend(name(target)?);
+        }
+        RecordData::Caa { flags, tag, value } => {
+            wire.push(*flags);
+            character_string(&mut wire, tag.as_bytes());
+            wire.extend_from_slice(value.as_bytes());
+        }
+        RecordData::Other { rtype, .. } => return Err(format!("{} records can't be signed", rtype)),
+    }
+    Ok(wire)
+}
+
+/// An NSEC3 type bitmap (RFC 5155 section 3.2.1); `types` must be sorted.
+pub fn type_bitmap(types: &[u16]) -> Vec<u8> {
+    let mut wire = Vec::new();
+    let mut index = 0;
+    while index < types.len() {
+        let window = types[index] >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut length = 0;
+        while index < types.len() && types[index] >> 8 == window {
+            let low = (types[index] & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            length = low / 8 + 1;
+            index += 1;
+        }
+        wire.push(window as u8);
+        wire.push(length as u8);
+        wire.extend_from_slice(&bitmap[..length]);
+    }
+    wire
+}
@@ -0,0 +1,447 @@
+//! Reading zone files back into records.
+//!
+//! Accepts the master-file syntax BIND does for the zones the daemon writes as well as
+//! hand-edited ones: `$ORIGIN` and `$TTL`, comments, records split over lines with
+//! parentheses, omitted owners, TTLs and classes, and TTL units such as `1h`. `$INCLUDE` is
+//! not followed.
+
+use serde::Serialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::record::{owner_name, Record, RecordData};
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl Soa {
+    /// The SOA RDATA in zone-file syntax.
+    pub fn render(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {}",
+            self.mname, self.rname, self.serial, self.refresh, self.retry, self.expire, self.minimum
+        )
+    }
+}
+
+pub struct ZoneFile {
+    /// `$TTL` of the zone, if set
+    pub default_ttl: Option<u32>,
+    pub soa: Option<Soa>,
+    /// Every record apart from the SOA, with owners relative to the zone and names in the
+    /// record data fully qualified
+    pub records: Vec<Record>,
+}
+
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// One logical record or directive: a line, or several joined by parentheses.
+struct Entry {
+    line: usize,
+    /// The first token is an owner name, not a TTL, class or type
+    has_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// Reads a quoted string after its opening quote, decoding `\X` and `\DDD` escapes.
+fn quoted_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => {
+                let digits: String = std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_digit())).take(3).collect();
+                if digits.is_empty() {
+                    text.push(chars.next().ok_or("Unterminated escape")?);
+                } else {
+                    let byte: u8 = digits.parse().map_err(|_| format!("Invalid escape \\{}", digits))?;
+                    text.push(byte as char);
+                }
+            }
+            Some(c) => text.push(c),
+            None => return Err("Unterminated quoted string".to_string()),
+        }
+    }
+}
+
+fn tokenize(line: &str, depth: &mut usize, tokens: &mut Vec<Token>) -> Result<(), String> {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '(' => *depth += 1,
+            ')' => *depth = depth.checked_sub(1).ok_or("Unbalanced ')'")?,
+            '"' => tokens.push(Token { text: quoted_string(&mut chars)?, quoted: true }),
+            c if c.is_whitespace() => {}
+            c => {
+                let mut text = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || matches!(next, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    text.push(next);
+                    chars.next();
+                }
+                tokens.push(Token { text, quoted: false });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn entries(content: &str) -> Result<Vec<Entry>, String> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut depth = 0;
+
+    for (index, line) in content.lines().enumerate() {
+        if depth == 0 {
+            entries.push(Entry {
+                line: index + 1,
+                has_owner: line.starts_with(|c: char| !c.is_whitespace() && c != ';' && c != '('),
+                tokens: Vec::new(),
+            });
+        }
+        let entry = entries.last_mut().expect("an entry was just pushed");
+        tokenize(line, &mut depth, &mut entry.tokens).map_err(|e| format!("line {}: {}", index + 1, e))?;
+    }
+    if depth > 0 {
+        let line = entries.last().map(|e| e.line).unwrap_or(0);
+        return Err(format!("line {}: Unbalanced '('", line));
+    }
+
+    entries.retain(|e| !e.tokens.is_empty());
+    Ok(entries)
+}
+
+/// Parses a TTL in seconds or with BIND's units (`1h30m`, `2d`, `1W`).
+fn parse_ttl(text: &str) -> Option<u32> {
+    if let Ok(secs) = text.parse() {
+        return Some(secs);
+    }
+
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return None,
+        };
+        let value: u32 = std::mem::take(&mut number).parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+    }
+
+    number.is_empty().then_some(total)
+}
+
+/// Makes a name from the file absolute against the current origin, lowercased.
+fn absolute_name(name: &str, origin: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') {
+        name
+    } else {
+        format!("{}.{}", name, origin)
+    }
+}
+
+/// Like [`absolute_name`], but keeps the root name `.` used by null MX and SRV records.
+fn target(name: &str, origin: &str) -> String {
+    if name == "." {
+        name.to_string()
+    } else {
+        absolute_name(name, origin)
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str, what: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Invalid {} '{}'", what, text))
+}
+
+fn parse_soa(rdata: &[&Token], origin: &str) -> Result<Soa, String> {
+    let [mname, rname, serial, refresh, retry, expire, minimum] = rdata else {
+        return Err("SOA needs 7 fields".to_string());
+    };
+    let timer = |token: &Token, what: &str| parse_ttl(&token.text).ok_or_else(|| format!("Invalid SOA {} '{}'", what, token.text));
+
+    Ok(Soa {
+        mname: absolute_name(&mname.text, origin),
+        rname: absolute_name(&rname.text, origin),
+        serial: number(&serial.text, "SOA serial")?,
+        refresh: timer(refresh, "refresh")?,
+        retry: timer(retry, "retry")?,
+        expire: timer(expire, "expire")?,
+        minimum: timer(minimum, "minimum")?,
+    })
+}
+
+fn parse_rdata(rtype: &str, rdata: &[&Token], origin: &str) -> Result<RecordData, String> {
+    let texts: Vec<&str> = rdata.iter().map(|t| t.text.as_str()).collect();
+    let wrong_fields = || format!("Wrong number of fields for {}", rtype);
+
+    match rtype {
+        "A" => match texts.as_slice() {
+            [addr] => addr.parse::<Ipv4Addr>().map(RecordData::A).map_err(|_| format!("Invalid IPv4 address '{}'", addr)),
+            _ => Err(wrong_fields()),
+        },
+        "AAAA" => match texts.as_slice() {
+            [addr] => addr.parse::<Ipv6Addr>().map(RecordData::Aaaa).map_err(|_| format!("Invalid IPv6 address '{}'", addr)),
+            _ => Err(wrong_fields()),
+        },
+        "CNAME" => match texts.as_slice() {
+            [name] => Ok(RecordData::Cname(absolute_name(name, origin))),
+            _ => Err(wrong_fields()),
+        },
+        "NS" => match texts.as_slice() {
+            [name] => Ok(RecordData::Ns(absolute_name(name, origin))),
+            _ => Err(wrong_fields()),
+        },
+        "MX" => match texts.as_slice() {
+            [priority, exchange] => {
+                Ok(RecordData::Mx { priority: number(priority, "MX priority")?, exchange: target(exchange, origin) })
+            }
+            _ => Err(wrong_fields()),
+        },
+        // Character-strings are joined the way resolvers join them
+        "TXT" if !texts.is_empty() => Ok(RecordData::Txt(texts.concat())),
+        "SRV" => match texts.as_slice() {
+            [priority, weight, port, name] => Ok(RecordData::Srv {
+                priority: number(priority, "SRV priority")?,
+                weight: number(weight, "SRV weight")?,
+                port: number(port, "SRV port")?,
+                target: target(name, origin),
+            }),
+            _ => Err(wrong_fields()),
+        },
+        "CAA" => match texts.as_slice() {
+            [flags, tag, value] => Ok(RecordData::Caa {
+                flags: number(flags, "CAA flags")?,
+                tag: tag.to_ascii_lowercase(),
+                value: value.to_string(),
+            }),
+            _ => Err(wrong_fields()),
+        },
+        _ if texts.is_empty() => Err(format!("Missing data for {}", rtype)),
+        _ => {
+            let rdata = rdata
+                .iter()
+                .map(|t| if t.quoted { format!("\"{}\"", t.text.replace('\\', "\\\\").replace('"', "\\\"")) } else { t.text.clone() })
+                .collect::<Vec<_>>()
+                .join(" ");
+            Ok(RecordData::Other { rtype: rtype.to_string(), rdata })
+        }
+    }
+}
+
+fn is_class(text: &str) -> bool {
+    matches!(text.to_ascii_uppercase().as_str(), "IN" | "CH" | "HS")
+}
+
+/// Parses the zone file of `domain`.
+pub fn parse(content: &str, domain: &str) -> Result<ZoneFile, String> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let apex = format!("{}.", domain);
+    let mut origin = apex.clone();
+    let mut zone = ZoneFile { default_ttl: None, soa: None, records: Vec::new() };
+    let mut last_owner: Option<String> = None;
+    let mut last_ttl: Option<u32> = None;
+
+    for entry in entries(content)? {
+        let at = |e: String| format!("line {}: {}", entry.line, e);
+        let mut tokens = entry.tokens.iter().peekable();
+
+        if entry.has_owner && entry.tokens[0].text.starts_with('$') {
+            let directive = entry.tokens[0].text.to_ascii_uppercase();
+            let argument = entry.tokens.get(1).map(|t| t.text.as_str()).ok_or_else(|| at(format!("{} needs an argument", directive)))?;
+            match directive.as_str() {
+                "$ORIGIN" => origin = absolute_name(argument, &origin),
+                "$TTL" => zone.default_ttl = Some(parse_ttl(argument).ok_or_else(|| at(format!("Invalid $TTL '{}'", argument)))?),
+                "$INCLUDE" => return Err(at("$INCLUDE is not supported".to_string())),
+                other => return Err(at(format!("Unknown directive {}", other))),
+            }
+            continue;
+        }
+
+        let owner = if entry.has_owner {
+            let name = absolute_name(&tokens.next().expect("entries have tokens").text, &origin);
+            last_owner = Some(name.clone());
+            name
+        } else {
+            last_owner.clone().ok_or_else(|| at("Record without an owner name".to_string()))?
+        };
+
+        // TTL and class may come in either order, and both are optional
+        let mut ttl = None;
+        while let Some(token) = tokens.peek() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text).ok_or_else(|| at(format!("Invalid TTL '{}'", token.text)))?);
+            } else if is_class(&token.text) {
+                if !token.text.eq_ignore_ascii_case("IN") {
+                    return Err(at(format!("Unsupported class {}", token.text)));
+                }
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let rtype = tokens.next().ok_or_else(|| at("Missing record type".to_string()))?.text.to_ascii_uppercase();
+        let rdata: Vec<&Token> = tokens.collect();
+
+        if rtype == "SOA" {
+            if owner != apex {
+                return Err(at(format!("SOA for {} in the zone of {}", owner, apex)));
+            }
+            let soa = parse_soa(&rdata, &origin).map_err(at)?;
+            // Without $TTL, records that omit a TTL fall back to the SOA minimum
+            last_ttl = Some(ttl.unwrap_or(soa.minimum));
+            zone.soa = Some(soa);
+            continue;
+        }
+
+        let ttl = match ttl.or(zone.default_ttl).or(last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err(at("Record without a TTL and no $TTL set".to_string())),
+        };
+        last_ttl = Some(ttl);
+
+        let name = owner_name(&owner, &domain).map_err(at)?;
+        let data = parse_rdata(&rtype, &rdata, &origin).map_err(at)?;
+        zone.records.push(Record { name, ttl, data });
+    }
+
+    Ok(zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DnsConfig;
+    use crate::dns::{render_body, render_zone, soa};
+
+    #[test]
+    fn reads_back_rendered_zones() {
+        let dns = DnsConfig { nameservers: vec!["ns1.example.net".to_string()], hostmaster: "host.master@example.net".to_string(), ..Default::default() };
+        let records = vec![
+            Record { name: "@".to_string(), ttl: 3600, data: RecordData::Ns("ns1.example.net.".to_string()) },
+            Record { name: "@".to_string(), ttl: 300, data: RecordData::A("192.0.2.10".parse().unwrap()) },
+            Record { name: "@".to_string(), ttl: 3600, data: RecordData::Aaaa("2001:db8::10".parse().unwrap()) },
+            Record { name: "www".to_string(), ttl: 3600, data: RecordData::Cname("@".to_string()) },
+            Record { name: "ftp".to_string(), ttl: 3600, data: RecordData::Cname("www".to_string()) },
+            Record { name: "@".to_string(), ttl: 3600, data: RecordData::Mx { priority: 10, exchange: "mail".to_string() } },
+            Record { name: "nomail".to_string(), ttl: 3600, data: RecordData::Mx { priority: 0, exchange: ".".to_string() } },
+            Record { name: "@".to_string(), ttl: 3600, data: RecordData::Txt("v=spf1 mx -all".to_string()) },
+            Record { name: "long._domainkey".to_string(), ttl: 3600, data: RecordData::Txt(format!("v=DKIM1; p={}", "A".repeat(400))) },
+            Record { name: "quoted".to_string(), ttl: 3600, data: RecordData::Txt("say \"hi\" \\ bye".to_string()) },
+            Record {
+                name: "_sip._tcp".to_string(),
+                ttl: 3600,
+                data: RecordData::Srv { priority: 10, weight: 5, port: 5060, target: "sip.example.org.".to_string() },
+            },
+            Record {
+                name: "@".to_string(),
+                ttl: 3600,
+                data: RecordData::Caa { flags: 128, tag: "issue".to_string(), value: "letsencrypt.org".to_string() },
+            },
+            Record { name: "*.dev".to_string(), ttl: 60, data: RecordData::A("192.0.2.20".parse().unwrap()) },
+            Record {
+                name: "host".to_string(),
+                ttl: 3600,
+                data: RecordData::Other { rtype: "SSHFP".to_string(), rdata: "4 2 123456789abcdef".to_string() },
+            },
+        ];
+
+        let soa = soa(2026101901, &dns);
+        let content = render_zone("example.com", &soa, &render_body(&records), &dns);
+        let zone = parse(&content, "example.com").unwrap();
+
+        assert_eq!(zone.default_ttl, Some(dns.default_ttl));
+        assert_eq!(zone.soa, Some(soa));
+        assert_eq!(zone.soa.unwrap().rname, "host\\.master.example.net.");
+        let expected: Vec<Record> = records.iter().map(|r| Record { data: r.data.qualified("example.com"), ..r.clone() }).collect();
+        assert_eq!(zone.records, expected);
+    }
+
+    #[test]
+    fn reads_hand_edited_zones() {
+        let content = r#"
+$TTL 1h
+$ORIGIN Example.COM.
+@   IN  SOA ns1.example.net. admin.example.net. (
+        2024010101 ; serial
+        3h 15m 2w 1d )
+    IN  NS  ns1.example.net.
+    NS      ns2.example.net.
+www 300 IN A 192.0.2.1 ; the web server
+    IN 1d AAAA 2001:db8::1
+mail.example.com. MX 10 @
+$ORIGIN sub.example.com.
+api A 192.0.2.2
+txt TXT ( "one"
+          "two" )
+"#;
+        let zone = parse(content, "example.com").unwrap();
+
+        let soa = zone.soa.unwrap();
+        assert_eq!((soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum), (2024010101, 10_800, 900, 1_209_600, 86_400));
+        assert_eq!(zone.default_ttl, Some(3600));
+
+        let rendered: Vec<String> = zone.records.iter().map(Record::render).collect();
+        assert_eq!(
+            rendered,
+            [
+                "@ 3600 IN NS ns1.example.net.",
+                "@ 3600 IN NS ns2.example.net.",
+                "www 300 IN A 192.0.2.1",
+                "www 86400 IN AAAA 2001:db8::1",
+                "mail 3600 IN MX 10 example.com.",
+                "api.sub 3600 IN A 192.0.2.2",
+                "txt.sub 3600 IN TXT \"onetwo\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_broken_zones() {
+        let cases = [
+            "www A 192.0.2.1\n",
+            "$TTL 1h\n$INCLUDE other.zone\n",
+            "$TTL 1h\nwww IN A 192.0.2.300\n",
+            "$TTL 1h\nwww CH A 192.0.2.1\n",
+            "$TTL 1h\nwww MX mail\n",
+            "$TTL 1h\nother.org. SOA ns1 admin 1 2 3 4 5\n",
+            "$TTL 1h\ntxt TXT \"unterminated\n",
+            "$TTL 1h\nwww A ( 192.0.2.1\n",
+        ];
+
+        for content in cases {
+            assert!(parse(content, "example.com").is_err(), "{:?} was accepted", content);
+        }
+    }
+
+    #[test]
+    fn parses_ttl_units() {
+        let cases = [("3600", Some(3600)), ("1h30m", Some(5400)), ("2D", Some(172_800)), ("1w", Some(604_800)), ("10x", None), ("1h5", None)];
+        for (text, expected) in cases {
+            assert_eq!(parse_ttl(text), expected, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Non-blocking process execution shared by all handlers.
+//!
+//! Commands run on `tokio::process` with a timeout, captured stdout/stderr and a global
+//! concurrency limit, so a slow `mysqldump` or `certbot` never ties up the runtime's worker
+//! threads and other requests (even `ping`) keep being served.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Upper bound on external processes the daemon runs at once.
+const MAX_CONCURRENT_COMMANDS: usize = 16;
+
+/// Applied to every command unless the caller overrides it.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+static SLOTS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_COMMANDS);
+
+/// Waits for a free process slot. Held by callers that drive `std::process` pipelines on
+/// blocking threads themselves, so those count against the same limit.
+pub async fn slot() -> SemaphorePermit<'static> {
+    // The semaphore is never closed, so acquiring cannot fail
+    SLOTS.acquire().await.expect("command semaphore closed")
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Spawn { program: String, source: std::io::Error },
+    Timeout { program: String, after: Duration },
+    Exit { program: String, code: Option<i32>, stderr: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Spawn { program, source } => write!(f, "Failed to run {}: {}", program, source),
+            CommandError::Timeout { program, after } => write!(f, "{} timed out after {}s", program, after.as_secs()),
+            CommandError::Exit { program, code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "{} exited with status {}", program, code)?,
+                    None => write!(f, "{} was killed by a signal", program)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+pub struct Output {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    user: Option<(u32, u32)>,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    cancellable: bool,
+}
+
+impl Command {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Command {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_clear: false,
+            current_dir: None,
+            user: None,
+            stdin: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+            cancellable: false,
+        }
+    }
+
+    /// Shorthand for the daemon's usual `sudo -n <program>` invocation.
+    pub fn sudo(program: impl AsRef<OsStr>) -> Self {
+        Command::new("sudo").arg("-n").arg(program)
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|a| a.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env.push((key.as_ref().to_os_string(), value.as_ref().to_os_string()));
+        self
+    }
+
+    /// Starts the process with an empty environment instead of the daemon's.
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Runs the process with this uid and gid, dropping supplementary groups.
+    /// Only works when the daemon runs as root.
+    pub fn run_as(mut self, uid: u32, gid: u32) -> Self {
+        self.user = Some((uid, gid));
+        self
+    }
+
+    /// Feeds `input` to the process on stdin.
+    pub fn stdin(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// For commands whose runtime scales with user data (archiving, dumps) and must not be cut off.
+    pub fn no_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    /// Registers the process with the current job, so a handler holding a
+    /// [`jobs::shield`](crate::jobs::shield) has it terminated by `cancel_job`.
+    pub fn cancellable(mut self) -> Self {
+        self.cancellable = true;
+        self
+    }
+
+    fn program_name(&self) -> String {
+        // For sudo, name the command actually being run in errors
+        if self.program == "sudo" {
+            if let Some(program) = self.args.iter().find(|a| !a.to_string_lossy().starts_with('-')) {
+                return program.to_string_lossy().to_string();
+            }
+        }
+        self.program.to_string_lossy().to_string()
+    }
+
+    /// Builds the equivalent `std::process::Command`, for streaming pipelines that have to run
+    /// on a blocking thread. Callers should hold a [`slot`] while it runs. The timeout doesn't
+    /// carry over: dumps and imports run until they finish or their job is cancelled.
+    pub fn std_command(&self) -> std::process::Command {
+        use std::os::unix::process::CommandExt;
+
+        let mut command = std::process::Command::new(&self.program);
+        if self.env_clear {
+            command.env_clear();
+        }
+        command.args(&self.args).envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        if let Some((uid, gid)) = self.user {
+            command.uid(uid).gid(gid);
+        }
+        command
+    }
+
+    /// Runs the command to completion and captures its output, whatever the exit status.
+    pub async fn output(self) -> Result<Output, CommandError> {
+        let program = self.program_name();
+        let _slot = slot().await;
+
+        let mut command = tokio::process::Command::from(self.std_command());
+        command
+            .stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command.spawn().map_err(|source| CommandError::Spawn { program: program.clone(), source })?;
+        let job = self.cancellable.then(crate::jobs::current).flatten();
+        if let (Some(job), Some(pid)) = (&job, child.id()) {
+            job.watch_child(pid);
+        }
+
+        if let (Some(input), Some(mut stdin)) = (self.stdin, child.stdin.take()) {
+            // Written from its own task so a child producing output before reading can't deadlock.
+            // A process that exits without reading its input is reported through its exit status.
+            tokio::spawn(async move {
+                let _ = stdin.write_all(&input).await;
+            });
+        }
+
+        let wait = child.wait_with_output();
+        let output = match self.timeout {
+            // Dropping the future on timeout kills the child via kill_on_drop
+            Some(after) => tokio::time::timeout(after, wait)
+                .await
+                .map_err(|_| CommandError::Timeout { program: program.clone(), after })
+                .and_then(|output| output.map_err(|source| CommandError::Spawn { program, source })),
+            None => wait.await.map_err(|source| CommandError::Spawn { program, source }),
+        };
+        if let Some(job) = &job {
+            job.release_child();
+        }
+        let output = output?;
+
+        Ok(Output {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+
+    /// Runs the command and maps a non-zero exit status into a [`CommandError::Exit`].
+    pub async fn run(self) -> Result<Output, CommandError> {
+        let program = self.program_name();
+        let output = self.output().await?;
+
+        if output.success() {
+            Ok(output)
+        } else {
+            Err(CommandError::Exit { program, code: output.code, stderr: output.stderr })
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Background jobs for daemon methods that can outlive a client's socket timeout.
+//!
+//! Long-running methods (see [`LONG_RUNNING_METHODS`]) are submitted here and return
+//! `{"job_id": ...}` straight away, unless called with `"async": false` to wait for the
+//! result in the call itself. Clients then poll `get_job`,
+//! browse `list_jobs` or stop work with `cancel_job`. Every state change is written to
+//! `/var/lib/supercp/jobs/<id>.json`, so job history survives a daemon restart; jobs that were
+//! still running when the daemon stopped are reported as `interrupted`.
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+
+use crate::DaemonState;
+
+pub const JOBS_DIR: &str = "/var/lib/supercp/jobs";
+
+/// Methods that may be submitted as background jobs.
+pub const LONG_RUNNING_METHODS: &[&str] = &[
+    "create_backup",
+    "create_db_backup",
+    "restore_backup",
+    "restore_db_backup",
+    "get_directory_size",
+    "request_ssl_cert",
+    "run_cron_job_now",
+];
+
+/// Finished jobs older than this are pruned when the daemon starts.
+const RETENTION_SECS: u64 = 7 * 24 * 3600;
+
+/// Error of a job stopped by `cancel_job`.
+pub const CANCELLED: &str = "Cancelled by request";
+
+/// Only the tail of a job's log is kept, to bound the size of its record.
+const MAX_LOG_LINES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    Interrupted,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub id: String,
+    pub method: String,
+    pub params: Value,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub progress: Option<Value>,
+    #[serde(default)]
+    pub logs: Vec<String>,
+    #[serde(default)]
+    pub result: Option<Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub created_at: u64,
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+/// What `cancel_job` needs to know to stop a job that is running.
+#[derive(Default)]
+struct Cancellation {
+    cancelled: bool,
+    /// Sections of the handler that wind down by themselves when cancelled instead of being
+    /// aborted, see [`shield`]
+    shields: usize,
+    /// The process such a section is waiting on
+    child: Option<u32>,
+}
+
+type SharedCancellation = Arc<std::sync::Mutex<Cancellation>>;
+
+fn lock(cancellation: &SharedCancellation) -> std::sync::MutexGuard<'_, Cancellation> {
+    cancellation.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn terminate(pid: u32) {
+    let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+}
+
+pub struct JobManager {
+    dir: PathBuf,
+    jobs: Mutex<HashMap<String, Job>>,
+    handles: Mutex<HashMap<String, (AbortHandle, SharedCancellation)>>,
+}
+
+/// Handle given to a running job so handlers can publish progress and log lines.
+#[derive(Clone)]
+pub struct JobHandle {
+    id: String,
+    manager: Arc<JobManager>,
+    cancellation: SharedCancellation,
+}
+
+/// Keeps the job from being aborted while held; see [`shield`].
+pub struct Shield(SharedCancellation);
+
+impl Drop for Shield {
+    fn drop(&mut self) {
+        lock(&self.0).shields -= 1;
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_JOB: JobHandle;
+}
+
+/// Returns the job the current task is running as, if any.
+///
+/// Capture this before moving work onto `spawn_blocking`, where task-locals are not visible.
+pub fn current() -> Option<JobHandle> {
+    CURRENT_JOB.try_with(|job| job.clone()).ok()
+}
+
+/// Publishes progress for the current job. A no-op for direct (synchronous) calls.
+pub async fn progress(progress: Value) {
+    if let Some(job) = current() {
+        job.set_progress(progress).await;
+    }
+}
+
+/// Appends a line to the current job's log. A no-op for direct (synchronous) calls.
+pub async fn log(line: impl Into<String>) {
+    if let Some(job) = current() {
+        job.log(line).await;
+    }
+}
+
+/// Makes `cancel_job` stop the current job by terminating the child registered with
+/// [`JobHandle::watch_child`] instead of aborting the task, until the guard is dropped.
+///
+/// Aborting can't stop a pipeline running on a blocking thread, and would skip the handler's
+/// own cleanup of partial output. A shielded handler sees its child die, checks
+/// [`JobHandle::is_cancelled`] and unwinds normally; one without a child polls
+/// [`check_cancelled`] between steps. Fails if the job is already cancelled.
+pub fn shield() -> Result<Option<Shield>, String> {
+    let Some(job) = current() else {
+        return Ok(None);
+    };
+    let mut cancellation = lock(&job.cancellation);
+    if cancellation.cancelled {
+        return Err(CANCELLED.to_string());
+    }
+    cancellation.shields += 1;
+    Ok(Some(Shield(Arc::clone(&job.cancellation))))
+}
+
+/// Fails with [`CANCELLED`] once the current job has been cancelled. For shielded sections
+/// that have no child to terminate and check between steps instead.
+pub fn check_cancelled() -> Result<(), String> {
+    match current() {
+        Some(job) if job.is_cancelled() => Err(CANCELLED.to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Params holding secrets, masked in job records.
+const SECRET_PARAMS: &[&str] = &["password", "api_key", "key", "private_key", "secret", "token"];
+
+/// Strips secrets from params before they are persisted or returned by `get_job`.
+fn redact(params: &Value) -> Value {
+    let mut params = params.clone();
+    if let Some(map) = params.as_object_mut() {
+        map.remove("async");
+        for (key, value) in map.iter_mut() {
+            if SECRET_PARAMS.contains(&key.as_str()) {
+                *value = json!("********");
+            }
+        }
+    }
+    params
+}
+
+impl JobHandle {
+    pub async fn set_progress(&self, progress: Value) {
+        self.manager.update(&self.id, |job| job.progress = Some(progress)).await;
+    }
+
+    /// Same as [`JobHandle::set_progress`], for use from blocking threads.
+    pub fn set_progress_blocking(&self, progress: Value) {
+        let mut jobs = self.manager.jobs.blocking_lock();
+        if let Some(job) = jobs.get_mut(&self.id) {
+            job.progress = Some(progress);
+            self.manager.persist(job);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.cancellation).cancelled
+    }
+
+    /// Registers the process a shielded section waits on, so cancelling the job terminates it.
+    /// A job that was cancelled before the process started has it terminated straight away.
+    pub fn watch_child(&self, pid: u32) {
+        let mut cancellation = lock(&self.cancellation);
+        if cancellation.cancelled {
+            terminate(pid);
+        }
+        cancellation.child = Some(pid);
+    }
+
+    /// Forgets the process registered with [`JobHandle::watch_child`], once it has been reaped.
+    pub fn release_child(&self) {
+        lock(&self.cancellation).child = None;
+    }
+
+    pub async fn log(&self, line: impl Into<String>) {
+        let line = format!("[{}] {}", now(), line.into());
+        self.manager
+            .update(&self.id, |job| {
+                job.logs.push(line);
+                if job.logs.len() > MAX_LOG_LINES {
+                    let excess = job.logs.len() - MAX_LOG_LINES;
+                    job.logs.drain(..excess);
+                }
+            })
+            .await;
+    }
+}
+
+impl JobManager {
+    /// Loads persisted jobs, marking any that were in flight as interrupted.
+    pub fn load(dir: &str) -> Self {
+        let dir = PathBuf::from(dir);
+        let mut manager = JobManager {
+            dir: dir.clone(),
+            jobs: Mutex::new(HashMap::new()),
+            handles: Mutex::new(HashMap::new()),
+        };
+
+        let _ = fs::create_dir_all(&dir);
+        let mut jobs = HashMap::new();
+
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                    continue;
+                }
+
+                let Some(mut job) = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| serde_json::from_str::<Job>(&content).ok())
+                else {
+                    continue;
+                };
+
+                if job.status.is_finished() {
+                    if job.finished_at.unwrap_or(job.created_at) + RETENTION_SECS < now() {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                } else {
+                    job.status = JobStatus::Interrupted;
+                    job.error = Some("Daemon restarted before the job finished".to_string());
+                    job.finished_at = Some(now());
+                    manager.persist(&job);
+                }
+
+                jobs.insert(job.id.clone(), job);
+            }
+        }
+
+        manager.jobs = Mutex::new(jobs);
+        manager
+    }
+
+    fn job_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn persist(&self, job: &Job) {
+        let path = self.job_path(&job.id);
+        let temp_path = path.with_extension("json.tmp");
+        if let Ok(content) = serde_json::to_string_pretty(job) {
+            if fs::write(&temp_path, content).is_ok() {
+                let _ = fs::rename(&temp_path, &path);
+            }
+        }
+    }
+
+    async fn update(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        let mut jobs = self.jobs.lock().await;
+        if let Some(job) = jobs.get_mut(id) {
+            f(job);
+            self.persist(job);
+        }
+    }
+
+    /// Starts `method` in the background and returns the new job's id.
+    pub async fn submit(self: &Arc<Self>, method: &str, params: Value, state: Arc<Mutex<DaemonState>>) -> Result<String, Box<dyn std::error::Error>> {
+        if !LONG_RUNNING_METHODS.contains(&method) {
+            return Err(format!("Method {} cannot run as a job", method).into());
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let job = Job {
+            id: id.clone(),
+            method: method.to_string(),
+            params: redact(&params),
+            status: JobStatus::Queued,
+            progress: None,
+            logs: Vec::new(),
+            result: None,
+            error: None,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+        };
+
+        {
+            let mut jobs = self.jobs.lock().await;
+            self.persist(&job);
+            jobs.insert(id.clone(), job);
+        }
+
+        let cancellation = SharedCancellation::default();
+        let handle = JobHandle { id: id.clone(), manager: Arc::clone(self), cancellation: Arc::clone(&cancellation) };
+        let manager = Arc::clone(self);
+        let method = method.to_string();
+        let job_id = id.clone();
+
+        // Hold the handles lock across spawn so a fast job cannot finish before its handle is stored
+        let mut handles = self.handles.lock().await;
+        let task = tokio::spawn(CURRENT_JOB.scope(handle, async move {
+            manager
+                .update(&job_id, |job| {
+                    job.status = JobStatus::Running;
+                    job.started_at = Some(now());
+                })
+                .await;
+
+            let outcome = crate::execute_job(&method, &params, state).await.map_err(|e| e.to_string());
+
+            manager
+                .update(&job_id, |job| {
+                    // A shielded job that was cancelled keeps that status and its cancellation time
+                    if job.status == JobStatus::Cancelled {
+                        return;
+                    }
+                    job.finished_at = Some(now());
+                    match outcome {
+                        Ok(result) => {
+                            job.status = JobStatus::Completed;
+                            job.result = Some(result);
+                        }
+                        Err(error) => {
+                            job.status = JobStatus::Failed;
+                            job.error = Some(error);
+                        }
+                    }
+                })
+                .await;
+            manager.handles.lock().await.remove(&job_id);
+        }));
+        handles.insert(id.clone(), (task.abort_handle(), cancellation));
+
+        Ok(id)
+    }
+
+    pub async fn get_job(&self, params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let id = params["id"].as_str().ok_or("Missing id")?;
+        let jobs = self.jobs.lock().await;
+        let job = jobs.get(id).ok_or_else(|| format!("Job {} not found", id))?;
+
+        Ok(serde_json::to_value(job)?)
+    }
+
+    pub async fn list_jobs(&self, params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let status = params["status"].as_str();
+        let method = params["method"].as_str();
+        let limit = params["limit"].as_u64().unwrap_or(100) as usize;
+
+        let jobs = self.jobs.lock().await;
+        let mut list: Vec<&Job> = jobs
+            .values()
+            .filter(|job| {
+                status.is_none_or(|s| serde_json::to_value(job.status).ok() == Some(json!(s)))
+                    && method.is_none_or(|m| job.method == m)
+            })
+            .collect();
+        list.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+
+        // Logs are left out of the listing to keep it small; get_job returns them
+        let summaries: Vec<Value> = list
+            .into_iter()
+            .take(limit)
+            .map(|job| {
+                json!({
+                    "id": job.id,
+                    "method": job.method,
+                    "status": job.status,
+                    "progress": job.progress,
+                    "error": job.error,
+                    "created_at": job.created_at,
+                    "started_at": job.started_at,
+                    "finished_at": job.finished_at,
+                })
+            })
+            .collect();
+
+        Ok(json!(summaries))
+    }
+
+    pub async fn cancel_job(&self, params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+        let id = params["id"].as_str().ok_or("Missing id")?;
+
+        let mut jobs = self.jobs.lock().await;
+        let job = jobs.get_mut(id).ok_or_else(|| format!("Job {} not found", id))?;
+        if job.status.is_finished() {
+            return Err(format!("Job {} has already finished", id).into());
+        }
+
+        if let Some((handle, cancellation)) = self.handles.lock().await.remove(id) {
+            let mut cancellation = lock(&cancellation);
+            cancellation.cancelled = true;
+            if cancellation.shields == 0 {
+                handle.abort();
+            } else if let Some(pid) = cancellation.child {
+                terminate(pid);
+            }
+        }
+
+        job.status = JobStatus::Cancelled;
+        job.error = Some(CANCELLED.to_string());
+        job.finished_at = Some(now());
+        self.persist(job);
+
+        Ok(format!("Job {} cancelled", id))
+    }
+}
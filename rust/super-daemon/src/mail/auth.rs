@@ -0,0 +1,403 @@
+//! Sender authentication: DKIM keys OpenDKIM signs with, and SPF and DMARC records.
+//!
+//! Each domain has one DKIM key, recorded in `/etc/supercp/mail/dkim.json`. OpenDKIM's
+//! `KeyTable` and `SigningTable` are rendered from that file, and the public half is published
+//! as `<selector>._domainkey.<domain>`. Any of the records can be written straight into the
+//! domain's zone when it is hosted here.
+
+use data_encoding::BASE64;
+use nix::unistd::{chown, User};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use super::{is_root, rooted, write_file, MAIL_LOCK};
+use crate::config::{self, MailConfig};
+use crate::dns::{self, validate_domain};
+use crate::exec::Command;
+
+const DEFAULT_SELECTOR: &str = "default";
+const RSA_BITS: u32 = 2048;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DkimKey {
+    pub selector: String,
+    /// `rsa` or `ed25519`
+    pub algorithm: String,
+    /// Value of the `<selector>._domainkey` TXT record
+    pub record: String,
+    pub created_at: i64,
+}
+
+fn state_path(mail: &MailConfig) -> PathBuf {
+    rooted(mail, "/etc/supercp/mail/dkim.json")
+}
+
+fn load(mail: &MailConfig) -> Result<BTreeMap<String, DkimKey>, String> {
+    let path = state_path(mail);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid DKIM file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Where OpenDKIM finds the key, as it sees it (outside any mail root).
+fn key_path(mail: &MailConfig, domain: &str, selector: &str) -> String {
+    format!("{}/keys/{}/{}.private", mail.opendkim_dir.trim_end_matches('/'), domain, selector)
+}
+
+fn domain_param(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    Ok(domain)
+}
+
+/// A new key pair as (PKCS#8 PEM private key, DKIM `k=` tag, DKIM `p=` value).
+async fn generate_key(algorithm: &str) -> Result<(String, &'static str, String), String> {
+    match algorithm {
+        // ring can't generate RSA keys, so OpenSSL does
+        "rsa" => {
+            let output = Command::new("openssl")
+                .arg("genpkey")
+                .arg("-algorithm")
+                .arg("RSA")
+                .arg("-pkeyopt")
+                .arg(format!("rsa_keygen_bits:{}", RSA_BITS))
+                .output()
+                .await
+                .map_err(|e| e.to_string())?;
+            if !output.success() {
+                return Err(format!("openssl failed to generate an RSA key: {}", output.stderr.trim()));
+            }
+            let key = rcgen::KeyPair::from_pem(&output.stdout).map_err(|e| format!("openssl returned an unusable key: {}", e))?;
+            Ok((output.stdout, "rsa", BASE64.encode(&key.public_key_der())))
+        }
+        // RFC 8463 publishes the bare 32-byte public key
+        "ed25519" => {
+            let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).map_err(|e| e.to_string())?;
+            Ok((key.serialize_pem(), "ed25519", BASE64.encode(key.public_key_raw())))
+        }
+        other => Err(format!("Unknown DKIM algorithm '{}'; use rsa or ed25519", other)),
+    }
+}
+
+/// Writes a private key readable by OpenDKIM only.
+fn write_key(mail: &MailConfig, domain: &str, selector: &str, pem: &str) -> Result<(), String> {
+    let path = rooted(mail, &key_path(mail, domain, selector));
+    let dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+    write_file(&path, pem, 0o600)?;
+
+    if is_root() {
+        if let Ok(Some(user)) = User::from_name("opendkim") {
+            for target in [&dir, &path] {
+                chown(target, Some(user.uid), Some(user.gid)).map_err(|e| format!("Failed to chown {}: {}", target.display(), e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders OpenDKIM's `KeyTable` and `SigningTable` from the keys.
+fn render_tables(mail: &MailConfig, keys: &BTreeMap<String, DkimKey>) -> Result<(), String> {
+    let mut key_table = String::new();
+    let mut signing_table = String::new();
+    for (domain, key) in keys {
+        let name = format!("{}._domainkey.{}", key.selector, domain);
+        key_table.push_str(&format!("{} {}:{}:{}\n", name, domain, key.selector, key_path(mail, domain, &key.selector)));
+        signing_table.push_str(&format!("*@{} {}\n", domain, name));
+    }
+    let dir = rooted(mail, &mail.opendkim_dir);
+    write_file(&dir.join("KeyTable"), &key_table, 0o644)?;
+    write_file(&dir.join("SigningTable"), &signing_table, 0o644)
+}
+
+/// Replaces the TXT records at `owner` that `replaces` matches with one holding `value`. The
+/// ones `replaces` matches at `retired` go too, as they belong to what `value` superseded.
+fn replace_txt(records: &mut Vec<Value>, owner: &str, value: &str, replaces: fn(&str) -> bool, retired: Option<&str>) {
+    records.retain(|r| {
+        let name = r["name"].as_str().unwrap_or_default();
+        let stale = name == owner || Some(name) == retired;
+        !(r["type"] == "TXT" && stale && r["value"].as_str().is_some_and(replaces))
+    });
+    records.push(json!({ "name": owner, "type": "TXT", "value": value }));
+}
+
+/// Publishes `value` at `name` in the hosted zone `name` belongs to (see `replace_txt`). A
+/// `retired` name outside that zone is left alone.
+async fn publish_txt(name: &str, value: &str, replaces: fn(&str) -> bool, retired: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let zone = dns::zone_for(name).ok_or_else(|| format!("No DNS zone hosted here covers {}", name))?;
+    let owner = dns::record::owner_name(&format!("{}.", name), &zone)?;
+    let retired = retired.and_then(|r| dns::record::owner_name(&format!("{}.", r), &zone).ok());
+    dns::edit_records(&zone, |records| replace_txt(records, &owner, value, replaces, retired.as_deref())).await?;
+    Ok(zone)
+}
+
+/// The result of a record helper, after publishing it if `add_to_zone` is set.
+async fn record_result(
+    name: &str,
+    value: &str,
+    params: &Value,
+    replaces: fn(&str) -> bool,
+    retired: Option<&str>,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let zone = if params["add_to_zone"].as_bool().unwrap_or(false) { Some(publish_txt(name, value, replaces, retired).await?) } else { None };
+    Ok(json!({ "name": name, "type": "TXT", "value": value, "zone": zone }))
+}
+
+/// Creates the domain's DKIM key and has OpenDKIM sign its mail with it.
+///
+/// Params: `domain`, `selector` (default `default`), `algorithm` (`rsa`, 2048 bits, or
+/// `ed25519`), `force` (replace the domain's key, whatever its selector and algorithm) and
+/// `add_to_zone` (publish the record in the domain's zone). Without `force` an existing key
+/// is returned as is, or refused if it has another selector or algorithm.
+pub async fn generate_dkim_key(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+    let selector = params["selector"].as_str().unwrap_or(DEFAULT_SELECTOR).to_ascii_lowercase();
+    let valid_selector = !selector.is_empty()
+        && selector.len() <= 63
+        && !selector.starts_with('-')
+        && selector.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_selector {
+        return Err(format!("Invalid DKIM selector '{}'", selector).into());
+    }
+    let algorithm = params["algorithm"].as_str().unwrap_or("rsa").to_ascii_lowercase();
+    let force = params["force"].as_bool().unwrap_or(false);
+    // Checked first, so a key is never replaced when its record can't be published
+    let name = format!("{}._domainkey.{}", selector, domain);
+    if params["add_to_zone"].as_bool().unwrap_or(false) && dns::zone_for(&name).is_none() {
+        return Err(format!("No DNS zone hosted here covers {}", name).into());
+    }
+
+    let mail = config::load()?.mail;
+    let (key, created, retired) = install_key(&mail, &domain, &selector, &algorithm, force).await?;
+
+    // A forced change of selector takes the old selector's record with it
+    let retired = retired.map(|previous| format!("{}._domainkey.{}", previous, domain));
+    let mut result = record_result(&name, &key.record, params, |v| v.starts_with("v=DKIM1"), retired.as_deref()).await?;
+    result["domain"] = json!(domain);
+    result["selector"] = json!(key.selector);
+    result["algorithm"] = json!(key.algorithm);
+    result["created"] = json!(created);
+    Ok(result)
+}
+
+/// The domain's DKIM key, whether it was just created, and the selector of the key it replaced
+/// when that one had another.
+async fn install_key(
+    mail: &MailConfig,
+    domain: &str,
+    selector: &str,
+    algorithm: &str,
+    force: bool,
+) -> Result<(DkimKey, bool, Option<String>), Box<dyn std::error::Error>> {
+    let _guard = MAIL_LOCK.lock().await;
+    let mut keys = load(mail)?;
+
+    match keys.get(domain) {
+        Some(key) if !force && key.selector == selector && key.algorithm == algorithm => return Ok((key.clone(), false, None)),
+        Some(key) if !force => {
+            return Err(format!(
+                "{} already has a DKIM key (selector '{}', {}); pass force to replace it",
+                domain, key.selector, key.algorithm
+            )
+            .into())
+        }
+        _ => {}
+    }
+
+    let (pem, k, public) = generate_key(algorithm).await?;
+    write_key(mail, domain, selector, &pem)?;
+    let key = DkimKey { selector: selector.to_string(), algorithm: algorithm.to_string(), record: format!("v=DKIM1; k={}; p={}", k, public), created_at: chrono::Utc::now().timestamp() };
+    let retired = keys.insert(domain.to_string(), key.clone()).map(|previous| previous.selector).filter(|previous| previous != selector);
+    if let Some(previous) = &retired {
+        let _ = fs::remove_file(rooted(mail, &key_path(mail, domain, previous)));
+    }
+    render_tables(mail, &keys)?;
+    let content = serde_json::to_string_pretty(&keys).map_err(|e| e.to_string())?;
+    write_file(&state_path(mail), &content, 0o600)?;
+    if mail.apply {
+        Command::sudo("systemctl").arg("reload").arg("opendkim").run().await.map_err(|e| format!("Failed to reload opendkim: {}", e))?;
+    }
+    Ok((key, true, retired))
+}
+
+/// Builds the domain's SPF record.
+///
+/// Params: `domain`, `a` and `mx` (authorize the domain's own hosts; both default to true),
+/// `ip4`, `ip6` and `include` (lists), `all` (`fail`, `softfail` (default), `neutral`) and
+/// `add_to_zone`, which replaces any SPF record already there.
+pub async fn generate_spf_record(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+
+    let mut terms = vec!["v=spf1".to_string()];
+    for mechanism in ["a", "mx"] {
+        if params[mechanism].as_bool().unwrap_or(true) {
+            terms.push(mechanism.to_string());
+        }
+    }
+    for mechanism in ["ip4", "ip6", "include"] {
+        for value in params[mechanism].as_array().into_iter().flatten() {
+            let value = value.as_str().ok_or_else(|| format!("{} entries must be strings", mechanism))?;
+            let valid = match mechanism {
+                "ip4" => value.split_once('/').map_or(value, |(ip, _)| ip).parse::<std::net::Ipv4Addr>().is_ok(),
+                "ip6" => value.split_once('/').map_or(value, |(ip, _)| ip).parse::<std::net::Ipv6Addr>().is_ok(),
+                _ => dns::record::is_hostname(value.trim_end_matches('.'), true),
+            };
+            if !valid {
+                return Err(format!("Invalid {} value '{}'", mechanism, value).into());
+            }
+            terms.push(format!("{}:{}", mechanism, value.trim_end_matches('.')));
+        }
+    }
+    terms.push(
+        match params["all"].as_str().unwrap_or("softfail") {
+            "fail" => "-all",
+            "softfail" => "~all",
+            "neutral" => "?all",
+            other => return Err(format!("Unknown SPF all policy '{}'; use fail, softfail or neutral", other).into()),
+        }
+        .to_string(),
+    );
+
+    record_result(&domain, &terms.join(" "), params, |v| v.starts_with("v=spf1"), None).await
+}
+
+/// Builds the domain's DMARC record.
+///
+/// Params: `domain`, `policy` (`none` (default), `quarantine` or `reject`),
+/// `subdomain_policy`, `rua` and `ruf` (report addresses), `pct`, `strict` (strict DKIM and
+/// SPF alignment) and `add_to_zone`.
+pub async fn generate_dmarc_record(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = domain_param(params)?;
+    let policy_param = |key: &str, default: Option<&'static str>| -> Result<Option<String>, String> {
+        match params[key].as_str().or(default) {
+            Some(p @ ("none" | "quarantine" | "reject")) => Ok(Some(p.to_string())),
+            Some(other) => Err(format!("Unknown DMARC {} '{}'; use none, quarantine or reject", key, other)),
+            None => Ok(None),
+        }
+    };
+
+    let mut tags = vec!["v=DMARC1".to_string()];
+    tags.push(format!("p={}", policy_param("policy", Some("none"))?.unwrap_or_default()));
+    if let Some(policy) = policy_param("subdomain_policy", None)? {
+        tags.push(format!("sp={}", policy));
+    }
+    for tag in ["rua", "ruf"] {
+        if let Some(address) = params[tag].as_str() {
+            let address = address.trim_start_matches("mailto:");
+            super::split_address(address)?;
+            tags.push(format!("{}=mailto:{}", tag, address));
+        }
+    }
+    if let Some(pct) = params["pct"].as_u64() {
+        if pct > 100 {
+            return Err("pct must be between 0 and 100".into());
+        }
+        tags.push(format!("pct={}", pct));
+    }
+    if params["strict"].as_bool().unwrap_or(false) {
+        tags.push("adkim=s".to_string());
+        tags.push("aspf=s".to_string());
+    }
+
+    record_result(&format!("_dmarc.{}", domain), &tags.join("; "), params, |v| v.starts_with("v=DMARC1"), None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn config(root: &Path) -> MailConfig {
+        MailConfig { root: root.display().to_string(), apply: false, ..Default::default() }
+    }
+
+    fn key_file(mail: &MailConfig, selector: &str) -> PathBuf {
+        rooted(mail, &key_path(mail, "example.com", selector))
+    }
+
+    #[tokio::test]
+    async fn keeps_the_same_key_and_refuses_another_without_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+        let (key, created, retired) = install_key(&mail, "example.com", "default", "ed25519", false).await.unwrap();
+        assert!(created);
+        assert_eq!(retired, None);
+        assert!(key.record.starts_with("v=DKIM1; k=ed25519; p="), "{}", key.record);
+        let pem = fs::read_to_string(key_file(&mail, "default")).unwrap();
+
+        // Asking again returns the key as it was
+        let (same, created, retired) = install_key(&mail, "example.com", "default", "ed25519", false).await.unwrap();
+        assert!(!created);
+        assert_eq!(retired, None);
+        assert_eq!(same.record, key.record);
+        assert_eq!(fs::read_to_string(key_file(&mail, "default")).unwrap(), pem);
+
+        for (selector, algorithm) in [("mail2024", "ed25519"), ("default", "rsa")] {
+            let Err(err) = install_key(&mail, "example.com", selector, algorithm, false).await else { panic!("{} replaced the key without force", selector) };
+            assert_eq!(err.to_string(), "example.com already has a DKIM key (selector 'default', ed25519); pass force to replace it");
+        }
+        assert!(!key_file(&mail, "mail2024").exists());
+        assert_eq!(load(&mail).unwrap()["example.com"].record, key.record);
+    }
+
+    #[tokio::test]
+    async fn force_replaces_the_key_and_retires_its_selector() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+        let (old, _, _) = install_key(&mail, "example.com", "default", "ed25519", false).await.unwrap();
+
+        // Same selector: a new key, nothing to retire
+        let (renewed, created, retired) = install_key(&mail, "example.com", "default", "ed25519", true).await.unwrap();
+        assert!(created);
+        assert_eq!(retired, None);
+        assert_ne!(renewed.record, old.record);
+        assert!(key_file(&mail, "default").exists());
+
+        // New selector: the old key file goes and its selector is handed back
+        let (rotated, created, retired) = install_key(&mail, "example.com", "mail2024", "ed25519", true).await.unwrap();
+        assert!(created);
+        assert_eq!(retired.as_deref(), Some("default"));
+        assert!(!key_file(&mail, "default").exists());
+        assert!(key_file(&mail, "mail2024").exists());
+
+        let keys = load(&mail).unwrap();
+        assert_eq!(keys["example.com"].selector, "mail2024");
+        assert_eq!(keys["example.com"].record, rotated.record);
+        let key_table = fs::read_to_string(rooted(&mail, &mail.opendkim_dir).join("KeyTable")).unwrap();
+        assert_eq!(key_table, format!("mail2024._domainkey.example.com example.com:mail2024:{}\n", key_path(&mail, "example.com", "mail2024")));
+    }
+
+    #[test]
+    fn replacing_a_record_drops_the_retired_selectors() {
+        let mut records = vec![
+            json!({ "name": "default._domainkey", "type": "TXT", "value": "v=DKIM1; k=rsa; p=old" }),
+            json!({ "name": "default._domainkey", "type": "TXT", "value": "unrelated" }),
+            json!({ "name": "other._domainkey", "type": "TXT", "value": "v=DKIM1; k=rsa; p=other" }),
+            json!({ "name": "@", "type": "TXT", "value": "v=spf1 mx -all" }),
+        ];
+        replace_txt(&mut records, "mail2024._domainkey", "v=DKIM1; k=ed25519; p=new", |v| v.starts_with("v=DKIM1"), Some("default._domainkey"));
+        assert_eq!(
+            records,
+            [
+                json!({ "name": "default._domainkey", "type": "TXT", "value": "unrelated" }),
+                json!({ "name": "other._domainkey", "type": "TXT", "value": "v=DKIM1; k=rsa; p=other" }),
+                json!({ "name": "@", "type": "TXT", "value": "v=spf1 mx -all" }),
+                json!({ "name": "mail2024._domainkey", "type": "TXT", "value": "v=DKIM1; k=ed25519; p=new" }),
+            ]
+        );
+
+        // Without a retired selector only the record at the name itself is replaced
+        replace_txt(&mut records, "other._domainkey", "v=DKIM1; k=rsa; p=renewed", |v| v.starts_with("v=DKIM1"), None);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3], json!({ "name": "other._domainkey", "type": "TXT", "value": "v=DKIM1; k=rsa; p=renewed" }));
+        assert!(records.iter().any(|r| r["name"] == "mail2024._domainkey"));
+    }
+}
@@ -0,0 +1,371 @@
+//! Out-of-office replies, as Sieve `vacation` scripts run by Dovecot's Pigeonhole.
+//!
+//! The settings are kept in `/etc/supercp/mail/autoresponders.json` and rendered into
+//! `<home>/sieve/autoresponder.sieve`, which `<home>/.dovecot.sieve` links to as the mailbox's
+//! active script. The date range is checked by the script itself, so a reply that starts next
+//! week needs nothing more from the daemon when the day comes.
+
+use chrono::NaiveDate;
+use nix::unistd::{chown, Gid, Uid};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::{lchown, symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use super::{home, is_root, load_mailboxes, rooted, split_address, write_file, MAIL_LOCK};
+use crate::config::{self, MailConfig};
+use crate::exec::Command;
+
+const SCRIPT_NAME: &str = "autoresponder.sieve";
+/// Dovecot's default active script
+const ACTIVE_SCRIPT: &str = ".dovecot.sieve";
+
+const DEFAULT_INTERVAL_DAYS: u64 = 1;
+/// Pigeonhole's default `sieve_vacation_max_period`
+const MAX_INTERVAL_DAYS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Autoresponder {
+    pub subject: String,
+    pub body: String,
+    /// First and last day replies are sent, `YYYY-MM-DD`, inclusive
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    /// Days before the same sender is answered again
+    pub interval_days: u64,
+    /// Sender of the replies; the mailbox itself if unset
+    pub from: Option<String>,
+    pub updated_at: i64,
+}
+
+fn state_path(mail: &MailConfig) -> PathBuf {
+    rooted(mail, "/etc/supercp/mail/autoresponders.json")
+}
+
+fn load(mail: &MailConfig) -> Result<BTreeMap<String, Autoresponder>, String> {
+    let path = state_path(mail);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid autoresponder file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn save(mail: &MailConfig, autoresponders: &BTreeMap<String, Autoresponder>) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(autoresponders).map_err(|e| e.to_string())?;
+    write_file(&state_path(mail), &content, 0o600)
+}
+
+/// Drops the autoresponder of a mailbox that is being deleted. Its script goes with the
+/// mailbox's home, if that is removed.
+pub fn forget(mail: &MailConfig, address: &str) -> Result<(), String> {
+    let mut autoresponders = load(mail)?;
+    if autoresponders.remove(address).is_some() {
+        save(mail, &autoresponders)?;
+    }
+    Ok(())
+}
+
+/// A Sieve quoted string.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn render(address: &str, autoresponder: &Autoresponder) -> String {
+    let mut conditions = Vec::new();
+    if let Some(start) = &autoresponder.start_date {
+        conditions.push(format!("currentdate :value \"ge\" \"date\" {}", quote(start)));
+    }
+    if let Some(end) = &autoresponder.end_date {
+        conditions.push(format!("currentdate :value \"le\" \"date\" {}", quote(end)));
+    }
+
+    let indent = if conditions.is_empty() { "" } else { "    " };
+    let mut vacation = format!(
+        "{}vacation :days {} :subject {} :addresses [{}]",
+        indent,
+        autoresponder.interval_days,
+        quote(&autoresponder.subject),
+        quote(address)
+    );
+    if let Some(from) = &autoresponder.from {
+        vacation.push_str(&format!(" :from {}", quote(from)));
+    }
+    vacation.push_str(&format!("\n{}    {};", indent, quote(&autoresponder.body.replace("\r\n", "\n"))));
+
+    let body = if conditions.is_empty() { vacation } else { format!("if allof({}) {{\n{}\n}}", conditions.join(", "), vacation) };
+    format!("# Autoresponder managed by SuperCP; changes here are overwritten\nrequire [\"vacation\", \"date\", \"relational\"];\n\n{}\n", body)
+}
+
+fn sieve_paths(mail: &MailConfig, address: &str) -> Result<(PathBuf, PathBuf), String> {
+    let (local, domain) = split_address(address)?;
+    let home = rooted(mail, &home(mail, &local, &domain));
+    Ok((home.join("sieve").join(SCRIPT_NAME), home.join(ACTIVE_SCRIPT)))
+}
+
+/// Whether the active script is ours, or there is none.
+fn active_is_ours(active: &Path) -> bool {
+    match fs::read_link(active) {
+        Ok(target) => target == Path::new("sieve").join(SCRIPT_NAME),
+        Err(_) => fs::symlink_metadata(active).is_err(),
+    }
+}
+
+fn chown_vmail(mail: &MailConfig, path: &Path) -> Result<(), String> {
+    if is_root() {
+        chown(path, Some(Uid::from_raw(mail.vmail_uid)), Some(Gid::from_raw(mail.vmail_gid))).map_err(|e| format!("Failed to chown {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Writes the script, compiles it and makes it the mailbox's active script.
+async fn install(mail: &MailConfig, address: &str, autoresponder: &Autoresponder) -> Result<(), String> {
+    let (script, active) = sieve_paths(mail, address)?;
+    if !active_is_ours(&active) {
+        return Err(format!("{} already has a Sieve script of its own at {}", address, active.display()));
+    }
+
+    let dir = script.parent().map(Path::to_path_buf).unwrap_or_default();
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+    chown_vmail(mail, &dir)?;
+    write_file(&script, &render(address, autoresponder), 0o600)?;
+    chown_vmail(mail, &script)?;
+
+    if mail.apply {
+        Command::sudo("sievec").arg(&script).run().await.map_err(|e| format!("Failed to compile {}: {}", script.display(), e))?;
+        let compiled = script.with_extension("svbin");
+        if compiled.exists() {
+            chown_vmail(mail, &compiled)?;
+        }
+    }
+
+    if fs::symlink_metadata(&active).is_err() {
+        symlink(Path::new("sieve").join(SCRIPT_NAME), &active).map_err(|e| format!("Failed to activate {}: {}", script.display(), e))?;
+        if is_root() {
+            lchown(&active, Some(mail.vmail_uid), Some(mail.vmail_gid)).map_err(|e| format!("Failed to chown {}: {}", active.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+fn date_param(params: &Value, key: &str) -> Result<Option<String>, String> {
+    match params[key].as_str().filter(|d| !d.is_empty()) {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|d| Some(d.format("%Y-%m-%d").to_string()))
+            .map_err(|_| format!("Invalid {} '{}'; use YYYY-MM-DD", key, date)),
+        None => Ok(None),
+    }
+}
+
+/// Whether replies are being sent today.
+fn is_active(autoresponder: &Autoresponder) -> bool {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    autoresponder.start_date.as_ref().is_none_or(|start| *start <= today) && autoresponder.end_date.as_ref().is_none_or(|end| *end >= today)
+}
+
+fn describe(address: &str, autoresponder: &Autoresponder) -> Value {
+    let mut value = json!(autoresponder);
+    value["email"] = json!(address);
+    value["active"] = json!(is_active(autoresponder));
+    value
+}
+
+fn address_param(params: &Value) -> Result<String, String> {
+    let (local, domain) = split_address(params["email"].as_str().ok_or("Missing email")?)?;
+    Ok(format!("{}@{}", local, domain))
+}
+
+/// Turns on a mailbox's autoresponder or changes it.
+///
+/// Params: `email`, `subject`, `body`, `start_date` and `end_date` (`YYYY-MM-DD`, inclusive;
+/// either may be left open), `interval_days` (before the same sender gets another reply,
+/// default 1) and `from`.
+pub async fn set_autoresponder(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let address = address_param(params)?;
+    let subject = params["subject"].as_str().ok_or("Missing subject")?.trim();
+    if subject.is_empty() || subject.chars().any(char::is_control) {
+        return Err("The subject must be a single non-empty line".into());
+    }
+    let body = params["body"].as_str().ok_or("Missing body")?;
+    if body.trim().is_empty() {
+        return Err("Missing body".into());
+    }
+    let start_date = date_param(params, "start_date")?;
+    let end_date = date_param(params, "end_date")?;
+    if let (Some(start), Some(end)) = (&start_date, &end_date) {
+        if start > end {
+            return Err(format!("The end date {} is before the start date {}", end, start).into());
+        }
+    }
+    let interval_days = params["interval_days"].as_u64().unwrap_or(DEFAULT_INTERVAL_DAYS);
+    if !(1..=MAX_INTERVAL_DAYS).contains(&interval_days) {
+        return Err(format!("interval_days must be between 1 and {}", MAX_INTERVAL_DAYS).into());
+    }
+    let from = match params["from"].as_str().filter(|f| !f.is_empty()) {
+        Some(from) => {
+            let (local, domain) = split_address(from)?;
+            Some(format!("{}@{}", local, domain))
+        }
+        None => None,
+    };
+
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+    if !load_mailboxes(&mail)?.contains_key(&address) {
+        return Err(format!("Email account {} does not exist", address).into());
+    }
+
+    let autoresponder = Autoresponder {
+        subject: subject.to_string(),
+        body: body.to_string(),
+        start_date,
+        end_date,
+        interval_days,
+        from,
+        updated_at: chrono::Utc::now().timestamp(),
+    };
+    install(&mail, &address, &autoresponder).await?;
+    let mut autoresponders = load(&mail)?;
+    autoresponders.insert(address.clone(), autoresponder);
+    save(&mail, &autoresponders)?;
+
+    Ok(format!("Autoresponder set for {}", address))
+}
+
+/// The mailbox's autoresponder, with whether it is sending replies today; null if it has none.
+pub async fn get_autoresponder(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let address = address_param(params)?;
+    let mail = config::load()?.mail;
+    Ok(load(&mail)?.get(&address).map(|a| describe(&address, a)).unwrap_or(Value::Null))
+}
+
+/// Turns off the mailbox's autoresponder and removes its script.
+pub async fn delete_autoresponder(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let address = address_param(params)?;
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+
+    let mut autoresponders = load(&mail)?;
+    if autoresponders.remove(&address).is_none() {
+        return Ok(format!("{} has no autoresponder", address));
+    }
+
+    let (script, active) = sieve_paths(&mail, &address)?;
+    if fs::read_link(&active).is_ok() && active_is_ours(&active) {
+        fs::remove_file(&active).map_err(|e| format!("Failed to deactivate {}: {}", script.display(), e))?;
+    }
+    for path in [script.with_extension("svbin"), script.clone()] {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(format!("Failed to remove {}: {}", path.display(), e).into()),
+            _ => {}
+        }
+    }
+    save(&mail, &autoresponders)?;
+
+    Ok(format!("Autoresponder removed for {}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autoresponder(start_date: Option<&str>, end_date: Option<&str>, from: Option<&str>) -> Autoresponder {
+        Autoresponder {
+            subject: "Out of office".to_string(),
+            body: "Back soon.".to_string(),
+            start_date: start_date.map(String::from),
+            end_date: end_date.map(String::from),
+            interval_days: 3,
+            from: from.map(String::from),
+            updated_at: 0,
+        }
+    }
+
+    const HEADER: &str = "# Autoresponder managed by SuperCP; changes here are overwritten\nrequire [\"vacation\", \"date\", \"relational\"];\n\n";
+
+    #[test]
+    fn quotes_backslashes_and_double_quotes() {
+        let mut reply = autoresponder(None, None, None);
+        reply.subject = r#"Re: "holiday" \o/"#.to_string();
+        reply.body = "Dear sender,\r\nI'm away; write to \"backup\" at C:\\mail.\r\n".to_string();
+        assert_eq!(
+            render("alice@example.com", &reply),
+            format!(
+                "{}{}",
+                HEADER,
+                concat!(
+                    "vacation :days 3 :subject \"Re: \\\"holiday\\\" \\\\o/\" :addresses [\"alice@example.com\"]\n",
+                    "    \"Dear sender,\nI'm away; write to \\\"backup\\\" at C:\\\\mail.\n\";\n",
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn checks_whichever_dates_are_set() {
+        let both = autoresponder(Some("2024-07-01"), Some("2024-07-14"), None);
+        assert_eq!(
+            render("alice@example.com", &both),
+            format!(
+                "{}{}",
+                HEADER,
+                concat!(
+                    "if allof(currentdate :value \"ge\" \"date\" \"2024-07-01\", currentdate :value \"le\" \"date\" \"2024-07-14\") {\n",
+                    "    vacation :days 3 :subject \"Out of office\" :addresses [\"alice@example.com\"]\n",
+                    "        \"Back soon.\";\n",
+                    "}\n",
+                )
+            )
+        );
+
+        let start_only = autoresponder(Some("2024-07-01"), None, None);
+        assert_eq!(
+            render("alice@example.com", &start_only),
+            format!(
+                "{}{}",
+                HEADER,
+                concat!(
+                    "if allof(currentdate :value \"ge\" \"date\" \"2024-07-01\") {\n",
+                    "    vacation :days 3 :subject \"Out of office\" :addresses [\"alice@example.com\"]\n",
+                    "        \"Back soon.\";\n",
+                    "}\n",
+                )
+            )
+        );
+
+        let end_only = autoresponder(None, Some("2024-07-14"), None);
+        assert_eq!(
+            render("alice@example.com", &end_only),
+            format!(
+                "{}{}",
+                HEADER,
+                concat!(
+                    "if allof(currentdate :value \"le\" \"date\" \"2024-07-14\") {\n",
+                    "    vacation :days 3 :subject \"Out of office\" :addresses [\"alice@example.com\"]\n",
+                    "        \"Back soon.\";\n",
+                    "}\n",
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn replies_from_another_address() {
+        let reply = autoresponder(None, None, Some("support@example.com"));
+        assert_eq!(
+            render("alice@example.com", &reply),
+            format!(
+                "{}{}",
+                HEADER,
+                concat!(
+                    "vacation :days 3 :subject \"Out of office\" :addresses [\"alice@example.com\"] :from \"support@example.com\"\n",
+                    "    \"Back soon.\";\n",
+                )
+            )
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! SHA512-CRYPT (`$6$`) password hashes, the glibc `crypt(3)` scheme Dovecot accepts as
+//! `{SHA512-CRYPT}`.
+
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha512};
+
+/// The crypt base64 alphabet.
+const ALPHABET: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const SALT_LENGTH: usize = 16;
+const ROUNDS: usize = 5000;
+
+/// The order the digest bytes are encoded in, three at a time.
+const BYTE_ORDER: [(usize, usize, usize); 21] = [
+    (0, 21, 42),
+    (22, 43, 1),
+    (44, 2, 23),
+    (3, 24, 45),
+    (25, 46, 4),
+    (47, 5, 26),
+    (6, 27, 48),
+    (28, 49, 7),
+    (50, 8, 29),
+    (9, 30, 51),
+    (31, 52, 10),
+    (53, 11, 32),
+    (12, 33, 54),
+    (34, 55, 13),
+    (56, 14, 35),
+    (15, 36, 57),
+    (37, 58, 16),
+    (59, 17, 38),
+    (18, 39, 60),
+    (40, 61, 19),
+    (62, 20, 41),
+];
+
+fn encode_24bit(out: &mut String, b2: u8, b1: u8, b0: u8, chars: usize) {
+    let mut word = ((b2 as u32) << 16) | ((b1 as u32) << 8) | b0 as u32;
+    for _ in 0..chars {
+        out.push(ALPHABET[(word & 0x3f) as usize] as char);
+        word >>= 6;
+    }
+}
+
+/// `bytes` repeated to `length` bytes.
+fn stretch(bytes: &[u8], length: usize) -> Vec<u8> {
+    bytes.iter().copied().cycle().take(length).collect()
+}
+
+/// The `$6$<salt>$<hash>` string for `password`, with the default 5000 rounds.
+fn sha512_crypt(password: &[u8], salt: &str) -> String {
+    let salt = &salt.as_bytes()[..salt.len().min(SALT_LENGTH)];
+
+    let alternate = Sha512::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+
+    let mut digest = Sha512::new().chain_update(password).chain_update(salt);
+    digest.update(stretch(&alternate, password.len()));
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            digest.update(alternate);
+        } else {
+            digest.update(password);
+        }
+        length >>= 1;
+    }
+    let mut current = digest.finalize();
+
+    let mut p = Sha512::new();
+    for _ in 0..password.len() {
+        p.update(password);
+    }
+    let p = stretch(&p.finalize(), password.len());
+
+    let mut s = Sha512::new();
+    for _ in 0..16 + current[0] as usize {
+        s.update(salt);
+    }
+    let s = stretch(&s.finalize(), salt.len());
+
+    for round in 0..ROUNDS {
+        let mut digest = Sha512::new();
+        if round % 2 == 1 {
+            digest.update(&p);
+        } else {
+            digest.update(current);
+        }
+        if round % 3 != 0 {
+            digest.update(&s);
+        }
+        if round % 7 != 0 {
+            digest.update(&p);
+        }
+        if round % 2 == 1 {
+            digest.update(current);
+        } else {
+            digest.update(&p);
+        }
+        current = digest.finalize();
+    }
+
+    let mut hash = format!("$6${}$", String::from_utf8_lossy(salt));
+    for (a, b, c) in BYTE_ORDER {
+        encode_24bit(&mut hash, current[a], current[b], current[c], 4);
+    }
+    encode_24bit(&mut hash, 0, 0, current[63], 2);
+    hash
+}
+
+/// Hashes `password` with a fresh random salt, in Dovecot's `{SHA512-CRYPT}$6$...` form.
+pub fn hash_password(password: &str) -> String {
+    let mut random = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut random);
+    let salt: String = random.iter().map(|b| ALPHABET[(b & 0x3f) as usize] as char).collect();
+    format!("{{SHA512-CRYPT}}{}", sha512_crypt(password.as_bytes(), &salt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_openssl_passwd() {
+        // openssl passwd -6 -salt <salt> <password>
+        assert_eq!(
+            sha512_crypt(b"Hello", "saltstring"),
+            "$6$saltstring$aQzKv7HhksN4CNT5HySRdxOEHxZvlWWP2je/lOgbrHx5iLYj3NJfVnC287n/dwkODYWL1.LZUdO9vX84fkCna/"
+        );
+        // Salts are cut to 16 characters
+        assert_eq!(
+            sha512_crypt(b"secret", "toolongsaltstringXYZ"),
+            "$6$toolongsaltstrin$YbDr56I3YnFMzFdiKjmvZJQFadx22PE13HAFEyCcNt7LQj3KY3OctF6ymrQHwjCUk.FcwaWOEAV7s6i0EMV4i0"
+        );
+    }
+
+    #[test]
+    fn matches_the_glibc_test_vector() {
+        assert_eq!(
+            sha512_crypt(b"Hello world!", "saltstring"),
+            "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1"
+        );
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        let (first, second) = (hash_password("secret"), hash_password("secret"));
+        assert!(first.starts_with("{SHA512-CRYPT}$6$"));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,271 @@
+//! Forwarders and catch-all addresses, rendered into Postfix's `virtual_alias_maps`.
+//!
+//! A forwarder sends mail for an address on to one or more others; listing the source itself
+//! among the destinations keeps a copy in its mailbox. A domain's catch-all takes mail for any
+//! address there without a mailbox or forwarder of its own.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use super::{load_mailboxes, rooted, split_address, write_file, Mailbox, MAIL_LOCK};
+use crate::config::{self, MailConfig};
+use crate::dns::validate_domain;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Forwarders {
+    /// Destinations by source address
+    pub forwarders: BTreeMap<String, Vec<String>>,
+    /// Destinations by domain
+    pub catch_all: BTreeMap<String, Vec<String>>,
+}
+
+fn state_path(mail: &MailConfig) -> PathBuf {
+    rooted(mail, "/etc/supercp/mail/forwarders.json")
+}
+
+pub fn load(mail: &MailConfig) -> Result<Forwarders, String> {
+    let path = state_path(mail);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid forwarder file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Forwarders::default()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+pub fn save(mail: &MailConfig, forwarders: &Forwarders) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(forwarders).map_err(|e| e.to_string())?;
+    write_file(&state_path(mail), &content, 0o600)
+}
+
+/// The domains forwarders and catch-alls receive mail for.
+pub fn domains(forwarders: &Forwarders) -> Vec<String> {
+    let sources = forwarders.forwarders.keys().filter_map(|source| source.rsplit_once('@').map(|(_, domain)| domain.to_string()));
+    sources.chain(forwarders.catch_all.keys().cloned()).collect()
+}
+
+/// The `virtual_alias_maps` entries. Mailboxes in a domain with a catch-all map to
+/// themselves, or Postfix would send their mail to the catch-all as well.
+pub fn alias_entries(forwarders: &Forwarders, mailboxes: &BTreeMap<String, Mailbox>) -> Vec<(String, String)> {
+    let mut entries: BTreeMap<String, String> = forwarders.forwarders.iter().map(|(source, to)| (source.clone(), to.join(","))).collect();
+    for address in mailboxes.keys() {
+        let in_catch_all_domain = address.rsplit_once('@').is_some_and(|(_, domain)| forwarders.catch_all.contains_key(domain));
+        if in_catch_all_domain && !entries.contains_key(address) {
+            entries.insert(address.clone(), address.clone());
+        }
+    }
+    let mut entries: Vec<(String, String)> = entries.into_iter().collect();
+    entries.extend(forwarders.catch_all.iter().map(|(domain, to)| (format!("@{}", domain), to.join(","))));
+    entries
+}
+
+/// Reads `destination` (one address) or `destinations` (a list) from the params.
+fn destinations(params: &Value) -> Result<Vec<String>, String> {
+    let given: Vec<&Value> = match (&params["destination"], &params["destinations"]) {
+        (Value::String(_), _) => vec![&params["destination"]],
+        (_, Value::Array(list)) => list.iter().collect(),
+        _ => return Err("Missing destination".to_string()),
+    };
+    let mut addresses = Vec::new();
+    for destination in given {
+        let (local, domain) = split_address(destination.as_str().ok_or("Destinations must be strings")?)?;
+        let address = format!("{}@{}", local, domain);
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    if addresses.is_empty() {
+        return Err("Missing destination".to_string());
+    }
+    Ok(addresses)
+}
+
+/// Adds a forwarder, or more destinations to an existing one.
+///
+/// Params: `source` (the address mail arrives for) and `destination` or `destinations`.
+/// The source may be among the destinations if it is a mailbox, to keep a copy there.
+pub async fn create_email_forwarder(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let (local, domain) = split_address(params["source"].as_str().ok_or("Missing source")?)?;
+    let source = format!("{}@{}", local, domain);
+    let to = destinations(params)?;
+
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+    let mailboxes = load_mailboxes(&mail)?;
+    if to.contains(&source) && !mailboxes.contains_key(&source) {
+        return Err(format!("{} can only forward to itself if it is a mailbox", source).into());
+    }
+
+    let mut forwarders = load(&mail)?;
+    let current = forwarders.forwarders.entry(source.clone()).or_default();
+    for destination in to {
+        if !current.contains(&destination) {
+            current.push(destination);
+        }
+    }
+    let summary = current.join(", ");
+    super::commit(&mail, &mailboxes, &forwarders).await?;
+
+    Ok(format!("Forwarder {} -> {} saved", source, summary))
+}
+
+/// Removes a forwarder, or with `destination` just that destination from it.
+pub async fn delete_email_forwarder(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let (local, domain) = split_address(params["source"].as_str().ok_or("Missing source")?)?;
+    let source = format!("{}@{}", local, domain);
+
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+    let mut forwarders = load(&mail)?;
+    let Some(current) = forwarders.forwarders.get_mut(&source) else {
+        return Ok(format!("Forwarder {} does not exist", source));
+    };
+
+    let message = match params["destination"].as_str() {
+        Some(destination) => {
+            let destination = destination.trim().to_ascii_lowercase();
+            current.retain(|d| *d != destination);
+            if current.is_empty() {
+                forwarders.forwarders.remove(&source);
+            }
+            format!("Forwarder {} -> {} deleted", source, destination)
+        }
+        None => {
+            forwarders.forwarders.remove(&source);
+            format!("Forwarder {} deleted", source)
+        }
+    };
+    super::commit(&mail, &load_mailboxes(&mail)?, &forwarders).await?;
+    Ok(message)
+}
+
+/// Forwarders and catch-alls, optionally only those of `domain`.
+pub async fn list_email_forwarders(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().map(|d| d.to_ascii_lowercase());
+    let forwarders = load(&config::load()?.mail)?;
+    let in_domain = |d: &str| domain.as_deref().is_none_or(|wanted| wanted == d);
+
+    let list: Vec<Value> = forwarders
+        .forwarders
+        .iter()
+        .filter(|(source, _)| source.rsplit_once('@').is_some_and(|(_, d)| in_domain(d)))
+        .map(|(source, to)| json!({ "source": source, "destinations": to }))
+        .collect();
+    let catch_all: Vec<Value> = forwarders
+        .catch_all
+        .iter()
+        .filter(|(d, _)| in_domain(d))
+        .map(|(d, to)| json!({ "domain": d, "destinations": to }))
+        .collect();
+
+    Ok(json!({ "forwarders": list, "catch_all": catch_all }))
+}
+
+/// Sets where a domain's catch-all delivers, or with no destination (or null) removes it.
+pub async fn set_email_catch_all(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    let to = match (&params["destination"], &params["destinations"]) {
+        (Value::Null, Value::Null) => None,
+        (Value::String(d), _) if d.is_empty() => None,
+        (_, Value::Array(list)) if list.is_empty() => None,
+        _ => Some(destinations(params)?),
+    };
+
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+    let mut forwarders = load(&mail)?;
+    let message = match to {
+        Some(to) => {
+            let message = format!("Catch-all for {} delivers to {}", domain, to.join(", "));
+            forwarders.catch_all.insert(domain, to);
+            message
+        }
+        None => {
+            if forwarders.catch_all.remove(&domain).is_none() {
+                return Ok(format!("{} has no catch-all", domain));
+            }
+            format!("Catch-all for {} removed", domain)
+        }
+    };
+    super::commit(&mail, &load_mailboxes(&mail)?, &forwarders).await?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mailboxes(addresses: &[&str]) -> BTreeMap<String, Mailbox> {
+        addresses.iter().map(|a| (a.to_string(), Mailbox { password: String::new(), quota_mb: 0 })).collect()
+    }
+
+    fn pair(from: &str, to: &str) -> (String, String) {
+        (from.to_string(), to.to_string())
+    }
+
+    #[test]
+    fn catch_all_domains_keep_their_mailboxes() {
+        let mut forwarders = Forwarders::default();
+        forwarders.catch_all.insert("example.com".to_string(), vec!["postmaster@example.net".to_string()]);
+        let boxes = mailboxes(&["alice@example.com", "bob@example.com", "carol@example.org"]);
+
+        assert_eq!(
+            alias_entries(&forwarders, &boxes),
+            [
+                pair("alice@example.com", "alice@example.com"),
+                pair("bob@example.com", "bob@example.com"),
+                pair("@example.com", "postmaster@example.net"),
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarders_win_over_the_mailbox_self_mapping() {
+        let mut forwarders = Forwarders::default();
+        forwarders.catch_all.insert("example.com".to_string(), vec!["postmaster@example.net".to_string(), "ops@example.net".to_string()]);
+        forwarders.forwarders.insert("alice@example.com".to_string(), vec!["alice@example.com".to_string(), "alice@example.net".to_string()]);
+        forwarders.forwarders.insert("sales@example.com".to_string(), vec!["bob@example.com".to_string()]);
+        forwarders.forwarders.insert("info@example.org".to_string(), vec!["carol@example.org".to_string()]);
+        let boxes = mailboxes(&["alice@example.com", "bob@example.com", "carol@example.org"]);
+
+        assert_eq!(
+            alias_entries(&forwarders, &boxes),
+            [
+                pair("alice@example.com", "alice@example.com,alice@example.net"),
+                pair("bob@example.com", "bob@example.com"),
+                pair("info@example.org", "carol@example.org"),
+                pair("sales@example.com", "bob@example.com"),
+                pair("@example.com", "postmaster@example.net,ops@example.net"),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleting_a_forwarder_restores_the_mailbox_mapping() {
+        let mut forwarders = Forwarders::default();
+        forwarders.catch_all.insert("example.com".to_string(), vec!["postmaster@example.net".to_string()]);
+        forwarders.forwarders.insert("alice@example.com".to_string(), vec!["alice@example.net".to_string()]);
+        let mut boxes = mailboxes(&["alice@example.com", "bob@example.com"]);
+
+        forwarders.forwarders.remove("alice@example.com");
+        assert_eq!(
+            alias_entries(&forwarders, &boxes),
+            [
+                pair("alice@example.com", "alice@example.com"),
+                pair("bob@example.com", "bob@example.com"),
+                pair("@example.com", "postmaster@example.net"),
+            ]
+        );
+
+        // A deleted mailbox falls to the catch-all, and without one nothing is left at all
+        boxes.remove("bob@example.com");
+        assert_eq!(alias_entries(&forwarders, &boxes), [pair("alice@example.com", "alice@example.com"), pair("@example.com", "postmaster@example.net")]);
+        forwarders.catch_all.remove("example.com");
+        assert!(alias_entries(&forwarders, &boxes).is_empty());
+    }
+}
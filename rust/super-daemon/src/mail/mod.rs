@@ -0,0 +1,379 @@
+//! Mailboxes served by Postfix and Dovecot.
+//!
+//! The daemon keeps its mailboxes in `/etc/supercp/mail/mailboxes.json` (and forwarders, see
+//! [`forward`], next to it) and renders the mail server's files from them after every change:
+//! Postfix's `virtual_mailbox_domains`, `virtual_mailbox_maps` and `virtual_alias_maps`
+//! tables and Dovecot's `passwd-file`, whose entries carry the password hash and quota. Mail
+//! is stored as `<vmail_dir>/<domain>/<user>/Maildir`, owned by the vmail user. All paths sit
+//! under `mail.root` from the daemon config.
+
+pub mod auth;
+pub mod autoresponder;
+pub mod crypt;
+pub mod forward;
+pub mod usage;
+
+use nix::unistd::{chown, Gid, Group, Uid};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+use crate::config::{self, MailConfig};
+use crate::dns::validate_domain;
+use crate::exec::Command;
+use forward::Forwarders;
+
+/// Serializes changes to the mail state and the files rendered from it.
+static MAIL_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The largest quota a mailbox takes, 1 PiB. Dovecot keeps quotas in signed 64-bit bytes.
+const MAX_QUOTA_MB: u64 = 1 << 30;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Mailbox {
+    /// Dovecot password hash with its scheme prefix, e.g. `{SHA512-CRYPT}$6$...`
+    pub password: String,
+    /// 0 for no quota
+    pub quota_mb: u64,
+}
+
+/// `path` under the configured mail root.
+pub fn rooted(mail: &MailConfig, path: &str) -> PathBuf {
+    Path::new(&mail.root).join(path.trim_start_matches('/'))
+}
+
+fn state_path(mail: &MailConfig) -> PathBuf {
+    rooted(mail, "/etc/supercp/mail/mailboxes.json")
+}
+
+/// Splits and checks an address, returning its lowercased local part and domain.
+pub fn split_address(address: &str) -> Result<(String, String), String> {
+    let address = address.trim().to_ascii_lowercase();
+    let (local, domain) = address.rsplit_once('@').ok_or_else(|| format!("Invalid email address '{}'", address))?;
+    let valid_local = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local.chars().all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c));
+    if !valid_local {
+        return Err(format!("Invalid email address '{}'", address));
+    }
+    validate_domain(domain).map_err(|e| e.to_string())?;
+    Ok((local.to_string(), domain.to_string()))
+}
+
+/// The mailbox's home directory, relative to the mail root; its mail is in `Maildir` below it.
+pub fn home(mail: &MailConfig, local: &str, domain: &str) -> String {
+    format!("{}/{}/{}", mail.vmail_dir.trim_end_matches('/'), domain, local)
+}
+
+pub fn load_mailboxes(mail: &MailConfig) -> Result<BTreeMap<String, Mailbox>, String> {
+    let path = state_path(mail);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid mailbox file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Writes a file through a temporary sibling, so readers never see half of it.
+pub fn write_file(path: &Path, content: &str, mode: u32) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let pending = path.with_extension("new");
+    fs::write(&pending, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    fs::set_permissions(&pending, fs::Permissions::from_mode(mode)).map_err(|e| e.to_string())?;
+    fs::rename(&pending, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn save_mailboxes(mail: &MailConfig, mailboxes: &BTreeMap<String, Mailbox>) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(mailboxes).map_err(|e| e.to_string())?;
+    write_file(&state_path(mail), &content, 0o600)
+}
+
+fn is_root() -> bool {
+    Uid::effective().is_root()
+}
+
+/// A Postfix lookup table: one `key value` line per entry.
+fn lookup_table(entries: impl IntoIterator<Item = (String, String)>) -> String {
+    entries.into_iter().map(|(key, value)| format!("{} {}\n", key, value)).collect()
+}
+
+/// The Dovecot `passwd-file` line of a mailbox.
+fn passwd_entry(mail: &MailConfig, address: &str, mailbox: &Mailbox) -> Result<String, String> {
+    let (local, domain) = split_address(address)?;
+    let mut extra = vec!["userdb_mail=maildir:~/Maildir".to_string()];
+    if mailbox.quota_mb > 0 {
+        extra.push(format!("userdb_quota_rule=*:storage={}M", mailbox.quota_mb));
+    }
+    Ok(format!(
+        "{}:{}:{}:{}::{}::{}\n",
+        address,
+        mailbox.password,
+        mail.vmail_uid,
+        mail.vmail_gid,
+        home(mail, &local, &domain),
+        extra.join(" ")
+    ))
+}
+
+/// Renders the Postfix tables and the Dovecot users file.
+fn render(mail: &MailConfig, mailboxes: &BTreeMap<String, Mailbox>, forwarders: &Forwarders) -> Result<Vec<PathBuf>, String> {
+    let mut domains: BTreeSet<String> = forward::domains(forwarders).into_iter().collect();
+    let mut maps = Vec::new();
+    let mut users = String::new();
+    for (address, mailbox) in mailboxes {
+        let (local, domain) = split_address(address)?;
+        maps.push((address.clone(), format!("{}/{}/Maildir/", domain, local)));
+        domains.insert(domain);
+        users.push_str(&passwd_entry(mail, address, mailbox)?);
+    }
+
+    let postfix_dir = rooted(mail, &mail.postfix_dir);
+    let domains_path = postfix_dir.join("virtual_mailbox_domains");
+    let maps_path = postfix_dir.join("virtual_mailbox_maps");
+    let aliases_path = postfix_dir.join("virtual_alias_maps");
+    write_file(&domains_path, &lookup_table(domains.into_iter().map(|d| (d, "OK".to_string()))), 0o644)?;
+    write_file(&maps_path, &lookup_table(maps), 0o644)?;
+    write_file(&aliases_path, &lookup_table(forward::alias_entries(forwarders, mailboxes)), 0o644)?;
+
+    // Holds password hashes: readable by Dovecot only
+    let users_path = rooted(mail, &mail.dovecot_users);
+    write_file(&users_path, &users, 0o640)?;
+    if is_root() {
+        if let Ok(Some(group)) = Group::from_name("dovecot") {
+            chown(&users_path, Some(Uid::from_raw(0)), Some(group.gid)).map_err(|e| format!("Failed to chown {}: {}", users_path.display(), e))?;
+        }
+    }
+
+    Ok(vec![domains_path, maps_path, aliases_path])
+}
+
+/// Rebuilds the Postfix tables and reloads the mail services, if `mail.apply` is on.
+async fn apply(mail: &MailConfig, tables: &[PathBuf]) -> Result<(), String> {
+    if !mail.apply {
+        return Ok(());
+    }
+    for table in tables {
+        Command::sudo("postmap")
+            .arg(format!("hash:{}", table.display()))
+            .run()
+            .await
+            .map_err(|e| format!("Failed to rebuild {}: {}", table.display(), e))?;
+    }
+    for service in ["postfix", "dovecot"] {
+        Command::sudo("systemctl").arg("reload").arg(service).run().await.map_err(|e| format!("Failed to reload {}: {}", service, e))?;
+    }
+    Ok(())
+}
+
+/// Writes the mail server's files for the mailboxes and forwarders and saves them as the new
+/// state.
+async fn commit(mail: &MailConfig, mailboxes: &BTreeMap<String, Mailbox>, forwarders: &Forwarders) -> Result<(), String> {
+    let tables = render(mail, mailboxes, forwarders)?;
+    save_mailboxes(mail, mailboxes)?;
+    forward::save(mail, forwarders)?;
+    apply(mail, &tables).await
+}
+
+/// Creates `<home>/Maildir/{cur,new,tmp}`, owned by the vmail user.
+fn create_maildir(mail: &MailConfig, local: &str, domain: &str) -> Result<PathBuf, String> {
+    let home = rooted(mail, &home(mail, local, domain));
+    let maildir = home.join("Maildir");
+    let owner = (Some(Uid::from_raw(mail.vmail_uid)), Some(Gid::from_raw(mail.vmail_gid)));
+
+    let mut dirs = vec![home.parent().map(Path::to_path_buf).unwrap_or_default(), home.clone(), maildir.clone()];
+    dirs.extend(["cur", "new", "tmp"].iter().map(|sub| maildir.join(sub)));
+    for dir in &dirs {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+        if is_root() {
+            chown(dir, owner.0, owner.1).map_err(|e| format!("Failed to chown {}: {}", dir.display(), e))?;
+        }
+    }
+    Ok(maildir)
+}
+
+/// Creates a mailbox or changes its password or quota.
+///
+/// Params: `email`, `password` (hashed with SHA512-CRYPT; may be empty for an existing
+/// mailbox to keep its password) and `quota_mb` (0 for none, at most 1 PiB; an existing
+/// mailbox keeps its quota if omitted).
+pub async fn update_email_account(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let mail = config::load()?.mail;
+    update_account(params, &mail).await
+}
+
+async fn update_account(params: &Value, mail: &MailConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let email = params["email"].as_str().ok_or("Missing email")?;
+    let password = params["password"].as_str().ok_or("Missing password")?;
+    let (local, domain) = split_address(email)?;
+    let address = format!("{}@{}", local, domain);
+
+    let _guard = MAIL_LOCK.lock().await;
+    let mut mailboxes = load_mailboxes(mail)?;
+
+    let existing = mailboxes.get(&address);
+    let password = match (password, existing) {
+        ("", Some(mailbox)) => mailbox.password.clone(),
+        ("", None) => return Err(format!("A password is required for the new mailbox {}", address).into()),
+        (password, _) if password.contains(['\n', '\r', '\0']) => return Err("The password contains a line break or NUL".into()),
+        (password, _) => crypt::hash_password(password),
+    };
+    let quota_mb = params["quota_mb"].as_u64().or(existing.map(|m| m.quota_mb)).unwrap_or(0);
+    if quota_mb > MAX_QUOTA_MB {
+        return Err(format!("A quota of {}MB is too large; the limit is {}MB", quota_mb, MAX_QUOTA_MB).into());
+    }
+
+    create_maildir(mail, &local, &domain)?;
+    mailboxes.insert(address.clone(), Mailbox { password, quota_mb });
+    commit(mail, &mailboxes, &forward::load(mail)?).await?;
+
+    Ok(format!("Email account {} updated (quota: {}MB)", address, quota_mb))
+}
+
+/// Removes a mailbox from the mail server. Its mail stays on disk unless `delete_mail` is true.
+pub async fn delete_email_account(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let email = params["email"].as_str().ok_or("Missing email")?;
+    let (local, domain) = split_address(email)?;
+    let address = format!("{}@{}", local, domain);
+
+    let mail = config::load()?.mail;
+    let _guard = MAIL_LOCK.lock().await;
+    let mut mailboxes = load_mailboxes(&mail)?;
+    if mailboxes.remove(&address).is_none() {
+        return Ok(format!("Email account {} does not exist", address));
+    }
+    // A forwarder that kept a copy in the mailbox can't deliver to it anymore
+    let mut forwarders = forward::load(&mail)?;
+    if let Some(to) = forwarders.forwarders.get_mut(&address) {
+        to.retain(|d| *d != address);
+        if to.is_empty() {
+            forwarders.forwarders.remove(&address);
+        }
+    }
+    commit(&mail, &mailboxes, &forwarders).await?;
+    autoresponder::forget(&mail, &address)?;
+
+    if params["delete_mail"].as_bool().unwrap_or(false) {
+        let home = rooted(&mail, &home(&mail, &local, &domain));
+        match fs::remove_dir_all(&home) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(format!("Email account {} deleted, but removing {} failed: {}", address, home.display(), e).into())
+            }
+            _ => return Ok(format!("Email account {} and its mail deleted", address)),
+        }
+    }
+    Ok(format!("Email account {} deleted", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(root: &Path) -> MailConfig {
+        MailConfig { root: root.display().to_string(), apply: false, ..Default::default() }
+    }
+
+    fn read(mail: &MailConfig, path: &str) -> String {
+        fs::read_to_string(rooted(mail, path)).unwrap()
+    }
+
+    /// The password hash and extra fields of a `passwd-file` line, after checking the rest.
+    fn passwd_fields(line: &str) -> (String, String) {
+        let fields: Vec<&str> = line.splitn(8, ':').collect();
+        assert_eq!(fields.len(), 8, "{}", line);
+        assert_eq!(fields[0], "alice@example.com");
+        assert_eq!(&fields[2..7], ["5000", "5000", "", "/var/mail/vhosts/example.com/alice", ""]);
+        (fields[1].to_string(), fields[7].to_string())
+    }
+
+    #[tokio::test]
+    async fn renders_the_postfix_tables_and_dovecot_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+        let mut forwarders = Forwarders::default();
+        forwarders.forwarders.insert("sales@example.org".to_string(), vec!["alice@example.com".to_string()]);
+        forwarders.catch_all.insert("example.com".to_string(), vec!["postmaster@example.net".to_string()]);
+        forward::save(&mail, &forwarders).unwrap();
+
+        let params = json!({ "email": "Alice@Example.com", "password": "secret", "quota_mb": 500 });
+        assert_eq!(update_account(&params, &mail).await.unwrap(), "Email account alice@example.com updated (quota: 500MB)");
+
+        assert_eq!(read(&mail, "/etc/postfix/virtual_mailbox_domains"), "example.com OK\nexample.org OK\n");
+        assert_eq!(read(&mail, "/etc/postfix/virtual_mailbox_maps"), "alice@example.com example.com/alice/Maildir/\n");
+        assert_eq!(
+            read(&mail, "/etc/postfix/virtual_alias_maps"),
+            "alice@example.com alice@example.com\nsales@example.org alice@example.com\n@example.com postmaster@example.net\n"
+        );
+
+        let users = read(&mail, "/etc/dovecot/users");
+        let (hash, extra) = passwd_fields(users.strip_suffix('\n').unwrap());
+        let salt_and_hash = hash.strip_prefix("{SHA512-CRYPT}$6$").unwrap();
+        assert!(salt_and_hash.split_once('$').is_some_and(|(salt, hash)| salt.len() == 16 && hash.len() == 86), "{}", hash);
+        assert_eq!(extra, "userdb_mail=maildir:~/Maildir userdb_quota_rule=*:storage=500M");
+        let mode = fs::metadata(rooted(&mail, "/etc/dovecot/users")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[tokio::test]
+    async fn empty_passwords_keep_the_current_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+
+        let missing = update_account(&json!({ "email": "alice@example.com", "password": "" }), &mail).await;
+        assert!(missing.unwrap_err().to_string().contains("A password is required"));
+
+        update_account(&json!({ "email": "alice@example.com", "password": "secret", "quota_mb": 500 }), &mail).await.unwrap();
+        let (hash, _) = passwd_fields(read(&mail, "/etc/dovecot/users").trim_end());
+
+        // Omitting the quota keeps it too
+        update_account(&json!({ "email": "alice@example.com", "password": "" }), &mail).await.unwrap();
+        assert_eq!(passwd_fields(read(&mail, "/etc/dovecot/users").trim_end()), (hash.clone(), "userdb_mail=maildir:~/Maildir userdb_quota_rule=*:storage=500M".to_string()));
+
+        update_account(&json!({ "email": "alice@example.com", "password": "", "quota_mb": 0 }), &mail).await.unwrap();
+        assert_eq!(passwd_fields(read(&mail, "/etc/dovecot/users").trim_end()), (hash.clone(), "userdb_mail=maildir:~/Maildir".to_string()));
+
+        update_account(&json!({ "email": "alice@example.com", "password": "changed" }), &mail).await.unwrap();
+        assert_ne!(passwd_fields(read(&mail, "/etc/dovecot/users").trim_end()).0, hash);
+    }
+
+    #[tokio::test]
+    async fn refuses_quotas_past_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+
+        let params = json!({ "email": "alice@example.com", "password": "secret", "quota_mb": u64::MAX });
+        let err = update_account(&params, &mail).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("A quota of {}MB is too large; the limit is 1073741824MB", u64::MAX));
+        assert!(load_mailboxes(&mail).unwrap().is_empty());
+
+        let params = json!({ "email": "alice@example.com", "password": "secret", "quota_mb": MAX_QUOTA_MB });
+        update_account(&params, &mail).await.unwrap();
+        assert!(read(&mail, "/etc/dovecot/users").contains("userdb_quota_rule=*:storage=1073741824M"));
+    }
+
+    #[test]
+    fn creates_maildirs_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = config(dir.path());
+
+        let maildir = create_maildir(&mail, "alice", "example.com").unwrap();
+        assert_eq!(maildir, dir.path().join("var/mail/vhosts/example.com/alice/Maildir"));
+        for sub in ["cur", "new", "tmp"] {
+            let mode = fs::metadata(maildir.join(sub)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700, "{}", sub);
+        }
+        // Running again over an existing maildir is fine
+        create_maildir(&mail, "alice", "example.com").unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+//! How much of its quota each mailbox uses.
+//!
+//! Usage comes from the Maildir++ `maildirsize` file when Dovecot's quota plugin keeps one,
+//! which costs a single read. Otherwise the Maildir is scanned: the inbox and every `.Folder`,
+//! taking message sizes from the `,S=<bytes>` Dovecot puts in file names where it can, so
+//! most messages don't need a `stat`.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use super::{home, load_mailboxes, rooted, split_address, Mailbox};
+use crate::config::{self, MailConfig};
+
+struct Usage {
+    bytes: u64,
+    messages: u64,
+    /// `maildirsize` or `scan`
+    source: &'static str,
+}
+
+/// Sums the size lines of a `maildirsize` file. The first line holds the quota definition;
+/// each later one a `<bytes> <messages>` change.
+fn from_maildirsize(path: &Path) -> Option<Usage> {
+    let content = fs::read_to_string(path).ok()?;
+    let (mut bytes, mut messages) = (0i64, 0i64);
+    for line in content.lines().skip(1) {
+        let mut fields = line.split_whitespace();
+        let (Some(b), Some(m)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        bytes = bytes.saturating_add(b.parse::<i64>().ok()?);
+        messages = messages.saturating_add(m.parse::<i64>().ok()?);
+    }
+    Some(Usage { bytes: bytes.max(0) as u64, messages: messages.max(0) as u64, source: "maildirsize" })
+}
+
+/// The size a Dovecot message file name records, as in `1700000000.M1P2.host,S=2048:2,S`.
+fn size_from_name(name: &str) -> Option<u64> {
+    let info = name.split(':').next()?;
+    info.split(',').find_map(|field| field.strip_prefix("S=")).and_then(|size| size.parse().ok())
+}
+
+fn scan(maildir: &Path) -> Usage {
+    let mut usage = Usage { bytes: 0, messages: 0, source: "scan" };
+    let mut folders = vec![maildir.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(maildir) {
+        folders.extend(
+            entries
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().starts_with('.') && e.path().is_dir())
+                .map(|e| e.path()),
+        );
+    }
+
+    for folder in folders {
+        for sub in ["cur", "new"] {
+            let Ok(entries) = fs::read_dir(folder.join(sub)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if name.starts_with('.') {
+                    continue;
+                }
+                let size = size_from_name(&name).or_else(|| entry.metadata().ok().filter(|m| m.is_file()).map(|m| m.len()));
+                if let Some(size) = size {
+                    usage.bytes += size;
+                    usage.messages += 1;
+                }
+            }
+        }
+    }
+    usage
+}
+
+fn describe(mail: &MailConfig, address: &str, mailbox: &Mailbox) -> Result<Value, String> {
+    let (local, domain) = split_address(address)?;
+    let maildir = rooted(mail, &home(mail, &local, &domain)).join("Maildir");
+    let usage = from_maildirsize(&maildir.join("maildirsize")).unwrap_or_else(|| scan(&maildir));
+
+    // Saturates for a state file edited past the limit `update_email_account` keeps to
+    let quota_bytes = mailbox.quota_mb.saturating_mul(1024 * 1024);
+    let percent = (quota_bytes > 0).then(|| ((usage.bytes as f64 / quota_bytes as f64) * 1000.0).round() / 10.0);
+    Ok(json!({
+        "email": address,
+        "used_bytes": usage.bytes,
+        "messages": usage.messages,
+        "quota_mb": mailbox.quota_mb,
+        "quota_bytes": quota_bytes,
+        "percent": percent,
+        "over_quota": quota_bytes > 0 && usage.bytes > quota_bytes,
+        "source": usage.source,
+    }))
+}
+
+/// Usage of several mailboxes, measured on a blocking thread.
+async fn measure(mail: MailConfig, mailboxes: BTreeMap<String, Mailbox>) -> Result<Vec<Value>, String> {
+    tokio::task::spawn_blocking(move || mailboxes.iter().map(|(address, mailbox)| describe(&mail, address, mailbox)).collect())
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Used bytes, message count and share of quota of one mailbox.
+pub async fn get_mailbox_usage(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let (local, domain) = split_address(params["email"].as_str().ok_or("Missing email")?)?;
+    let address = format!("{}@{}", local, domain);
+
+    let mail = config::load()?.mail;
+    let mailbox = load_mailboxes(&mail)?.remove(&address).ok_or_else(|| format!("Email account {} does not exist", address))?;
+    let mut usage = measure(mail, BTreeMap::from([(address, mailbox)])).await?;
+    Ok(usage.remove(0))
+}
+
+/// Usage of every mailbox, or of those in `domain`.
+pub async fn list_mailbox_usage(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().map(|d| d.trim_end_matches('.').to_ascii_lowercase());
+
+    let mail = config::load()?.mail;
+    let mut mailboxes = load_mailboxes(&mail)?;
+    if let Some(domain) = &domain {
+        mailboxes.retain(|address, _| address.rsplit_once('@').is_some_and(|(_, d)| d == domain));
+    }
+    Ok(json!(measure(mail, mailboxes).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maildirsize(content: &str) -> Option<Usage> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("maildirsize");
+        fs::write(&path, content).unwrap();
+        from_maildirsize(&path)
+    }
+
+    #[test]
+    fn sums_maildirsize_changes() {
+        let usage = maildirsize("524288000S\n4096 2\n1024 1\n-2048 -1\n\n").unwrap();
+        assert_eq!((usage.bytes, usage.messages, usage.source), (3072, 2, "maildirsize"));
+
+        // Only the quota definition: nothing stored yet
+        let usage = maildirsize("524288000S,1000C\n").unwrap();
+        assert_eq!((usage.bytes, usage.messages), (0, 0));
+
+        // Deletions recorded before the additions they undo don't go below zero
+        let usage = maildirsize("0S\n-4096 -2\n").unwrap();
+        assert_eq!((usage.bytes, usage.messages), (0, 0));
+
+        let usage = maildirsize(&format!("0S\n{} 1\n{} 1\n", i64::MAX, i64::MAX)).unwrap();
+        assert_eq!(usage.bytes, i64::MAX as u64);
+    }
+
+    #[test]
+    fn falls_back_to_a_scan_without_a_usable_maildirsize() {
+        assert!(maildirsize("0S\n4096 two\n").is_none());
+        assert!(maildirsize("0S\nlots 1\n").is_none());
+        assert!(from_maildirsize(Path::new("/nonexistent/maildirsize")).is_none());
+    }
+
+    #[test]
+    fn reads_sizes_from_dovecot_file_names() {
+        assert_eq!(size_from_name("1700000000.M1P2.host,S=2048:2,S"), Some(2048));
+        assert_eq!(size_from_name("1700000000.M1P2.host,S=2048,W=2100:2,"), Some(2048));
+        assert_eq!(size_from_name("1700000000.M1P2.host,W=2100,S=2048"), Some(2048));
+        // Flags after the colon aren't sizes, even when one reads like one
+        assert_eq!(size_from_name("1700000000.M1P2.host:2,S=5"), None);
+        assert_eq!(size_from_name("1700000000.M1P2.host"), None);
+        assert_eq!(size_from_name("1700000000.M1P2.host,S=big:2,"), None);
+    }
+
+    #[test]
+    fn scans_the_inbox_and_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let maildir = dir.path();
+        for sub in ["cur", "new", ".Sent/cur", ".Sent/new"] {
+            fs::create_dir_all(maildir.join(sub)).unwrap();
+        }
+        fs::write(maildir.join("cur/1.M1.host,S=2048:2,S"), "").unwrap();
+        fs::write(maildir.join("new/2.M2.host"), "12345").unwrap();
+        fs::write(maildir.join(".Sent/cur/3.M3.host,S=100:2,S"), "").unwrap();
+        fs::write(maildir.join("cur/.hidden"), "ignored").unwrap();
+
+        let usage = scan(maildir);
+        assert_eq!((usage.bytes, usage.messages, usage.source), (2153, 3, "scan"));
+    }
+
+    #[test]
+    fn huge_quotas_saturate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = MailConfig { root: dir.path().display().to_string(), apply: false, ..Default::default() };
+        let mailbox = Mailbox { password: String::new(), quota_mb: u64::MAX };
+        let usage = describe(&mail, "alice@example.com", &mailbox).unwrap();
+        assert_eq!(usage["quota_bytes"], u64::MAX);
+        assert_eq!(usage["over_quota"], false);
+        assert_eq!(usage["percent"], 0.0);
+    }
+}
@@ -4,20 +4,36 @@ use serde_json::{json, Value};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
-use sysinfo::{System, Disks};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+
+mod backup;
+mod config;
+mod cron;
+mod dns;
+mod exec;
+mod jobs;
+mod mail;
+mod ssl;
+mod stats;
+mod vhost;
+
+use exec::Command;
 
 struct DaemonState {
     firewall_active: bool,
+    backup_progress: HashMap<String, Value>,
 }
 
 async fn user_exists(username: &str) -> Result<bool, Box<dyn std::error::Error>> {
-    let output = std::process::Command::new("id")
+    let output = Command::new("id")
         .arg(username)
-        .output()?;
+        .output()
+        .await?;
     
-    Ok(output.status.success())
+    Ok(output.success())
 }
 
 async fn create_vhost(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
@@ -34,49 +50,40 @@ async fn create_vhost(params: &Value) -> Result<String, Box<dyn std::error::Erro
     // 1. Create directories
     let root_path = Path::new(root);
     if !root_path.exists() {
-        std::process::Command::new("sudo").arg("-n").arg("mkdir").arg("-p").arg(root).status()?;
+        Command::sudo("mkdir").arg("-p").arg(root).output().await?;
         // In a real system, we would chown to the user here
     }
 
     // 2. Load stubs
-    let nginx_stub = fs::read_to_string("/home/super/getsupercp/resources/templates/system/nginx_vhost.conf.stub")?;
+    let nginx_stub = fs::read_to_string(vhost::NGINX_STUB)?;
     let php_stub = fs::read_to_string("/home/super/getsupercp/resources/templates/system/php_fpm_pool.conf.stub")?;
 
     // 3. Replace placeholders
     let has_ssl = params["has_ssl"].as_bool().unwrap_or(false);
-    let ssl_cert = params["ssl_certificate_path"].as_str().unwrap_or("");
-    let ssl_key = params["ssl_key_path"].as_str().unwrap_or("");
-
-    let ssl_redirect = if has_ssl {
-        "return 301 https://$host$request_uri;"
+    let ssl = if has_ssl {
+        let ssl_cert = params["ssl_certificate_path"].as_str().ok_or("Missing ssl_certificate_path")?;
+        let ssl_key = params["ssl_key_path"].as_str().ok_or("Missing ssl_key_path")?;
+        Some(vhost::SslSettings::from_params(ssl_cert, ssl_key, params, None))
     } else {
-        ""
+        None
     };
 
-    let ssl_config = if has_ssl {
-        format!(
-            "listen 443 ssl;\n    ssl_certificate {};\n    ssl_certificate_key {};",
-            ssl_cert, ssl_key
-        )
-    } else {
-        "".to_string()
+    let spec = vhost::VhostSpec {
+        domain: domain.to_string(),
+        user: user.to_string(),
+        root: root.to_string(),
+        php_version: php_version.to_string(),
+        aliases: params["aliases"].as_array().into_iter().flatten().filter_map(|a| a.as_str()).map(|a| a.to_string()).collect(),
+        ssl,
     };
-
-    let nginx_conf = nginx_stub
-        .replace("{{DOMAIN}}", domain)
-        .replace("{{ALIASES}}", "")
-        .replace("{{ROOT}}", root)
-        .replace("{{PHP_VERSION}}", php_version)
-        .replace("{{USER}}", user)
-        .replace("{{SSL_REDIRECT}}", ssl_redirect)
-        .replace("{{SSL_CONFIG}}", &ssl_config);
+    let nginx_conf = spec.render(&nginx_stub);
 
     let php_conf = php_stub
         .replace("{{USER}}", user)
         .replace("{{PHP_VERSION}}", php_version);
 
     // 4. Write configs
-    let nginx_available = format!("/etc/nginx/sites-available/{}", domain);
+    let nginx_available = vhost::nginx_available(domain);
     let nginx_enabled = format!("/etc/nginx/sites-enabled/{}", domain);
     let php_pool_dir = format!("/etc/php/{}/fpm/pool.d", php_version);
     let php_pool = format!("{}/{}.conf", php_pool_dir, user);
@@ -91,22 +98,35 @@ async fn create_vhost(params: &Value) -> Result<String, Box<dyn std::error::Erro
     fs::write(&temp_nginx, nginx_conf)?;
     fs::write(&temp_php, php_conf)?;
 
-    let status = std::process::Command::new("sudo").arg("-n").arg("mv").arg(&temp_nginx).arg(&nginx_available).status()?;
-    if !status.success() {
-        return Err(format!("Failed to move Nginx config to {}. Ensure daemon has sudo access.", nginx_available).into());
-    }
+    spec.install_hsts().await?;
 
-    let status = std::process::Command::new("sudo").arg("-n").arg("ln").arg("-sf").arg(&nginx_available).arg(&nginx_enabled).status()?;
-    if !status.success() {
-        return Err(format!("Failed to enable Nginx config at {}. Ensure daemon has sudo access.", nginx_enabled).into());
-    }
+    Command::sudo("mv").arg(&temp_nginx).arg(&nginx_available).run().await.map_err(|e| {
+        format!("Failed to move Nginx config to {} ({}). Ensure daemon has sudo access.", nginx_available, e)
+    })?;
 
-    let status = std::process::Command::new("sudo").arg("-n").arg("mv").arg(&temp_php).arg(&php_pool).status()?;
-    if !status.success() {
-        return Err(format!("Failed to move PHP pool config to {}. Ensure daemon has sudo access.", php_pool).into());
-    }
+    Command::sudo("ln").arg("-sf").arg(&nginx_available).arg(&nginx_enabled).run().await.map_err(|e| {
+        format!("Failed to enable Nginx config at {} ({}). Ensure daemon has sudo access.", nginx_enabled, e)
+    })?;
+
+    Command::sudo("mv").arg(&temp_php).arg(&php_pool).run().await.map_err(|e| {
+        format!("Failed to move PHP pool config to {} ({}). Ensure daemon has sudo access.", php_pool, e)
+    })?;
 
     reload_services().await?;
+    spec.save()?;
+
+    // 5. Optionally give the site its DNS zone from a template
+    let dns_template = match &params["dns_template"] {
+        Value::String(name) => Some(name.as_str()),
+        Value::Bool(true) => Some(dns::template::DEFAULT_TEMPLATE),
+        _ => None,
+    };
+    if let Some(template) = dns_template {
+        let request = json!({ "domain": domain, "template": template, "variables": params["dns_variables"] });
+        dns::template::apply_dns_template(&request)
+            .await
+            .map_err(|e| format!("VHost created for {}, but applying DNS template '{}' failed: {}", domain, template, e))?;
+    }
 
     Ok(format!("VHost created for {}. Configs: {}, {}", domain, nginx_available, php_pool))
 }
@@ -120,9 +140,11 @@ async fn delete_vhost(params: &Value) -> Result<String, Box<dyn std::error::Erro
     let nginx_enabled = format!("/etc/nginx/sites-enabled/{}", domain);
     let php_pool = format!("/etc/php/{}/fpm/pool.d/{}.conf", php_version, user);
 
-    std::process::Command::new("sudo").arg("-n").arg("rm").arg("-f").arg(&nginx_enabled).status()?;
-    std::process::Command::new("sudo").arg("-n").arg("rm").arg("-f").arg(&nginx_available).status()?;
-    std::process::Command::new("sudo").arg("-n").arg("rm").arg("-f").arg(&php_pool).status()?;
+    Command::sudo("rm").arg("-f").arg(&nginx_enabled).output().await?;
+    Command::sudo("rm").arg("-f").arg(&nginx_available).output().await?;
+    Command::sudo("rm").arg("-f").arg(&php_pool).output().await?;
+    Command::sudo("rm").arg("-f").arg(vhost::hsts_snippet(domain)).output().await?;
+    vhost::remove_spec(domain)?;
 
     reload_services().await?;
 
@@ -134,13 +156,11 @@ async fn list_vhosts() -> Result<Value, Box<dyn std::error::Error>> {
     let mut domains = Vec::new();
 
     if let Ok(entries) = fs::read_dir(nginx_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
-                    if name != "default" {
-                        domains.push(name.to_string());
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+                if name != "default" {
+                    domains.push(name.to_string());
                 }
             }
         }
@@ -150,17 +170,21 @@ async fn list_vhosts() -> Result<Value, Box<dyn std::error::Error>> {
 }
 
 async fn get_status() -> Result<Value, Box<dyn std::error::Error>> {
-    let services = vec!["nginx", "php8.4-fpm", "mysql", "redis-server"];
+    let services = ["nginx", "php8.4-fpm", "mysql", "redis-server"];
     let mut status = serde_json::Map::new();
 
-    for service in services {
-        let output = std::process::Command::new("systemctl")
+    let checks = services.iter().map(|service| {
+        Command::new("systemctl")
             .arg("is-active")
             .arg(service)
-            .output();
+            .timeout(Duration::from_secs(5))
+            .output()
+    });
+    let results = futures::future::join_all(checks).await;
 
+    for (service, output) in services.iter().zip(results) {
         let is_active = match output {
-            Ok(out) => String::from_utf8_lossy(&out.stdout).trim() == "active",
+            Ok(out) => out.stdout.trim() == "active",
             Err(_) => false,
         };
 
@@ -168,6 +192,7 @@ async fn get_status() -> Result<Value, Box<dyn std::error::Error>> {
     }
 
     status.insert("daemon".to_string(), json!("running"));
+    status.insert("ssl".to_string(), ssl::renew::status());
     
     Ok(Value::Object(status))
 }
@@ -176,133 +201,33 @@ async fn restart_service(params: &Value) -> Result<String, Box<dyn std::error::E
     let service = params["service"].as_str().ok_or("Missing service")?;
     
     // Security: only allow specific services
-    let allowed = vec!["nginx", "php8.4-fpm", "mysql", "redis-server"];
+    let allowed = ["nginx", "php8.4-fpm", "mysql", "redis-server"];
     if !allowed.contains(&service) {
         return Err("Service not allowed".into());
     }
 
-    let status = std::process::Command::new("systemctl")
+    Command::new("systemctl")
         .arg("restart")
         .arg(service)
-        .status()?;
-
-    if status.success() {
-        Ok(format!("Service {} restarted successfully", service))
-    } else {
-        Err(format!("Failed to restart service {}", service).into())
-    }
-}
-
-async fn create_backup(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let name = params["name"].as_str().ok_or("Missing name")?;
-    let source_path = params["source_path"].as_str().ok_or("Missing source_path")?;
-    let backup_dir = "/var/lib/supercp/backups";
-    fs::create_dir_all(backup_dir)?;
-
-    let target_path = format!("{}/{}.tar.gz", backup_dir, name);
-
-    // In a real system, we would use tar crate or Command::new("tar")
-    let status = std::process::Command::new("tar")
-        .arg("-czf")
-        .arg(&target_path)
-        .arg("-C")
-        .arg(Path::new(source_path).parent().unwrap_or(Path::new("/")))
-        .arg(Path::new(source_path).file_name().unwrap_or_default())
-        .status()?;
-
-    if status.success() {
-        // Ensure the web server can read the backup for download
-        let _ = fs::set_permissions(&target_path, fs::Permissions::from_mode(0o644));
-        Ok(target_path)
-    } else {
-        Err("Failed to create backup archive".into())
-    }
-}
-
-async fn create_db_backup(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let db_name = params["db_name"].as_str().ok_or("Missing db_name")?;
-    let backup_dir = "/var/lib/supercp/backups";
-    fs::create_dir_all(backup_dir)?;
-
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs();
-    let target_path = format!("{}/{}_{}.sql", backup_dir, db_name, timestamp);
-
-    // Use mysqldump. Since we are root, we can dump any database.
-    let output = std::process::Command::new("mysqldump")
-        .arg(db_name)
-        .output()?;
-
-    if output.status.success() {
-        fs::write(&target_path, output.stdout)?;
-        // Ensure the web server can read the backup for download
-        let _ = fs::set_permissions(&target_path, fs::Permissions::from_mode(0o644));
-        Ok(target_path)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to create database backup: {}", error).into())
-    }
-}
-
-async fn restore_backup(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let path = params["path"].as_str().ok_or("Missing path")?;
-    let target_path = params["target_path"].as_str().ok_or("Missing target_path")?;
-
-    if !Path::new(path).exists() {
-        return Err("Backup file not found".into());
-    }
+        .run()
+        .await
+        .map_err(|e| format!("Failed to restart service {}: {}", service, e))?;
 
-    let status = std::process::Command::new("tar")
-        .arg("-xzf")
-        .arg(path)
-        .arg("-C")
-        .arg(target_path)
-        .status()?;
-
-    if status.success() {
-        Ok(format!("Backup restored to {}", target_path))
-    } else {
-        Err("Failed to restore backup archive".into())
-    }
-}
-
-async fn restore_db_backup(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let path = params["path"].as_str().ok_or("Missing path")?;
-    let db_name = params["db_name"].as_str().ok_or("Missing db_name")?;
-
-    if !Path::new(path).exists() {
-        return Err("Backup file not found".into());
-    }
-
-    // Restore using mysql command
-    let file = std::fs::File::open(path)?;
-    let status = std::process::Command::new("mysql")
-        .arg(db_name)
-        .stdin(std::process::Stdio::from(file))
-        .status()?;
-    
-    if status.success() {
-        Ok(format!("Database {} restored from {}", db_name, path))
-    } else {
-        Err(format!("Failed to restore database {}", db_name).into())
-    }
+    Ok(format!("Service {} restarted successfully", service))
 }
 
 async fn reload_services() -> Result<String, Box<dyn std::error::Error>> {
-    let nginx_status = std::process::Command::new("sudo")
-        .arg("-n")
-        .arg("systemctl")
+    let nginx_status = Command::sudo("systemctl")
         .arg("reload")
         .arg("nginx")
-        .status()?;
+        .output()
+        .await?;
         
-    let php_status = std::process::Command::new("sudo")
-        .arg("-n")
-        .arg("systemctl")
+    let php_status = Command::sudo("systemctl")
         .arg("reload")
         .arg("php8.4-fpm")
-        .status()?;
+        .output()
+        .await?;
     
     if nginx_status.success() && php_status.success() {
         Ok("Services reloaded successfully".to_string())
@@ -311,8 +236,22 @@ async fn reload_services() -> Result<String, Box<dyn std::error::Error>> {
     }
 }
 
+/// MySQL's rules for database names, less the characters that would break out of the quoting
+/// the names get in SQL here.
+fn validate_db_name(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if name.is_empty()
+        || name.chars().count() > 64
+        || name.ends_with(' ')
+        || name.chars().any(|c| c.is_control() || matches!(c, '`' | '\'' | '"' | '\\' | '/' | '.'))
+    {
+        return Err(format!("Invalid database name: {}", name).into());
+    }
+    Ok(())
+}
+
 async fn create_database(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
     let name = params["name"].as_str().ok_or("Missing name")?;
+    validate_db_name(name)?;
     let user = params["user"].as_str().ok_or("Missing user")?;
     let password = params["password"].as_str().ok_or("Missing password")?;
     let db_type = params["type"].as_str().unwrap_or("mysql");
@@ -322,14 +261,12 @@ async fn create_database(params: &Value) -> Result<String, Box<dyn std::error::E
     }
 
     // 1. Create Database
-    let status = std::process::Command::new("mysql")
+    Command::new("mysql")
         .arg("-e")
         .arg(format!("CREATE DATABASE IF NOT EXISTS `{}`", name))
-        .status()?;
-
-    if !status.success() {
-        return Err(format!("Failed to create database {}", name).into());
-    }
+        .run()
+        .await
+        .map_err(|e| format!("Failed to create database {}: {}", name, e))?;
 
     // 2. Create User and Grant Privileges
     // We use 'localhost' for now. In a real system, this might be configurable.
@@ -340,14 +277,12 @@ async fn create_database(params: &Value) -> Result<String, Box<dyn std::error::E
         user, password, name, user
     );
 
-    let status = std::process::Command::new("mysql")
-        .arg("-e")
-        .arg(sql)
-        .status()?;
-
-    if !status.success() {
-        return Err(format!("Failed to create user or grant privileges for {}", user).into());
-    }
+    // The SQL carries the password, so it goes over stdin rather than the process list
+    Command::new("mysql")
+        .stdin(sql)
+        .run()
+        .await
+        .map_err(|e| format!("Failed to create user or grant privileges for {}: {}", user, e))?;
 
     // 3. Save metadata
     let db_dir = "/var/lib/supercp/databases";
@@ -369,14 +304,12 @@ async fn delete_database(params: &Value) -> Result<String, Box<dyn std::error::E
     let name = params["name"].as_str().ok_or("Missing name")?;
     
     // 1. Drop Database
-    let status = std::process::Command::new("mysql")
+    Command::new("mysql")
         .arg("-e")
         .arg(format!("DROP DATABASE IF EXISTS `{}`", name))
-        .status()?;
-
-    if !status.success() {
-        return Err(format!("Failed to drop database {}", name).into());
-    }
+        .run()
+        .await
+        .map_err(|e| format!("Failed to drop database {}: {}", name, e))?;
 
     // Note: We don't automatically drop the user because multiple databases might use the same user.
     // In a more advanced system, we would track user-database relationships.
@@ -395,13 +328,11 @@ async fn list_databases() -> Result<Value, Box<dyn std::error::Error>> {
     let mut dbs = Vec::new();
 
     if let Ok(entries) = fs::read_dir(db_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
-                        dbs.push(name.to_string());
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    dbs.push(name.to_string());
                 }
             }
         }
@@ -452,13 +383,11 @@ async fn list_ftp_users() -> Result<Value, Box<dyn std::error::Error>> {
     let mut users = Vec::new();
 
     if let Ok(entries) = fs::read_dir(ftp_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
-                        users.push(name.to_string());
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    users.push(name.to_string());
                 }
             }
         }
@@ -475,23 +404,20 @@ async fn get_database_size(params: &Value) -> Result<u64, Box<dyn std::error::Er
         name
     );
 
-    let output = std::process::Command::new("mysql")
+    let output = Command::new("mysql")
         .arg("-N")
         .arg("-s")
         .arg("-e")
         .arg(sql)
-        .output()?;
-
-    if output.status.success() {
-        let size_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        if size_str == "NULL" || size_str.is_empty() {
-            Ok(0)
-        } else {
-            Ok(size_str.parse::<u64>().unwrap_or(0))
-        }
+        .run()
+        .await
+        .map_err(|e| format!("Failed to get database size: {}", e))?;
+
+    let size_str = output.stdout.trim();
+    if size_str == "NULL" || size_str.is_empty() {
+        Ok(0)
     } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to get database size: {}", error).into())
+        Ok(size_str.parse::<u64>().unwrap_or(0))
     }
 }
 
@@ -503,242 +429,17 @@ async fn get_directory_size(params: &Value) -> Result<u64, Box<dyn std::error::E
         return Err("Path does not exist".into());
     }
 
-    let output = std::process::Command::new("du")
+    // Large trees can take minutes to walk; callers wanting an answer sooner should submit a job
+    let output = Command::new("du")
         .arg("-sb")
         .arg(&target_path)
-        .output()?;
+        .timeout(Duration::from_secs(30 * 60))
+        .run()
+        .await
+        .map_err(|e| format!("Failed to get directory size: {}", e))?;
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let size = stdout.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0);
-        Ok(size)
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to get directory size: {}", error).into())
-    }
-}
-
-async fn update_cron_jobs(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let user = params["user"].as_str().ok_or("Missing user")?;
-    let jobs = params["jobs"].as_array().ok_or("Missing jobs")?;
-
-    let cron_dir = "/var/spool/supercp/cron";
-    fs::create_dir_all(cron_dir)?;
-
-    let cron_path = format!("{}/{}.cron", cron_dir, user);
-    let mut content = String::new();
-
-    for job in jobs {
-        let command = job["command"].as_str().unwrap_or("");
-        let schedule = job["schedule"].as_str().unwrap_or("");
-        content.push_str(&format!("{} {}\n", schedule, command));
-    }
-
-    fs::write(&cron_path, content)?;
-
-    // Apply the crontab for the user
-    let status = std::process::Command::new("crontab")
-        .arg("-u")
-        .arg(user)
-        .arg(&cron_path)
-        .status()?;
-
-    if status.success() {
-        Ok(format!("Cron jobs updated and applied for {}", user))
-    } else {
-        Err(format!("Failed to apply crontab for {}", user).into())
-    }
-}
-
-async fn list_cron_jobs(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
-    let user = params["user"].as_str().ok_or("Missing user")?;
-    let cron_path = format!("/var/spool/supercp/cron/{}.cron", user);
-
-    if !Path::new(&cron_path).exists() {
-        return Ok(json!([]));
-    }
-
-    let content = fs::read_to_string(&cron_path)?;
-    let mut jobs = Vec::new();
-
-    for line in content.lines() {
-        if !line.trim().is_empty() {
-            jobs.push(line.to_string());
-        }
-    }
-
-    Ok(json!(jobs))
-}
-
-async fn update_dns_zone(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let domain = params["domain"].as_str().ok_or("Missing domain")?;
-    let records = params["records"].as_array().ok_or("Missing records")?;
-
-    let dns_dir = "/etc/supercp/dns";
-    fs::create_dir_all(dns_dir)?;
-
-    let zone_path = format!("{}/{}.zone", dns_dir, domain);
-    let mut content = format!("$ORIGIN {}.\n", domain);
-    content.push_str("$TTL 3600\n");
-    content.push_str("@ IN SOA ns1.supercp.com. admin.supercp.com. ( 2026010301 3600 600 1209600 3600 )\n");
-
-    for record in records {
-        let name = record["name"].as_str().unwrap_or("@");
-        let rtype = record["type"].as_str().unwrap_or("A");
-        let value = record["value"].as_str().unwrap_or("");
-        let ttl = record["ttl"].as_u64().unwrap_or(3600);
-        let priority = record["priority"].as_u64();
-
-        let line = if let Some(p) = priority {
-            format!("{} {} IN {} {} {}\n", name, ttl, rtype, p, value)
-        } else {
-            format!("{} {} IN {} {}\n", name, ttl, rtype, value)
-        };
-        content.push_str(&line);
-    }
-
-    fs::write(&zone_path, content)?;
-
-    Ok(format!("DNS zone updated for {}", domain))
-}
-
-async fn delete_dns_zone(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let domain = params["domain"].as_str().ok_or("Missing domain")?;
-    let zone_path = format!("/etc/supercp/dns/{}.zone", domain);
-
-    if Path::new(&zone_path).exists() {
-        fs::remove_file(&zone_path)?;
-    }
-
-    Ok(format!("DNS zone deleted for {}", domain))
-}
-
-async fn request_ssl_cert(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let domain = params["domain"].as_str().ok_or("Missing domain")?;
-    let email = params["email"].as_str().unwrap_or("admin@example.com");
-    
-    // Request certificate via Let's Encrypt using certbot
-    let output = std::process::Command::new("sudo")
-        .arg("-n")
-        .arg("certbot")
-        .arg("certonly")
-        .arg("--non-interactive")
-        .arg("--agree-tos")
-        .arg("--nginx")
-        .arg("-d")
-        .arg(domain)
-        .arg("-m")
-        .arg(email)
-        .arg("--register-unsafely-without-email")
-        .output()?;
-    
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        // If cert already exists, that's fine
-        if !error.contains("Certificate not due for renewal") && !error.contains("Cert already exists") {
-            return Err(format!("Failed to request SSL certificate for {}: {}", domain, error).into());
-        }
-    }
-    
-    // Verify the certificate was created
-    let cert_dir = format!("/etc/letsencrypt/live/{}", domain);
-    let cert_path = format!("{}/fullchain.pem", cert_dir);
-    let key_path = format!("{}/privkey.pem", cert_dir);
-    
-    if !Path::new(&cert_path).exists() || !Path::new(&key_path).exists() {
-        return Err(format!("Certificate files not found after certbot execution for {}", domain).into());
-    }
-
-    Ok(format!("SSL certificate requested and configured for {} via Let's Encrypt", domain))
-}
-
-async fn update_email_account(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let email = params["email"].as_str().ok_or("Missing email")?;
-    let _password = params["password"].as_str().ok_or("Missing password")?;
-    let quota_mb = params["quota_mb"].as_u64().unwrap_or(0);
-
-    let email_dir = "/var/mail/supercp";
-    fs::create_dir_all(email_dir)?;
-
-    let account_path = format!("{}/{}.json", email_dir, email);
-    let meta = json!({
-        "email": email,
-        "quota_mb": quota_mb,
-    });
-
-    fs::write(&account_path, serde_json::to_string_pretty(&meta)?)?;
-
-    // In a real system, we would:
-    // 1. Update Dovecot/Postfix virtual user maps
-    // 2. Create maildir: /var/mail/vhosts/domain/user
-    // 3. Set quota in Dovecot
-    
-    Ok(format!("Email account {} updated (quota: {}MB)", email, quota_mb))
-}
-
-async fn delete_email_account(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
-    let email = params["email"].as_str().ok_or("Missing email")?;
-    let account_path = format!("/var/mail/supercp/{}.json", email);
-
-    if Path::new(&account_path).exists() {
-        fs::remove_file(&account_path)?;
-    }
-
-    // In a real system, we would also remove the maildir and update maps
-    Ok(format!("Email account {} deleted", email))
-}
-
-async fn get_system_stats() -> Result<Value, Box<dyn std::error::Error>> {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    
-    let mut networks = sysinfo::Networks::new_with_refreshed_list();
-
-    // Wait a bit for CPU usage to be calculated correctly
-    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-    sys.refresh_cpu_all();
-    networks.refresh(true);
-
-    let cpu_usage = sys.global_cpu_usage();
-    
-    let memory = json!({
-        "total": sys.total_memory() / 1024 / 1024, // MB
-        "used": sys.used_memory() / 1024 / 1024,
-        "free": sys.free_memory() / 1024 / 1024,
-    });
-
-    let mut disks_info = Vec::new();
-    let disks = Disks::new_with_refreshed_list();
-    for disk in &disks {
-        disks_info.push(json!({
-            "name": disk.name().to_string_lossy(),
-            "mount_point": disk.mount_point().to_string_lossy(),
-            "total": disk.total_space() / 1024 / 1024, // MB
-            "available": disk.available_space() / 1024 / 1024,
-        }));
-    }
-
-    let mut network_info = Vec::new();
-    for (interface_name, data) in &networks {
-        network_info.push(json!({
-            "interface": interface_name,
-            "received": data.received(),
-            "transmitted": data.transmitted(),
-            "total_received": data.total_received(),
-            "total_transmitted": data.total_transmitted(),
-        }));
-    }
-
-    let load_avg = System::load_average();
-
-    Ok(json!({
-        "cpu_usage": cpu_usage,
-        "memory": memory,
-        "disks": disks_info,
-        "networks": network_info,
-        "uptime": System::uptime(),
-        "load_average": [load_avg.one, load_avg.five, load_avg.fifteen]
-    }))
+    let size = output.stdout.split_whitespace().next().unwrap_or("0").parse::<u64>().unwrap_or(0);
+    Ok(size)
 }
 
 fn resolve_safe_path(path_str: &str) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
@@ -766,18 +467,16 @@ async fn list_files(params: &Value) -> Result<Value, Box<dyn std::error::Error>>
 
     let mut files = Vec::new();
     if let Ok(entries) = fs::read_dir(target_path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let metadata = entry.metadata()?;
-                let file_type = if metadata.is_dir() { "directory" } else { "file" };
-                files.push(json!({
-                    "name": entry.file_name().to_string_lossy(),
-                    "type": file_type,
-                    "size": metadata.len(),
-                    "modified": metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
-                    "permissions": format!("{:o}", metadata.permissions().mode() & 0o777),
-                }));
-            }
+        for entry in entries.flatten() {
+            let metadata = entry.metadata()?;
+            let file_type = if metadata.is_dir() { "directory" } else { "file" };
+            files.push(json!({
+                "name": entry.file_name().to_string_lossy(),
+                "type": file_type,
+                "size": metadata.len(),
+                "modified": metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+                "permissions": format!("{:o}", metadata.permissions().mode() & 0o777),
+            }));
         }
     }
 
@@ -853,22 +552,18 @@ async fn get_logs(params: &Value) -> Result<String, Box<dyn std::error::Error>>
     }
 
     // Use 'tail' command for efficient reading of the end of the file
-    let output = std::process::Command::new("tail")
+    let output = Command::new("tail")
         .arg("-n")
         .arg(lines.to_string())
         .arg(log_path)
-        .output()?;
-
-    if output.status.success() {
-        let result = String::from_utf8_lossy(&output.stdout).to_string();
-        if result.is_empty() {
-            Ok("Log is empty".to_string())
-        } else {
-            Ok(result)
-        }
+        .run()
+        .await
+        .map_err(|e| format!("Failed to read logs: {}", e))?;
+
+    if output.stdout.is_empty() {
+        Ok("Log is empty".to_string())
     } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to read logs: {}", error).into())
+        Ok(output.stdout)
     }
 }
 
@@ -889,19 +584,15 @@ async fn get_service_logs(params: &Value) -> Result<String, Box<dyn std::error::
     }
 
     // Use 'tail' command for efficient reading
-    let output = std::process::Command::new("tail")
+    let output = Command::new("tail")
         .arg("-n")
         .arg(lines.to_string())
         .arg(log_path)
-        .output()?;
+        .run()
+        .await
+        .map_err(|e| format!("Failed to read service logs: {}", e))?;
 
-    if output.status.success() {
-        let result = String::from_utf8_lossy(&output.stdout).to_string();
-        Ok(if result.is_empty() { "Log is empty".to_string() } else { result })
-    } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to read service logs: {}", error).into())
-    }
+    Ok(if output.stdout.is_empty() { "Log is empty".to_string() } else { output.stdout })
 }
 
 async fn apply_firewall_rule(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
@@ -910,22 +601,17 @@ async fn apply_firewall_rule(params: &Value) -> Result<String, Box<dyn std::erro
     let action = params["action"].as_str().unwrap_or("allow");
     let source = params["source"].as_str().unwrap_or("any");
 
-    let mut cmd = std::process::Command::new("sudo");
-    cmd.arg("-n").arg("ufw").arg(action);
+    let mut cmd = Command::sudo("ufw").arg(action);
     
     if source != "any" {
-        cmd.arg("from").arg(source);
+        cmd = cmd.arg("from").arg(source);
     }
     
-    cmd.arg("to").arg("any").arg("port").arg(port.to_string()).arg("proto").arg(protocol);
+    cmd = cmd.arg("to").arg("any").arg("port").arg(port.to_string()).arg("proto").arg(protocol);
 
-    let status = cmd.status()?;
+    cmd.run().await.map_err(|e| format!("Failed to apply firewall rule for port {}: {}", port, e))?;
     
-    if status.success() {
-        Ok(format!("Firewall rule applied: {} {}/{} from {}", action, port, protocol, source))
-    } else {
-        Err(format!("Failed to apply firewall rule for port {}", port).into())
-    }
+    Ok(format!("Firewall rule applied: {} {}/{} from {}", action, port, protocol, source))
 }
 
 async fn delete_firewall_rule(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
@@ -933,35 +619,29 @@ async fn delete_firewall_rule(params: &Value) -> Result<String, Box<dyn std::err
     let protocol = params["protocol"].as_str().unwrap_or("tcp");
     let action = params["action"].as_str().unwrap_or("allow");
 
-    let status = std::process::Command::new("sudo")
-        .arg("-n")
-        .arg("ufw")
+    Command::sudo("ufw")
         .arg("delete")
         .arg(action)
         .arg(format!("{}/{}", port, protocol))
-        .status()?;
+        .run()
+        .await
+        .map_err(|e| format!("Failed to delete firewall rule for port {}: {}", port, e))?;
 
-    if status.success() {
-        Ok(format!("Firewall rule deleted: {} {}/{}", action, port, protocol))
-    } else {
-        Err(format!("Failed to delete firewall rule for port {}", port).into())
-    }
+    Ok(format!("Firewall rule deleted: {} {}/{}", action, port, protocol))
 }
 
 async fn toggle_firewall(params: &Value, state: Arc<Mutex<DaemonState>>) -> Result<String, Box<dyn std::error::Error>> {
     let enable = params["enable"].as_bool().ok_or("Missing enable parameter")?;
     
-    let mut cmd = std::process::Command::new("sudo");
-    cmd.arg("-n").arg("ufw");
-    if enable {
-        cmd.arg("--force").arg("enable");
+    let cmd = if enable {
+        Command::sudo("ufw").arg("--force").arg("enable")
     } else {
-        cmd.arg("disable");
-    }
+        Command::sudo("ufw").arg("disable")
+    };
     
-    let status = cmd.status()?;
+    let output = cmd.output().await?;
     
-    if status.success() {
+    if output.success() {
         let mut state = state.lock().await;
         state.firewall_active = enable;
         Ok(format!("Firewall {}", if enable { "enabled" } else { "disabled" }))
@@ -971,8 +651,8 @@ async fn toggle_firewall(params: &Value, state: Arc<Mutex<DaemonState>>) -> Resu
 }
 
 async fn get_firewall_status(state: Arc<Mutex<DaemonState>>) -> Result<Value, Box<dyn std::error::Error>> {
-    let output = std::process::Command::new("sudo").arg("-n").arg("ufw").arg("status").output()?;
-    let status_str = String::from_utf8_lossy(&output.stdout);
+    let output = Command::sudo("ufw").arg("status").output().await?;
+    let status_str = output.stdout;
     
     let active = status_str.contains("Status: active");
     
@@ -1007,8 +687,28 @@ async fn get_firewall_status(state: Arc<Mutex<DaemonState>>) -> Result<Value, Bo
     }))
 }
 
+/// Runs a long-running method on behalf of the job manager.
+async fn execute_job(method: &str, params: &Value, state: Arc<Mutex<DaemonState>>) -> Result<Value, Box<dyn std::error::Error>> {
+    match method {
+        "create_backup" => Ok(json!(backup::create_backup(params).await?)),
+        "create_db_backup" => Ok(json!(backup::create_db_backup(params, state).await?)),
+        "restore_backup" => backup::restore::restore_backup(params).await,
+        "restore_db_backup" => backup::restore::restore_db_backup(params, state).await,
+        "get_directory_size" => Ok(json!(get_directory_size(params).await?)),
+        "request_ssl_cert" => Ok(json!(ssl::request_ssl_cert(params).await?)),
+        "run_cron_job_now" => cron::run_cron_job_now(params).await,
+        _ => Err(format!("Method {} cannot run as a job", method).into()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Cron runs installed jobs through this binary so each run is recorded
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some(cron::runner::COMMAND) {
+        std::process::exit(cron::runner::run(&args[2..]).await);
+    }
+
     let socket_path = "/home/super/getsupercp/storage/framework/sockets/super-daemon.sock";
 
     // Clean up existing socket
@@ -1022,11 +722,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Super Daemon listening on {}", socket_path);
 
-    let state = Arc::new(Mutex::new(DaemonState { firewall_active: true }));
+    let state = Arc::new(Mutex::new(DaemonState {
+        firewall_active: true,
+        backup_progress: HashMap::new(),
+    }));
+    let jobs = Arc::new(jobs::JobManager::load(jobs::JOBS_DIR));
+    tokio::spawn(dns::dnssec::resign_loop());
+    tokio::spawn(ssl::renew::renew_loop());
 
     loop {
         let (stream, _) = listener.accept().await?;
         let state = Arc::clone(&state);
+        let jobs = Arc::clone(&jobs);
         
         tokio::spawn(async move {
             let (reader, mut writer) = tokio::io::split(stream);
@@ -1036,8 +743,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             if reader.read_line(&mut line).await.is_ok() {
                 let req: Value = serde_json::from_str(&line).unwrap_or(Value::Null);
                 let method = req["method"].as_str().unwrap_or("");
+                // Long methods outlive the client's socket timeout, so they run as jobs unless
+                // the caller asks to wait with "async": false
+                let run_as_job = jobs::LONG_RUNNING_METHODS.contains(&method)
+                    && req["params"]["async"].as_bool() != Some(false);
                 
                 let response = match method {
+                    _ if run_as_job => {
+                        match jobs.submit(method, req["params"].clone(), Arc::clone(&state)).await {
+                            Ok(job_id) => json!({"jsonrpc": "2.0", "result": {"job_id": job_id}, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
                     "ping" => json!({"jsonrpc": "2.0", "result": "pong", "id": req["id"]}),
                     "create_vhost" => {
                         match create_vhost(&req["params"]).await {
@@ -1070,26 +787,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     },
                     "create_backup" => {
-                        match create_backup(&req["params"]).await {
+                        match backup::create_backup(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "create_db_backup" => {
-                        match create_db_backup(&req["params"]).await {
+                        match backup::create_db_backup(&req["params"], Arc::clone(&state)).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
+                    "get_backup_progress" => {
+                        match backup::get_backup_progress(&req["params"], Arc::clone(&state)).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_backups" => {
+                        match backup::catalog::list_backups(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "verify_backup" => {
+                        match backup::catalog::verify_backup(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "preview_backup" => {
+                        match backup::restore::preview_backup(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
                     "restore_backup" => {
-                        match restore_backup(&req["params"]).await {
-                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                        match backup::restore::restore_backup(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "restore_db_backup" => {
-                        match restore_db_backup(&req["params"]).await {
-                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                        match backup::restore::restore_db_backup(&req["params"], Arc::clone(&state)).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
@@ -1148,49 +889,187 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     },
                     "update_cron_jobs" => {
-                        match update_cron_jobs(&req["params"]).await {
+                        match cron::update_cron_jobs(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "list_cron_jobs" => {
-                        match list_cron_jobs(&req["params"]).await {
+                        match cron::list_cron_jobs(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "get_cron_history" => {
+                        match cron::history::get_cron_history(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "run_cron_job_now" => {
+                        match cron::run_cron_job_now(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "describe_cron_schedule" => {
+                        match cron::describe_cron_schedule(&req["params"]).await {
                             Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "update_dns_zone" => {
-                        match update_dns_zone(&req["params"]).await {
+                        match dns::update_dns_zone(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
+                    "get_dns_zone" => {
+                        match dns::get_dns_zone(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_dns_zones" => {
+                        match dns::list_dns_zones(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
                     "delete_dns_zone" => {
-                        match delete_dns_zone(&req["params"]).await {
+                        match dns::delete_dns_zone(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "enable_dnssec" => {
+                        match dns::dnssec::enable_dnssec(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "disable_dnssec" => {
+                        match dns::dnssec::disable_dnssec(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "get_dnssec_ds" => {
+                        match dns::dnssec::get_dnssec_ds(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "apply_dns_template" => {
+                        match dns::template::apply_dns_template(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_certificates" => {
+                        match ssl::list_certificates(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "install_custom_certificate" => {
+                        match ssl::custom::install_custom_certificate(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "request_ssl_cert" => {
-                        match request_ssl_cert(&req["params"]).await {
+                        match ssl::request_ssl_cert(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "update_email_account" => {
-                        match update_email_account(&req["params"]).await {
+                        match mail::update_email_account(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "delete_email_account" => {
-                        match delete_email_account(&req["params"]).await {
+                        match mail::delete_email_account(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "create_email_forwarder" => {
+                        match mail::forward::create_email_forwarder(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "delete_email_forwarder" => {
+                        match mail::forward::delete_email_forwarder(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_email_forwarders" => {
+                        match mail::forward::list_email_forwarders(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "set_email_catch_all" => {
+                        match mail::forward::set_email_catch_all(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "generate_dkim_key" => {
+                        match mail::auth::generate_dkim_key(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "generate_spf_record" => {
+                        match mail::auth::generate_spf_record(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "generate_dmarc_record" => {
+                        match mail::auth::generate_dmarc_record(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "get_mailbox_usage" => {
+                        match mail::usage::get_mailbox_usage(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_mailbox_usage" => {
+                        match mail::usage::list_mailbox_usage(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "set_autoresponder" => {
+                        match mail::autoresponder::set_autoresponder(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "get_autoresponder" => {
+                        match mail::autoresponder::get_autoresponder(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "delete_autoresponder" => {
+                        match mail::autoresponder::delete_autoresponder(&req["params"]).await {
                             Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
                     "get_system_stats" => {
-                        match get_system_stats().await {
+                        match stats::get_system_stats().await {
                             Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
@@ -1267,6 +1146,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
                         }
                     },
+                    "get_job" => {
+                        match jobs.get_job(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "list_jobs" => {
+                        match jobs.list_jobs(&req["params"]).await {
+                            Ok(data) => json!({"jsonrpc": "2.0", "result": data, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
+                    "cancel_job" => {
+                        match jobs.cancel_job(&req["params"]).await {
+                            Ok(msg) => json!({"jsonrpc": "2.0", "result": msg, "id": req["id"]}),
+                            Err(e) => json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}, "id": req["id"]}),
+                        }
+                    },
                     _ => json!({"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": req["id"]}),
                 };
 
@@ -0,0 +1,337 @@
+//! ACME v2 client (RFC 8555).
+//!
+//! Requests are signed with the account's P-256 key (ES256). Each HTTP call runs on a
+//! blocking thread, so challenge solvers can be async and work with the rest of the daemon.
+
+use data_encoding::BASE64URL_NOPAD;
+use futures::future::BoxFuture;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::jobs;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a challenge to validate or an order to be issued.
+const POLL_TIMEOUT: Duration = Duration::from_secs(180);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Upper bound on response bodies; certificate chains are a few KiB.
+const MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+fn b64(data: &[u8]) -> String {
+    BASE64URL_NOPAD.encode(data)
+}
+
+/// Builds the HTTP agent, trusting the extra CA certificates in `ca_bundle` (for a local
+/// test CA such as Pebble) on top of the usual web roots.
+pub fn agent(ca_bundle: Option<&str>) -> Result<ureq::Agent, String> {
+    let builder = ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).user_agent("supercp-acme");
+    let Some(ca_bundle) = ca_bundle else {
+        return Ok(builder.build());
+    };
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    let mut roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    for cert in CertificateDer::pem_file_iter(ca_bundle).map_err(|e| format!("Failed to read {}: {}", ca_bundle, e))? {
+        let cert = cert.map_err(|e| format!("Invalid certificate in {}: {}", ca_bundle, e))?;
+        roots.add(cert).map_err(|e| format!("Invalid certificate in {}: {}", ca_bundle, e))?;
+    }
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(builder.tls_config(Arc::new(config)).build())
+}
+
+struct Response {
+    status: u16,
+    location: Option<String>,
+    nonce: Option<String>,
+    body: String,
+}
+
+impl Response {
+    fn json(&self) -> Result<Value, String> {
+        serde_json::from_str(&self.body).map_err(|e| format!("Invalid response from the ACME server: {}", e))
+    }
+
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The problem document of a failed request, as a message.
+    fn problem(&self, action: &str) -> String {
+        match self.json() {
+            Ok(problem) => format!(
+                "ACME server rejected {} ({}): {}",
+                action,
+                problem["type"].as_str().unwrap_or("unknown error"),
+                problem["detail"].as_str().unwrap_or("")
+            ),
+            Err(_) => format!("ACME server rejected {} (HTTP {}): {}", action, self.status, self.body.trim()),
+        }
+    }
+}
+
+/// Makes one HTTP request. Error statuses come back as responses, since ACME explains them
+/// in the body.
+async fn http(agent: &ureq::Agent, method: &'static str, url: &str, body: Option<Vec<u8>>) -> Result<Response, String> {
+    let agent = agent.clone();
+    let url = url.to_string();
+    tokio::task::spawn_blocking(move || {
+        let request = agent.request(method, &url);
+        let result = match body {
+            Some(body) => request.set("Content-Type", "application/jose+json").send_bytes(&body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(format!("ACME server {} unreachable: {}", url, e)),
+        };
+
+        let status = response.status();
+        let location = response.header("Location").map(|s| s.to_string());
+        let nonce = response.header("Replay-Nonce").map(|s| s.to_string());
+        let mut body = String::new();
+        response.into_reader().take(MAX_RESPONSE_BYTES).read_to_string(&mut body).map_err(|e| e.to_string())?;
+        Ok(Response { status, location, nonce, body })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Proves control of identifiers for one challenge type.
+pub trait Solver: Send + Sync {
+    /// The ACME challenge type handled, such as `http-01`.
+    fn kind(&self) -> &'static str;
+
+    /// Publishes the key authorization for `token` so the CA can see it.
+    fn present<'a>(&'a self, identifier: &'a str, token: &'a str, key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Removes what [`Solver::present`] published. Failures are only logged.
+    fn cleanup<'a>(&'a self, identifier: &'a str, token: &'a str, key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// An issued certificate and the key it was issued for, both PEM.
+pub struct Issued {
+    pub chain: String,
+    pub key: String,
+}
+
+pub struct Client {
+    agent: ureq::Agent,
+    directory: Value,
+    key: SigningKey,
+    /// Account URL, once registered
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Client {
+    pub async fn new(agent: ureq::Agent, directory_url: &str, key: SigningKey) -> Result<Self, String> {
+        let response = http(&agent, "GET", directory_url, None).await?;
+        if !response.is_success() {
+            return Err(response.problem("the directory request"));
+        }
+        let directory = response.json()?;
+        Ok(Client { agent, directory, key, kid: None, nonce: None })
+    }
+
+    fn endpoint(&self, name: &str) -> Result<String, String> {
+        self.directory[name].as_str().map(|s| s.to_string()).ok_or_else(|| format!("ACME directory has no {}", name))
+    }
+
+    /// The account key as a JWK, with its members in the order RFC 7638 thumbprints need.
+    fn jwk(&self) -> Value {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(point.x().map(|x| x.as_slice()).unwrap_or_default()),
+            "y": b64(point.y().map(|y| y.as_slice()).unwrap_or_default()),
+        })
+    }
+
+    fn thumbprint(&self) -> String {
+        b64(&Sha256::digest(self.jwk().to_string().as_bytes()))
+    }
+
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    async fn nonce(&mut self) -> Result<String, String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = http(&self.agent, "HEAD", &self.endpoint("newNonce")?, None).await?;
+        response.nonce.ok_or_else(|| "ACME server returned no nonce".to_string())
+    }
+
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Vec<u8> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+
+        let protected = b64(protected.to_string().as_bytes());
+        // POST-as-GET has an empty payload
+        let payload = payload.map(|p| b64(p.to_string().as_bytes())).unwrap_or_default();
+        let signature: Signature = self.key.sign(format!("{}.{}", protected, payload).as_bytes());
+
+        json!({ "protected": protected, "payload": payload, "signature": b64(&signature.to_bytes()) }).to_string().into_bytes()
+    }
+
+    /// Sends a signed request, retrying once if the server rejects the nonce.
+    async fn post(&mut self, url: &str, payload: Option<&Value>, action: &str) -> Result<Response, String> {
+        for attempt in 0..2 {
+            let nonce = self.nonce().await?;
+            let response = http(&self.agent, "POST", url, Some(self.sign(url, &nonce, payload))).await?;
+            self.nonce = response.nonce.clone();
+
+            if response.is_success() {
+                return Ok(response);
+            }
+            let bad_nonce = response.json().ok().and_then(|p| p["type"].as_str().map(|t| t.ends_with(":badNonce"))) == Some(true);
+            if !(bad_nonce && attempt == 0) {
+                return Err(response.problem(action));
+            }
+        }
+        unreachable!("the second attempt always returns")
+    }
+
+    /// Registers the account key, or finds the account it already belongs to. Returns the
+    /// account URL and whether the account is new.
+    pub async fn account(&mut self, contact: Option<&str>) -> Result<(String, bool), String> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = contact {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+
+        let url = self.endpoint("newAccount")?;
+        let response = self.post(&url, Some(&payload), "the account registration").await?;
+        let kid = response.location.clone().ok_or("ACME server returned no account URL")?;
+        self.kid = Some(kid.clone());
+        Ok((kid, response.status == 201))
+    }
+
+    /// Replaces the contact address of the registered account.
+    pub async fn update_contact(&mut self, contact: &str) -> Result<(), String> {
+        let kid = self.kid.clone().ok_or("No ACME account to update")?;
+        let payload = json!({ "contact": [format!("mailto:{}", contact)] });
+        self.post(&kid, Some(&payload), "the account update").await?;
+        Ok(())
+    }
+
+    /// Polls an order or authorization until it leaves the pending states.
+    async fn poll(&mut self, url: &str, what: &str) -> Result<Value, String> {
+        let deadline = Instant::now() + POLL_TIMEOUT;
+        loop {
+            jobs::check_cancelled()?;
+            let object = self.post(url, None, what).await?.json()?;
+            match object["status"].as_str() {
+                Some("pending") | Some("processing") if Instant::now() < deadline => tokio::time::sleep(POLL_INTERVAL).await,
+                Some("pending") | Some("processing") => return Err(format!("Timed out waiting for the {}", what)),
+                _ => return Ok(object),
+            }
+        }
+    }
+
+    /// Completes one authorization with the solver.
+    async fn authorize(&mut self, url: &str, solver: &dyn Solver) -> Result<(), String> {
+        let authorization = self.post(url, None, "the authorization request").await?.json()?;
+        let identifier = authorization["identifier"]["value"].as_str().unwrap_or("").to_string();
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+
+        let challenge = authorization["challenges"]
+            .as_array()
+            .and_then(|challenges| challenges.iter().find(|c| c["type"] == solver.kind()))
+            .ok_or_else(|| format!("ACME server offers no {} challenge for {}", solver.kind(), identifier))?;
+        let token = challenge["token"].as_str().ok_or("Challenge has no token")?.to_string();
+        let challenge_url = challenge["url"].as_str().ok_or("Challenge has no URL")?.to_string();
+
+        let key_authorization = self.key_authorization(&token);
+        let shown = if authorization["wildcard"] == true { format!("*.{}", identifier) } else { identifier.clone() };
+
+        // Cleanup also runs when presenting fails half way
+        let result = async {
+            solver.present(&identifier, &token, &key_authorization).await?;
+            jobs::log(format!("Validating {} with {}", shown, solver.kind())).await;
+            self.post(&challenge_url, Some(&json!({})), "the challenge response").await?;
+            let authorization = self.poll(url, "authorization").await?;
+            if authorization["status"] == "valid" {
+                return Ok(());
+            }
+            // The failed challenge says why
+            let error = authorization["challenges"]
+                .as_array()
+                .and_then(|challenges| challenges.iter().find(|c| c["type"] == solver.kind()))
+                .map(|c| c["error"]["detail"].as_str().unwrap_or("").to_string())
+                .unwrap_or_default();
+            Err(format!("Validation of {} failed: {}", identifier, error))
+        }
+        .await;
+
+        if let Err(e) = solver.cleanup(&identifier, &token, &key_authorization).await {
+            eprintln!("ACME: failed to clean up the {} challenge for {}: {}", solver.kind(), identifier, e);
+        }
+        result
+    }
+
+    /// Orders a certificate for `names`, the first of which becomes the subject.
+    pub async fn issue(&mut self, names: &[String], solver: &dyn Solver) -> Result<Issued, String> {
+        let identifiers: Vec<Value> = names.iter().map(|n| json!({ "type": "dns", "value": n })).collect();
+        let url = self.endpoint("newOrder")?;
+        let response = self.post(&url, Some(&json!({ "identifiers": identifiers })), "the order").await?;
+        let order_url = response.location.clone().ok_or("ACME server returned no order URL")?;
+        let order = response.json()?;
+
+        let authorizations: Vec<String> =
+            order["authorizations"].as_array().into_iter().flatten().filter_map(|a| a.as_str().map(|s| s.to_string())).collect();
+        for authorization in &authorizations {
+            jobs::check_cancelled()?;
+            self.authorize(authorization, solver).await?;
+        }
+        jobs::check_cancelled()?;
+
+        // A fresh key for every certificate
+        let key = rcgen::KeyPair::generate().map_err(|e| e.to_string())?;
+        let mut params = rcgen::CertificateParams::new(names.to_vec()).map_err(|e| e.to_string())?;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::CommonName, names[0].clone());
+        let csr = params.serialize_request(&key).map_err(|e| e.to_string())?;
+
+        jobs::log("Finalizing the order").await;
+        let finalize = order["finalize"].as_str().ok_or("Order has no finalize URL")?.to_string();
+        self.post(&finalize, Some(&json!({ "csr": b64(csr.der()) })), "the finalization").await?;
+
+        let order = self.poll(&order_url, "order").await?;
+        if order["status"] != "valid" {
+            return Err(format!(
+                "Order ended as {}: {}",
+                order["status"].as_str().unwrap_or("unknown"),
+                order["error"]["detail"].as_str().unwrap_or("")
+            ));
+        }
+        let certificate_url = order["certificate"].as_str().ok_or("Order has no certificate URL")?.to_string();
+        let chain = self.post(&certificate_url, None, "the certificate download").await?.body;
+        if !chain.contains("-----BEGIN CERTIFICATE-----") {
+            return Err("ACME server returned no certificate".to_string());
+        }
+
+        Ok(Issued { chain, key: key.serialize_pem() })
+    }
+}
@@ -0,0 +1,339 @@
+//! TLS certificates from an ACME CA (Let's Encrypt by default).
+//!
+//! The daemon holds one ACME account per directory URL in `<ssl.dir>/accounts/`,
+//! validates domains with HTTP-01 through the site's document root or with DNS-01 through
+//! the daemon's own zones (see [`solver`]), and stores what it is issued as described in
+//! [`store`].
+
+pub mod acme;
+pub mod custom;
+pub mod renew;
+pub mod solver;
+pub mod store;
+
+use data_encoding::BASE64;
+use p256::ecdsa::SigningKey;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+
+use crate::config::{self, SslConfig};
+use crate::dns::validate_domain;
+use crate::jobs;
+use crate::vhost;
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    directory: String,
+    /// The P-256 private scalar, base64
+    private_key: String,
+    /// Account URL assigned by the CA
+    kid: Option<String>,
+    contact: Option<String>,
+}
+
+fn account_path(ssl: &SslConfig, directory: &str) -> String {
+    let digest = hex::encode(Sha256::digest(directory.as_bytes()));
+    format!("{}/accounts/{}.json", ssl.dir.trim_end_matches('/'), &digest[..16])
+}
+
+impl Account {
+    fn load(ssl: &SslConfig, directory: &str) -> Result<Option<Self>, String> {
+        let path = account_path(ssl, directory);
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| format!("Invalid ACME account file {}: {}", path, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn save(&self, ssl: &SslConfig) -> Result<(), String> {
+        let dir = format!("{}/accounts", ssl.dir.trim_end_matches('/'));
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        for dir in [&ssl.dir, &dir] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+        }
+
+        let path = account_path(ssl, &self.directory);
+        let pending = format!("{}.new", path);
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&pending, content).map_err(|e| e.to_string())?;
+        fs::set_permissions(&pending, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+        fs::rename(&pending, &path).map_err(|e| e.to_string())
+    }
+
+    fn signing_key(&self) -> Result<SigningKey, String> {
+        let bytes = BASE64.decode(self.private_key.as_bytes()).map_err(|e| format!("Invalid ACME account key: {}", e))?;
+        SigningKey::from_slice(&bytes).map_err(|e| format!("Invalid ACME account key: {}", e))
+    }
+}
+
+/// Connects to the configured CA with the stored account, registering one on first use.
+async fn client(ssl: &SslConfig, email: Option<&str>) -> Result<acme::Client, String> {
+    let directory = ssl.acme_directory.as_str();
+    let mut account = match Account::load(ssl, directory)? {
+        Some(account) => account,
+        None => {
+            let key = SigningKey::random(&mut rand_core::OsRng);
+            let account = Account {
+                directory: directory.to_string(),
+                private_key: BASE64.encode(&key.to_bytes()),
+                kid: None,
+                contact: None,
+            };
+            // Saved before registering, so a retry doesn't create a second account
+            account.save(ssl)?;
+            account
+        }
+    };
+
+    let agent = acme::agent(ssl.ca_bundle.as_deref())?;
+    let mut client = acme::Client::new(agent, directory, account.signing_key()?).await?;
+    // Registering is idempotent, and also recovers from the CA having forgotten the account
+    let (kid, created) = client.account(email).await?;
+    if created {
+        jobs::log(format!("Registered an ACME account with {}", directory)).await;
+    } else if let Some(email) = email.filter(|e| account.contact.as_deref() != Some(*e)) {
+        client.update_contact(email).await?;
+    }
+
+    let contact = match email {
+        Some(email) => Some(email.to_string()),
+        None if created => None,
+        None => account.contact.clone(),
+    };
+    if account.kid.as_deref() != Some(kid.as_str()) || account.contact != contact {
+        account.kid = Some(kid);
+        account.contact = contact;
+        account.save(ssl)?;
+    }
+    Ok(client)
+}
+
+/// Checks a certificate name: a hostname, or a wildcard over one.
+fn validate_name(name: &str) -> Result<(), String> {
+    let base = name.strip_prefix("*.").unwrap_or(name);
+    if validate_domain(base).is_err() {
+        return Err(format!("Invalid certificate name '{}'", name));
+    }
+    Ok(())
+}
+
+/// Points the domain's vhost, if it has one, at its certificate and reloads nginx. Returns
+/// the vhost config's path.
+async fn configure_vhost(domain: &str, paths: &store::Paths, params: &Value) -> Result<Option<String>, String> {
+    if params["configure_vhost"].as_bool() == Some(false) || !vhost::exists(domain) {
+        return Ok(None);
+    }
+    jobs::log(format!("Configuring the vhost for {}", domain)).await;
+    vhost::enable_ssl(domain, &paths.fullchain, &paths.key, params).await.map(Some)
+}
+
+/// Obtains a certificate from the ACME CA.
+///
+/// Params: `domain`, optional `names` (further SANs, which may include wildcards such as
+/// `*.example.com`), `challenge` (`http-01` or `dns-01`; wildcards need `dns-01`, which is the
+/// default when any are asked for), `email` (account contact), `webroot` (for HTTP-01;
+/// defaults to the root of the domain's nginx vhost) and `force` (renew even if the current
+/// certificate is fine). The certificate is stored under the domain's name.
+///
+/// If the domain has a vhost it is switched to the certificate, unless `configure_vhost` is
+/// false; `ssl_redirect`, `http2` and `hsts` are passed on to [`vhost::enable_ssl`].
+pub async fn request_ssl_cert(params: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let domain = params["domain"].as_str().ok_or("Missing domain")?.trim_end_matches('.').to_ascii_lowercase();
+    validate_domain(&domain)?;
+    let force = params["force"].as_bool().unwrap_or(false);
+
+    let mut names = vec![domain.clone()];
+    for name in params["names"].as_array().into_iter().flatten() {
+        let name = name.as_str().ok_or("Certificate names must be strings")?.trim_end_matches('.').to_ascii_lowercase();
+        validate_name(&name)?;
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let wildcard = names.iter().any(|n| n.starts_with("*."));
+
+    let config = config::load()?;
+    let ssl = &config.ssl;
+    let email = params["email"].as_str().or(ssl.email.as_deref()).map(|e| e.to_string());
+    if let Some(email) = &email {
+        if !email.contains('@') || email.chars().any(|c| c.is_whitespace()) {
+            return Err(format!("Invalid email '{}'", email).into());
+        }
+    }
+
+    let margin = ssl.renew_before_days as i64 * 86_400;
+    if !force && store::is_current(ssl, &domain, &names, margin) {
+        let paths = store::paths(ssl, &domain);
+        let configured = configure_vhost(&domain, &paths, params).await.map_err(|e| format!("Configuring the vhost for {} failed: {}", domain, e))?;
+        return Ok(format!(
+            "SSL certificate for {} is still valid and not due for renewal: {}{}",
+            domain,
+            paths.fullchain,
+            configured.map(|path| format!("; vhost {} uses it", path)).unwrap_or_default()
+        ));
+    }
+
+    let challenge = params["challenge"].as_str().unwrap_or(if wildcard { "dns-01" } else { "http-01" });
+    let explicit_webroot = params["webroot"].as_str().or(params["root"].as_str());
+    let solver: Box<dyn acme::Solver> = match challenge {
+        "http-01" if wildcard => return Err("Wildcard certificates can only be validated with dns-01".into()),
+        "http-01" => {
+            let webroot = match explicit_webroot {
+                Some(webroot) => webroot.to_string(),
+                None => solver::vhost_root(&domain)?,
+            };
+            Box::new(solver::Http01 { webroot })
+        }
+        "dns-01" => Box::new(solver::Dns01::new(&config)),
+        other => return Err(format!("Unsupported challenge type '{}'", other).into()),
+    };
+
+    // Aborting mid-validation would skip the solver's cleanup and leave the challenge TXT record
+    // or token file behind, so cancelling stops the ACME steps between polls instead
+    let _shield = jobs::shield()?;
+    jobs::log(format!("Requesting a certificate for {} from {}", names.join(", "), ssl.acme_directory)).await;
+    let mut client = client(ssl, email.as_deref()).await?;
+    let issued = client.issue(&names, solver.as_ref()).await.map_err(|e| format!("Failed to obtain an SSL certificate for {}: {}", domain, e))?;
+
+    let mut meta = json!({
+        "names": names,
+        "source": "acme",
+        "directory": ssl.acme_directory,
+        "challenge": challenge,
+        "issued_at": store::now(),
+    });
+    // Renewals use the same webroot; without one they look at the vhost again
+    if let Some(webroot) = explicit_webroot.filter(|_| challenge == "http-01") {
+        meta["webroot"] = json!(webroot);
+    }
+    let paths = store::save(ssl, &domain, &issued.chain, &issued.key, &meta)?;
+    jobs::log(format!("Certificate stored in {}", store::cert_dir(ssl, &domain))).await;
+
+    let issued = format!("SSL certificate issued for {}: {} (key {})", names.join(", "), paths.fullchain, paths.key);
+    match configure_vhost(&domain, &paths, params).await {
+        Ok(Some(path)) => Ok(format!("{}; vhost {} updated", issued, path)),
+        Ok(None) => Ok(issued),
+        Err(e) => Err(format!("{}, but configuring the vhost failed: {}", issued, e).into()),
+    }
+}
+
+/// Lists the stored certificates with their names, issuer, validity and key type.
+///
+/// Params: optional `domain` to list only the certificates covering it.
+pub async fn list_certificates(params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let filter = params["domain"].as_str().map(|d| d.trim_end_matches('.').to_ascii_lowercase());
+    let ssl = config::load()?.ssl;
+    let failures = renew::failures(&ssl);
+
+    let mut certificates = Vec::new();
+    for name in store::list(&ssl)? {
+        let paths = store::paths(&ssl, &name);
+        let meta = store::meta(&ssl, &name);
+        let mut entry = match fs::read(&paths.cert).map_err(|e| e.to_string()).and_then(|pem| store::inspect(&pem)) {
+            Ok(info) => info,
+            Err(e) => json!({ "error": format!("Failed to read {}: {}", paths.cert, e) }),
+        };
+
+        if let Some(domain) = &filter {
+            let covers = entry["names"].as_array().into_iter().flatten().filter_map(|n| n.as_str()).any(|n| store::name_matches(n, domain));
+            if name != *domain && !covers {
+                continue;
+            }
+        }
+
+        entry["name"] = json!(name);
+        entry["source"] = meta.get("source").cloned().unwrap_or(json!("unknown"));
+        entry["challenge"] = meta.get("challenge").cloned().unwrap_or(Value::Null);
+        entry["auto_renew"] = json!(meta["source"] == "acme");
+        entry["certificate_path"] = json!(paths.fullchain);
+        entry["key_path"] = json!(paths.key);
+        entry["renewal_error"] = failures.get(&name).map(|f| json!(f)).unwrap_or(Value::Null);
+        certificates.push(entry);
+    }
+
+    Ok(json!(certificates))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    /// Answers HTTP-01 requests from `webroot`, as nginx would for a vhost.
+    fn serve_challenges(port: u16, webroot: &Path) {
+        let listener = TcpListener::bind(("0.0.0.0", port)).expect("challenge port in use");
+        let dir = webroot.join(solver::CHALLENGE_PATH);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut line = String::new();
+                if BufReader::new(&stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                let token = line.split_whitespace().nth(1).and_then(|p| p.rsplit('/').next()).unwrap_or_default();
+                let response = match fs::read_to_string(dir.join(token)) {
+                    Ok(body) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+                    Err(_) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = (&stream).write_all(response.as_bytes());
+            }
+        });
+    }
+
+    /// Runs a full issuance against a Pebble test CA:
+    ///
+    /// ```sh
+    /// pebble -config test/config/pebble-config.json -dnsserver 127.0.0.1:8053 &
+    /// pebble-challtestsrv -defaultIPv4 127.0.0.1 &
+    /// PEBBLE_DIRECTORY=https://localhost:14000/dir PEBBLE_CA=test/certs/pebble.minica.pem \
+    ///     cargo test -p super-daemon -- --ignored pebble
+    /// ```
+    ///
+    /// `PEBBLE_DOMAIN` (default `pebble.test`) must resolve to this host from Pebble, which
+    /// validates HTTP-01 on `PEBBLE_HTTP_PORT` (default 5002).
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs a Pebble ACME server, see PEBBLE_DIRECTORY"]
+    async fn issues_certificates_from_pebble() {
+        let Ok(directory) = std::env::var("PEBBLE_DIRECTORY") else {
+            eprintln!("PEBBLE_DIRECTORY is not set, skipping");
+            return;
+        };
+        let domain = std::env::var("PEBBLE_DOMAIN").unwrap_or_else(|_| "pebble.test".to_string());
+        let port = std::env::var("PEBBLE_HTTP_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(5002);
+        let ca_bundle = std::env::var("PEBBLE_CA").ok();
+
+        let dir = tempfile::tempdir().unwrap();
+        let webroot = dir.path().join("www");
+        serve_challenges(port, &webroot);
+
+        let agent = acme::agent(ca_bundle.as_deref()).unwrap();
+        let key = SigningKey::random(&mut rand_core::OsRng);
+        let mut client = acme::Client::new(agent.clone(), &directory, key.clone()).await.unwrap();
+        let (kid, created) = client.account(Some("admin@example.com")).await.unwrap();
+        assert!(created, "a fresh key registers a new account");
+        // Registering again with the same key finds the account
+        let mut again = acme::Client::new(agent, &directory, key).await.unwrap();
+        assert_eq!(again.account(None).await.unwrap(), (kid, false));
+
+        let names = vec![domain.clone()];
+        let solver = solver::Http01 { webroot: webroot.display().to_string() };
+        let issued = client.issue(&names, &solver).await.unwrap();
+        let (issued_names, expires) = store::leaf_names(issued.chain.as_bytes()).unwrap();
+        assert_eq!(issued_names, names);
+        assert!(expires > store::now());
+        // The solver cleans up after itself
+        assert_eq!(fs::read_dir(webroot.join(solver::CHALLENGE_PATH)).unwrap().count(), 0);
+
+        let ssl = SslConfig { dir: dir.path().join("ssl").display().to_string(), ..Default::default() };
+        let paths = store::save(&ssl, &domain, &issued.chain, &issued.key, &json!({ "names": names, "source": "acme" })).unwrap();
+        assert_eq!(fs::metadata(&paths.key).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(store::is_current(&ssl, &domain, &names, 0));
+        assert_eq!(store::list(&ssl).unwrap(), names);
+    }
+}
@@ -0,0 +1,190 @@
+//! Automatic renewal of ACME certificates.
+//!
+//! Twice a day every stored certificate that came from ACME and expires within
+//! `ssl.renew_before_days` is ordered again the way it was first obtained. The outcome of the
+//! last attempt for each certificate is kept in `<ssl.dir>/renewal.json`, and failures
+//! show up in `get_status` until a renewal succeeds.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use super::store;
+use crate::config::{self, SslConfig};
+
+const RENEW_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Failure {
+    pub error: String,
+    pub last_attempt: i64,
+    /// Consecutive failed attempts
+    pub attempts: u32,
+}
+
+fn state_path(ssl: &SslConfig) -> String {
+    format!("{}/renewal.json", ssl.dir.trim_end_matches('/'))
+}
+
+/// Failed renewals by certificate name.
+pub fn failures(ssl: &SslConfig) -> BTreeMap<String, Failure> {
+    match fs::read_to_string(state_path(ssl)) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+fn save_failures(ssl: &SslConfig, failures: &BTreeMap<String, Failure>) -> Result<(), String> {
+    let path = state_path(ssl);
+    if failures.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        };
+    }
+    let pending = format!("{}.new", path);
+    fs::write(&pending, serde_json::to_string_pretty(failures).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    fs::rename(&pending, &path).map_err(|e| e.to_string())
+}
+
+/// Records the outcome of a renewal attempt.
+fn record(ssl: &SslConfig, name: &str, result: &Result<String, String>) -> Result<(), String> {
+    let mut failures = failures(ssl);
+    match result {
+        Ok(_) => {
+            failures.remove(name);
+        }
+        Err(e) => {
+            let attempts = failures.get(name).map(|f| f.attempts).unwrap_or(0) + 1;
+            failures.insert(name.to_string(), Failure { error: e.clone(), last_attempt: store::now(), attempts });
+        }
+    }
+    save_failures(ssl, &failures)
+}
+
+/// The `request_ssl_cert` params that obtain the certificate stored under `name` again.
+fn renewal_params(name: &str, meta: &Value) -> Value {
+    let names: Vec<&str> = meta["names"].as_array().into_iter().flatten().filter_map(|n| n.as_str()).filter(|n| *n != name).collect();
+    let mut params = json!({ "domain": name, "names": names, "force": true });
+    for key in ["challenge", "webroot"] {
+        if meta[key].is_string() {
+            params[key] = meta[key].clone();
+        }
+    }
+    params
+}
+
+/// Renews every ACME certificate inside the renewal window.
+async fn renew_due() -> Result<(), Box<dyn std::error::Error>> {
+    let ssl = config::load()?.ssl;
+    let margin = ssl.renew_before_days as i64 * 86_400;
+
+    for name in store::list(&ssl)? {
+        let meta = store::meta(&ssl, &name);
+        if meta["source"] != "acme" {
+            continue;
+        }
+        let names: Vec<String> = meta["names"].as_array().into_iter().flatten().filter_map(|n| n.as_str().map(|s| s.to_string())).collect();
+        if store::is_current(&ssl, &name, &names, margin) {
+            continue;
+        }
+
+        let result = super::request_ssl_cert(&renewal_params(&name, &meta)).await.map_err(|e| e.to_string());
+        match &result {
+            Ok(message) => println!("SSL: renewed {}: {}", name, message),
+            Err(e) => eprintln!("SSL: failed to renew {}: {}", name, e),
+        }
+        if let Err(e) = record(&ssl, &name, &result) {
+            eprintln!("SSL: failed to record the renewal of {}: {}", name, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Background task that renews certificates before they expire; spawned once at startup.
+pub async fn renew_loop() {
+    let mut interval = tokio::time::interval(RENEW_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = renew_due().await {
+            eprintln!("SSL: renewal check failed: {}", e);
+        }
+    }
+}
+
+/// Certificate health for `get_status`: how many there are, which expire within the renewal
+/// window, and which failed to renew.
+pub fn status() -> Value {
+    let ssl = config::load().map(|c| c.ssl).unwrap_or_default();
+    let renew_before_days = ssl.renew_before_days as i64;
+    let names = store::list(&ssl).unwrap_or_default();
+    let failures = failures(&ssl);
+
+    let mut expiring = Vec::new();
+    for name in &names {
+        let Ok(pem) = fs::read(store::paths(&ssl, name).cert) else {
+            continue;
+        };
+        if let Ok((_, not_after)) = store::leaf_names(&pem) {
+            let days_remaining = (not_after - store::now()).div_euclid(86_400);
+            if days_remaining < renew_before_days {
+                expiring.push(json!({ "name": name, "not_after": not_after, "days_remaining": days_remaining }));
+            }
+        }
+    }
+
+    let renewal_failures: Vec<Value> = failures
+        .iter()
+        .filter(|(name, _)| names.contains(name))
+        .map(|(name, failure)| {
+            json!({
+                "name": name,
+                "error": failure.error,
+                "last_attempt": failure.last_attempt,
+                "attempts": failure.attempts,
+            })
+        })
+        .collect();
+
+    json!({
+        "certificates": names.len(),
+        "expiring": expiring,
+        "renewal_failures": renewal_failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renews_with_the_original_request() {
+        let meta = json!({
+            "names": ["example.com", "www.example.com", "*.example.com"],
+            "source": "acme",
+            "challenge": "http-01",
+            "webroot": "/var/www/example",
+            "issued_at": 1,
+        });
+        assert_eq!(
+            renewal_params("example.com", &meta),
+            json!({
+                "domain": "example.com",
+                "names": ["www.example.com", "*.example.com"],
+                "force": true,
+                "challenge": "http-01",
+                "webroot": "/var/www/example",
+            })
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_was_not_recorded() {
+        let meta = json!({ "source": "acme", "webroot": null, "challenge": 1 });
+        assert_eq!(renewal_params("example.com", &meta), json!({ "domain": "example.com", "names": [], "force": true }));
+    }
+}
@@ -0,0 +1,217 @@
+//! ACME challenge solvers.
+
+use data_encoding::BASE64URL_NOPAD;
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{Duration, Instant};
+
+use super::acme::Solver;
+use crate::config::Config;
+use crate::dns::{self, query};
+use crate::exec::Command;
+use crate::jobs;
+
+pub const CHALLENGE_PATH: &str = ".well-known/acme-challenge";
+
+/// HTTP-01: serves the key authorization as a file under the site's document root, which
+/// nginx exposes at `/.well-known/acme-challenge/`.
+pub struct Http01 {
+    pub webroot: String,
+}
+
+impl Http01 {
+    fn challenge_dir(&self) -> String {
+        format!("{}/{}", self.webroot.trim_end_matches('/'), CHALLENGE_PATH)
+    }
+
+    fn token_path(&self, token: &str) -> Result<String, String> {
+        // Tokens are base64url, but they come from the network
+        if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid challenge token '{}'", token));
+        }
+        Ok(format!("{}/{}", self.challenge_dir(), token))
+    }
+}
+
+impl Solver for Http01 {
+    fn kind(&self) -> &'static str {
+        "http-01"
+    }
+
+    fn present<'a>(&'a self, _identifier: &'a str, token: &'a str, key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let path = self.token_path(token)?;
+            let dir = self.challenge_dir();
+            fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).map_err(|e| e.to_string())?;
+            fs::write(&path, key_authorization).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).map_err(|e| e.to_string())
+        }
+        .boxed()
+    }
+
+    fn cleanup<'a>(&'a self, _identifier: &'a str, token: &'a str, _key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let path = self.token_path(token)?;
+            fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path, e))
+        }
+        .boxed()
+    }
+}
+
+/// TTL of challenge records, kept short so a retried challenge isn't served from caches.
+const CHALLENGE_TTL: u32 = 60;
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(120);
+const PROPAGATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// DNS-01: publishes the key authorization's digest as a TXT record at
+/// `_acme-challenge.<domain>`. The record goes into the daemon's own zone for the domain, or
+/// through `ssl.dns_hook` for domains whose DNS is hosted elsewhere. Presenting only returns
+/// once the record can be seen on the nameservers.
+pub struct Dns01 {
+    hook: Option<String>,
+    resolvers: Vec<String>,
+    nameservers: Vec<String>,
+    propagation_timeout: Duration,
+}
+
+impl Dns01 {
+    pub fn new(config: &Config) -> Self {
+        Dns01 {
+            hook: config.ssl.dns_hook.clone(),
+            resolvers: config.ssl.dns_resolvers.clone(),
+            nameservers: config.dns.nameservers.clone(),
+            propagation_timeout: Duration::from_secs(config.ssl.dns_propagation_timeout),
+        }
+    }
+
+    fn record_name(identifier: &str) -> String {
+        format!("_acme-challenge.{}", identifier)
+    }
+
+    fn record_value(key_authorization: &str) -> String {
+        BASE64URL_NOPAD.encode(&Sha256::digest(key_authorization.as_bytes()))
+    }
+
+    /// The local zone for `identifier`, unless a hook takes care of it.
+    fn local_zone(&self, identifier: &str) -> Result<Option<String>, String> {
+        match dns::zone_for(identifier) {
+            Some(zone) => Ok(Some(zone)),
+            None if self.hook.is_some() => Ok(None),
+            None => Err(format!("No DNS zone for {} is hosted here and no ssl.dns_hook is configured", identifier)),
+        }
+    }
+
+    async fn run_hook(hook: &str, action: &str, name: &str, value: &str) -> Result<(), String> {
+        Command::new(hook)
+            .arg(action)
+            .arg(name)
+            .arg(value)
+            .timeout(HOOK_TIMEOUT)
+            .run()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("DNS hook {} {} failed for {}: {}", hook, action, name, e))
+    }
+
+    /// Waits until every server answers with the challenge value.
+    async fn wait_for(&self, servers: &[String], name: &str, value: &str) -> Result<(), String> {
+        if servers.is_empty() || self.propagation_timeout.is_zero() {
+            return Ok(());
+        }
+        jobs::log(format!("Waiting for {} to reach {}", name, servers.join(", "))).await;
+
+        let deadline = Instant::now() + self.propagation_timeout;
+        let mut pending: Vec<&String> = servers.iter().collect();
+        loop {
+            jobs::check_cancelled()?;
+            let mut still_pending = Vec::new();
+            let mut last_error = None;
+            for server in pending {
+                match query::txt(server, &format!("{}.", name)).await {
+                    Ok(values) if values.iter().any(|v| v == value) => {}
+                    Ok(_) => still_pending.push(server),
+                    Err(e) => {
+                        still_pending.push(server);
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if still_pending.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let servers: Vec<&str> = still_pending.iter().map(|s| s.as_str()).collect();
+                return Err(format!(
+                    "{} did not appear on {} within {}s{}",
+                    name,
+                    servers.join(", "),
+                    self.propagation_timeout.as_secs(),
+                    last_error.map(|e| format!(" ({})", e)).unwrap_or_default()
+                ));
+            }
+            pending = still_pending;
+            tokio::time::sleep(PROPAGATION_INTERVAL).await;
+        }
+    }
+}
+
+impl Solver for Dns01 {
+    fn kind(&self) -> &'static str {
+        "dns-01"
+    }
+
+    fn present<'a>(&'a self, identifier: &'a str, _token: &'a str, key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let name = Self::record_name(identifier);
+            let value = Self::record_value(key_authorization);
+
+            let servers = match self.local_zone(identifier)? {
+                Some(zone) => {
+                    let record = json!({ "name": format!("{}.", name), "type": "TXT", "value": value, "ttl": CHALLENGE_TTL });
+                    dns::edit_records(&zone, |records| records.push(record)).await.map_err(|e| e.to_string())?;
+                    if self.resolvers.is_empty() { &self.nameservers } else { &self.resolvers }
+                }
+                None => {
+                    Self::run_hook(self.hook.as_deref().unwrap_or_default(), "present", &name, &value).await?;
+                    &self.resolvers
+                }
+            };
+            self.wait_for(servers, &name, &value).await
+        }
+        .boxed()
+    }
+
+    fn cleanup<'a>(&'a self, identifier: &'a str, _token: &'a str, key_authorization: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let name = Self::record_name(identifier);
+            let value = Self::record_value(key_authorization);
+
+            match self.local_zone(identifier)? {
+                Some(zone) => {
+                    let owner = dns::record::owner_name(&format!("{}.", name), &zone)?;
+                    let is_challenge = move |r: &serde_json::Value| r["type"] == "TXT" && r["name"] == owner.as_str() && r["value"] == value.as_str();
+                    dns::edit_records(&zone, |records| records.retain(|r| !is_challenge(r))).await.map(|_| ()).map_err(|e| e.to_string())
+                }
+                None => Self::run_hook(self.hook.as_deref().unwrap_or_default(), "cleanup", &name, &value).await,
+            }
+        }
+        .boxed()
+    }
+}
+
+/// The document root nginx serves `domain` from, read from its vhost config.
+pub fn vhost_root(domain: &str) -> Result<String, String> {
+    let path = format!("/etc/nginx/sites-available/{}", domain);
+    let config = fs::read_to_string(&path).map_err(|e| format!("No webroot given and no vhost config at {} ({})", path, e))?;
+    config
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("root "))
+        .map(|root| root.trim().trim_end_matches(';').trim().to_string())
+        .next()
+        .ok_or_else(|| format!("No root directive in {}", path))
+}
@@ -0,0 +1,247 @@
+//! Certificate storage.
+//!
+//! Each certificate lives in `<ssl.dir>/certs/<name>/` as `cert.pem` (the leaf),
+//! `chain.pem` (the intermediates), `fullchain.pem` (both, for nginx) and `privkey.pem`, with
+//! `meta.json` recording how it was obtained. The directory is root-only and the key 0600.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::oid_registry::OID_SIG_ED25519;
+use x509_parser::pem::Pem;
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::X509Name;
+
+use crate::config::SslConfig;
+use crate::dns::validate_domain;
+
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+pub fn certs_dir(ssl: &SslConfig) -> String {
+    format!("{}/certs", ssl.dir.trim_end_matches('/'))
+}
+
+pub fn cert_dir(ssl: &SslConfig, name: &str) -> String {
+    format!("{}/{}", certs_dir(ssl), name)
+}
+
+pub struct Paths {
+    pub cert: String,
+    pub chain: String,
+    pub fullchain: String,
+    pub key: String,
+}
+
+pub fn paths(ssl: &SslConfig, name: &str) -> Paths {
+    let dir = cert_dir(ssl, name);
+    Paths {
+        cert: format!("{}/cert.pem", dir),
+        chain: format!("{}/chain.pem", dir),
+        fullchain: format!("{}/fullchain.pem", dir),
+        key: format!("{}/privkey.pem", dir),
+    }
+}
+
+fn write(path: &str, content: &str, mode: u32) -> Result<(), String> {
+    let pending = format!("{}.new", path);
+    fs::write(&pending, content).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    fs::set_permissions(&pending, fs::Permissions::from_mode(mode)).map_err(|e| e.to_string())?;
+    fs::rename(&pending, path).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+/// Splits a PEM chain into its leaf certificate and the rest.
+fn split_chain(chain: &str) -> (String, String) {
+    match chain.find(PEM_END) {
+        Some(end) => {
+            let end = end + PEM_END.len();
+            let rest = chain[end..].trim();
+            let rest = if rest.is_empty() { String::new() } else { format!("{}\n", rest) };
+            (format!("{}\n", chain[..end].trim()), rest)
+        }
+        None => (chain.to_string(), String::new()),
+    }
+}
+
+/// Stores a certificate chain with its key under `name`.
+pub fn save(ssl: &SslConfig, name: &str, fullchain: &str, key: &str, meta: &Value) -> Result<Paths, String> {
+    let dir = cert_dir(ssl, name);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+    for dir in [ssl.dir.clone(), certs_dir(ssl), dir] {
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+    }
+
+    let paths = paths(ssl, name);
+    let (cert, chain) = split_chain(fullchain);
+    // The key goes first, so the certificate files never point at a key that isn't there yet
+    write(&paths.key, key, 0o600)?;
+    write(&paths.cert, &cert, 0o644)?;
+    write(&paths.chain, &chain, 0o644)?;
+    write(&paths.fullchain, fullchain, 0o644)?;
+    write(&format!("{}/meta.json", cert_dir(ssl, name)), &serde_json::to_string_pretty(meta).map_err(|e| e.to_string())?, 0o644)?;
+    Ok(paths)
+}
+
+/// The names certificates are stored under.
+pub fn list(ssl: &SslConfig) -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(certs_dir(ssl)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_string()))
+        .filter(|name| validate_domain(name).is_ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// The `meta.json` of a stored certificate, or null.
+pub fn meta(ssl: &SslConfig, name: &str) -> Value {
+    fs::read_to_string(format!("{}/meta.json", cert_dir(ssl, name))).ok().and_then(|m| serde_json::from_str(&m).ok()).unwrap_or(Value::Null)
+}
+
+/// Describes the leaf certificate in a PEM file: subject, SANs, issuer, validity and key.
+pub fn inspect(pem: &[u8]) -> Result<Value, String> {
+    let pem = Pem::iter_from_buffer(pem).next().ok_or("No certificate found")?.map_err(|e| format!("Invalid PEM: {}", e))?;
+    let cert = pem.parse_x509().map_err(|e| format!("Invalid certificate: {}", e))?;
+
+    let common_name = |name: &X509Name| name.iter_common_name().next().and_then(|cn| cn.as_str().ok()).map(|cn| cn.to_string());
+    let not_after = cert.validity().not_after.timestamp();
+
+    let key = cert.public_key();
+    let key_type = match key.parsed() {
+        Ok(PublicKey::RSA(rsa)) => format!("RSA-{}", rsa.key_size()),
+        Ok(PublicKey::EC(ec)) => format!("EC-P{}", ec.key_size()),
+        _ if key.algorithm.algorithm == OID_SIG_ED25519 => "Ed25519".to_string(),
+        _ => key.algorithm.algorithm.to_id_string(),
+    };
+
+    Ok(json!({
+        "subject": common_name(cert.subject()).unwrap_or_else(|| cert.subject().to_string()),
+        "names": dns_names(&cert),
+        "issuer": common_name(cert.issuer()).unwrap_or_else(|| cert.issuer().to_string()),
+        "issuer_dn": cert.issuer().to_string(),
+        "serial": cert.raw_serial_as_string(),
+        "not_before": cert.validity().not_before.timestamp(),
+        "not_after": not_after,
+        "days_remaining": (not_after - now()).div_euclid(86_400),
+        "key_type": key_type,
+        "self_signed": cert.subject() == cert.issuer(),
+    }))
+}
+
+/// Whether a certificate name such as `*.example.com` covers `name`.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.to_ascii_lowercase(), name.to_ascii_lowercase());
+    match pattern.strip_prefix("*.") {
+        Some(parent) => name.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && label != "*" && rest == parent) || name == pattern,
+        None => pattern == name,
+    }
+}
+
+fn dns_names(cert: &X509Certificate) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(name) = name {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// The DNS names and expiry (Unix time) of the leaf certificate in a PEM file.
+pub fn leaf_names(pem: &[u8]) -> Result<(Vec<String>, i64), String> {
+    let pem = Pem::iter_from_buffer(pem).next().ok_or("No certificate found")?.map_err(|e| format!("Invalid PEM: {}", e))?;
+    let cert = pem.parse_x509().map_err(|e| format!("Invalid certificate: {}", e))?;
+    Ok((dns_names(&cert), cert.validity().not_after.timestamp()))
+}
+
+/// Whether the certificate stored under `name` covers all of `names` for at least another
+/// `margin` seconds.
+pub fn is_current(ssl: &SslConfig, name: &str, names: &[String], margin: i64) -> bool {
+    let paths = paths(ssl, name);
+    let Ok(pem) = fs::read(&paths.cert) else {
+        return false;
+    };
+    if fs::metadata(&paths.key).is_err() {
+        return false;
+    }
+    match leaf_names(&pem) {
+        Ok((covered, not_after)) => not_after > now() + margin && names.iter().all(|n| covered.iter().any(|c| name_matches(c, n))),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pem(body: &str) -> String {
+        format!("-----BEGIN CERTIFICATE-----\n{}\n{}\n", body, PEM_END)
+    }
+
+    #[test]
+    fn wildcards_cover_one_label() {
+        let cases = [
+            ("example.com", "example.com", true),
+            ("Example.COM", "example.com", true),
+            ("example.com", "www.example.com", false),
+            ("*.example.com", "www.example.com", true),
+            ("*.example.com", "WWW.Example.com", true),
+            ("*.example.com", "*.example.com", true),
+            ("*.example.com", "example.com", false),
+            ("*.example.com", "a.b.example.com", false),
+            ("*.example.com", ".example.com", false),
+            ("*.example.com", "www.example.org", false),
+            ("www.example.com", "*.example.com", false),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(name_matches(pattern, name), expected, "{} against {}", name, pattern);
+        }
+    }
+
+    #[test]
+    fn splits_the_leaf_from_the_chain() {
+        let (leaf, rest) = split_chain(&format!("{}\n{}{}", pem("leaf"), pem("intermediate"), pem("root")));
+        assert_eq!(leaf, pem("leaf"));
+        assert_eq!(rest, format!("{}{}", pem("intermediate"), pem("root")));
+
+        let (leaf, rest) = split_chain(&format!("\n{}\n\n", pem("leaf")));
+        assert_eq!(leaf, pem("leaf"));
+        assert_eq!(rest, "");
+
+        assert_eq!(split_chain("not a certificate"), ("not a certificate".to_string(), String::new()));
+    }
+
+    #[test]
+    fn saves_certificates_with_a_private_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let ssl = SslConfig { dir: dir.path().join("ssl").display().to_string(), ..Default::default() };
+        let chain = format!("{}{}", pem("leaf"), pem("intermediate"));
+
+        let paths = save(&ssl, "example.com", &chain, "KEY\n", &json!({ "source": "custom" })).unwrap();
+        assert_eq!(fs::read_to_string(&paths.cert).unwrap(), pem("leaf"));
+        assert_eq!(fs::read_to_string(&paths.chain).unwrap(), pem("intermediate"));
+        assert_eq!(fs::read_to_string(&paths.fullchain).unwrap(), chain);
+        assert_eq!(fs::read_to_string(&paths.key).unwrap(), "KEY\n");
+        assert_eq!(fs::metadata(&paths.key).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::metadata(cert_dir(&ssl, "example.com")).unwrap().permissions().mode() & 0o777, 0o700);
+
+        assert_eq!(list(&ssl).unwrap(), vec!["example.com".to_string()]);
+        assert_eq!(meta(&ssl, "example.com")["source"], "custom");
+    }
+}
@@ -0,0 +1,74 @@
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use sysinfo::{Disks, Networks, System};
+
+/// CPU usage and network rates are deltas between refreshes, so the samplers are kept
+/// between calls instead of being rebuilt (and waited on) for every request.
+struct Sampler {
+    sys: System,
+    networks: Networks,
+}
+
+static SAMPLER: Mutex<Option<Sampler>> = Mutex::new(None);
+
+fn collect() -> Value {
+    let mut guard = SAMPLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let sampler = guard.get_or_insert_with(|| {
+        let mut sys = System::new();
+        sys.refresh_cpu_usage();
+        // The very first sample needs a baseline; later calls measure since the previous one
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        Sampler { sys, networks: Networks::new_with_refreshed_list() }
+    });
+
+    sampler.sys.refresh_cpu_usage();
+    sampler.sys.refresh_memory();
+    sampler.networks.refresh(true);
+
+    let sys = &sampler.sys;
+    let cpu_usage = sys.global_cpu_usage();
+
+    let memory = json!({
+        "total": sys.total_memory() / 1024 / 1024, // MB
+        "used": sys.used_memory() / 1024 / 1024,
+        "free": sys.free_memory() / 1024 / 1024,
+    });
+
+    let mut disks_info = Vec::new();
+    let disks = Disks::new_with_refreshed_list();
+    for disk in &disks {
+        disks_info.push(json!({
+            "name": disk.name().to_string_lossy(),
+            "mount_point": disk.mount_point().to_string_lossy(),
+            "total": disk.total_space() / 1024 / 1024, // MB
+            "available": disk.available_space() / 1024 / 1024,
+        }));
+    }
+
+    let mut network_info = Vec::new();
+    for (interface_name, data) in &sampler.networks {
+        network_info.push(json!({
+            "interface": interface_name,
+            "received": data.received(),
+            "transmitted": data.transmitted(),
+            "total_received": data.total_received(),
+            "total_transmitted": data.total_transmitted(),
+        }));
+    }
+
+    let load_avg = System::load_average();
+
+    json!({
+        "cpu_usage": cpu_usage,
+        "memory": memory,
+        "disks": disks_info,
+        "networks": network_info,
+        "uptime": System::uptime(),
+        "load_average": [load_avg.one, load_avg.five, load_avg.fifteen]
+    })
+}
+
+pub async fn get_system_stats() -> Result<Value, Box<dyn std::error::Error>> {
+    // sysinfo reads /proc synchronously; keep that off the async workers
+    Ok(tokio::task::spawn_blocking(collect).await?)
+}
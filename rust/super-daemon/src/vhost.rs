@@ -0,0 +1,329 @@
+//! Nginx site configs.
+//!
+//! `create_vhost` renders a site from the nginx stub and records what it rendered it from in
+//! `/etc/supercp/vhosts/<domain>.json`, so the site can be rendered again later, for example
+//! once a certificate has been issued for it. Sites created before that file existed are
+//! read back from their nginx config instead.
+//!
+//! nginx only inherits `add_header` into locations that set no headers of their own, so the
+//! HSTS header lives in a per-site snippet included at server level and again in each such
+//! location (the stub marks them with `{{HSTS}}`).
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use crate::exec::Command;
+use crate::jobs;
+
+pub const NGINX_STUB: &str = "/home/super/getsupercp/resources/templates/system/nginx_vhost.conf.stub";
+pub const VHOSTS_DIR: &str = "/etc/supercp/vhosts";
+pub const SNIPPETS_DIR: &str = "/etc/nginx/snippets";
+
+/// HSTS max-age when it is switched on without one: a year.
+const DEFAULT_HSTS_MAX_AGE: u64 = 31_536_000;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SslSettings {
+    pub certificate: String,
+    pub key: String,
+    /// Redirect plain HTTP to HTTPS
+    pub redirect: bool,
+    pub http2: bool,
+    /// `Strict-Transport-Security` max-age in seconds; none sends no header
+    pub hsts_max_age: Option<u64>,
+}
+
+impl SslSettings {
+    /// Reads the `redirect`, `http2` and `hsts` options, defaulting to those of `current`
+    /// (or to redirect and HTTP/2 on, HSTS off).
+    pub fn from_params(certificate: &str, key: &str, params: &Value, current: Option<&SslSettings>) -> Self {
+        let hsts_max_age = match &params["hsts"] {
+            Value::Bool(true) => Some(DEFAULT_HSTS_MAX_AGE),
+            Value::Bool(false) => None,
+            Value::Number(max_age) => max_age.as_u64().filter(|m| *m > 0),
+            _ => current.and_then(|c| c.hsts_max_age),
+        };
+        SslSettings {
+            certificate: certificate.to_string(),
+            key: key.to_string(),
+            redirect: params["ssl_redirect"].as_bool().or(current.map(|c| c.redirect)).unwrap_or(true),
+            http2: params["http2"].as_bool().or(current.map(|c| c.http2)).unwrap_or(true),
+            hsts_max_age,
+        }
+    }
+}
+
+/// What a site's nginx config is rendered from.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VhostSpec {
+    pub domain: String,
+    pub user: String,
+    pub root: String,
+    pub php_version: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub ssl: Option<SslSettings>,
+}
+
+/// The name the user's PHP-FPM pool and socket go by.
+pub fn safe_name(user: &str) -> String {
+    user.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+pub fn nginx_available(domain: &str) -> String {
+    format!("/etc/nginx/sites-available/{}", domain)
+}
+
+/// The snippet holding the site's HSTS header.
+pub fn hsts_snippet(domain: &str) -> String {
+    format!("{}/supercp-hsts-{}.conf", SNIPPETS_DIR, domain)
+}
+
+fn spec_path(domain: &str) -> String {
+    format!("{}/{}.json", VHOSTS_DIR, domain)
+}
+
+pub fn exists(domain: &str) -> bool {
+    fs::metadata(spec_path(domain)).is_ok() || fs::metadata(nginx_available(domain)).is_ok()
+}
+
+pub fn remove_spec(domain: &str) -> std::io::Result<()> {
+    match fs::remove_file(spec_path(domain)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl VhostSpec {
+    pub fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(VHOSTS_DIR).map_err(|e| e.to_string())?;
+        let path = spec_path(&self.domain);
+        let pending = format!("{}.new", path);
+        fs::write(&pending, serde_json::to_string_pretty(self).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        fs::rename(&pending, &path).map_err(|e| e.to_string())
+    }
+
+    /// The site's spec, from its spec file or else from its current nginx config.
+    pub fn load(domain: &str) -> Result<Self, String> {
+        match fs::read_to_string(spec_path(domain)) {
+            Ok(content) => return serde_json::from_str(&content).map_err(|e| format!("Invalid vhost file {}: {}", spec_path(domain), e)),
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.to_string()),
+            Err(_) => {}
+        }
+
+        let path = nginx_available(domain);
+        let config = match fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(format!("No vhost for {}", domain)),
+            Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+        };
+        Self::from_config(domain, &config).ok_or_else(|| format!("Can't read the settings of {} back from {}", domain, path))
+    }
+
+    /// Recovers the spec from a config rendered from the stub.
+    fn from_config(domain: &str, config: &str) -> Option<Self> {
+        let directive = |name: &str| {
+            config.lines().find_map(|line| line.trim().strip_prefix(name).map(|v| v.trim().trim_end_matches(';').trim().to_string()))
+        };
+
+        let root = directive("root ")?;
+        // server unix:/run/php/php8.4-fpm-<safe name>.sock;
+        let socket = directive("server unix:/run/php/php")?;
+        let (php_version, pool) = socket.trim_end_matches(".sock").split_once("-fpm-")?;
+        let aliases = directive("server_name ")
+            .map(|names| names.split_whitespace().filter(|n| *n != domain).map(|n| n.to_string()).collect())
+            .unwrap_or_default();
+
+        // Older configs carry the header inline rather than in the snippet
+        let hsts = fs::read_to_string(hsts_snippet(domain)).ok().filter(|_| config.contains(&hsts_snippet(domain)));
+        let ssl = directive("ssl_certificate ").zip(directive("ssl_certificate_key ")).map(|(certificate, key)| SslSettings {
+            certificate,
+            key,
+            redirect: config.contains("return 301 https://"),
+            http2: config.contains(" http2"),
+            hsts_max_age: hsts
+                .as_deref()
+                .unwrap_or(config)
+                .split("Strict-Transport-Security \"max-age=")
+                .nth(1)
+                .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+                .and_then(|max_age| max_age.parse().ok()),
+        });
+
+        Some(VhostSpec {
+            domain: domain.to_string(),
+            user: pool.to_string(),
+            root,
+            php_version: php_version.to_string(),
+            aliases,
+            ssl,
+        })
+    }
+
+    /// Renders the nginx config from the stub.
+    pub fn render(&self, stub: &str) -> String {
+        let (redirect, ssl_config) = match &self.ssl {
+            Some(ssl) => (ssl.redirect, render_ssl(&self.domain, ssl)),
+            None => (false, String::new()),
+        };
+        let hsts = match self.hsts() {
+            Some(_) => format!("include {};", hsts_snippet(&self.domain)),
+            None => String::new(),
+        };
+        // ACME HTTP-01 requests stay on plain HTTP, so renewals work even with a broken certificate
+        let ssl_redirect = if redirect {
+            "set $redirect_https $scheme;\n    \
+             if ($request_uri ~ ^/\\.well-known/acme-challenge/) {\n        set $redirect_https \"\";\n    }\n    \
+             if ($redirect_https = http) {\n        return 301 https://$host$request_uri;\n    }"
+        } else {
+            ""
+        };
+
+        stub.replace("{{DOMAIN}}", &self.domain)
+            .replace("{{ALIASES}}", &self.aliases.join(" "))
+            .replace("{{ROOT}}", &self.root)
+            .replace("{{PHP_VERSION}}", &self.php_version)
+            .replace("{{USER}}", &self.user)
+            .replace("{{SAFE_NAME}}", &safe_name(&self.user))
+            .replace("{{TIMESTAMP}}", &chrono::Utc::now().to_rfc3339())
+            .replace("{{SSL_REDIRECT}}", ssl_redirect)
+            .replace("{{SSL_CONFIG}}", &ssl_config)
+            .replace("{{HSTS}}", &hsts)
+    }
+
+    /// The content of the site's HSTS snippet, if it sends the header.
+    pub fn hsts(&self) -> Option<String> {
+        let max_age = self.ssl.as_ref()?.hsts_max_age?;
+        Some(format!("add_header Strict-Transport-Security \"max-age={}\" always;\n", max_age))
+    }
+
+    /// Writes the site's HSTS snippet, or removes it when the site sends no header.
+    pub async fn install_hsts(&self) -> Result<(), String> {
+        put(&hsts_snippet(&self.domain), self.hsts().as_deref()).await
+    }
+}
+
+/// Puts `content` at `path` through sudo, or removes the file for `None`.
+async fn put(path: &str, content: Option<&str>) -> Result<(), String> {
+    let Some(content) = content else {
+        return Command::sudo("rm").arg("-f").arg(path).run().await.map(|_| ()).map_err(|e| format!("Failed to remove {}: {}", path, e));
+    };
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        Command::sudo("mkdir").arg("-p").arg(dir).run().await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let temp = format!("/tmp/{}", path.trim_start_matches('/').replace('/', "_"));
+    fs::write(&temp, content).map_err(|e| e.to_string())?;
+    Command::sudo("mv")
+        .arg(&temp)
+        .arg(path)
+        .run()
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to move {} into place ({}). Ensure daemon has sudo access.", path, e))
+}
+
+fn render_ssl(domain: &str, ssl: &SslSettings) -> String {
+    let http2 = if ssl.http2 { " http2" } else { "" };
+    let mut lines = vec![
+        format!("listen 443 ssl{};", http2),
+        format!("listen [::]:443 ssl{};", http2),
+        format!("ssl_certificate {};", ssl.certificate),
+        format!("ssl_certificate_key {};", ssl.key),
+        "ssl_protocols TLSv1.2 TLSv1.3;".to_string(),
+        "ssl_session_cache shared:SSL:10m;".to_string(),
+        "ssl_session_timeout 1d;".to_string(),
+    ];
+    if ssl.hsts_max_age.is_some() {
+        lines.push(format!("include {};", hsts_snippet(domain)));
+    }
+    lines.join("\n    ")
+}
+
+/// Writes a site's nginx config and reloads nginx. If `nginx -t` rejects the new config the
+/// previous one is put back, so a bad render never takes nginx down.
+pub async fn install(spec: &VhostSpec) -> Result<String, String> {
+    let stub = fs::read_to_string(NGINX_STUB).map_err(|e| format!("Failed to read {}: {}", NGINX_STUB, e))?;
+    let path = nginx_available(&spec.domain);
+    let snippet = hsts_snippet(&spec.domain);
+    let previous = fs::read_to_string(&path).ok();
+    let previous_hsts = fs::read_to_string(&snippet).ok();
+
+    spec.install_hsts().await?;
+    put(&path, Some(&spec.render(&stub))).await?;
+
+    jobs::log(format!("Testing the nginx config for {}", spec.domain)).await;
+    let test = Command::sudo("nginx").arg("-t").timeout(Duration::from_secs(30)).output().await.map_err(|e| e.to_string())?;
+    if !test.success() {
+        let restored = put(&path, previous.as_deref()).await.is_ok() && put(&snippet, previous_hsts.as_deref()).await.is_ok();
+        return Err(format!(
+            "nginx rejected the new config for {}{}: {}",
+            spec.domain,
+            if restored { " (previous config restored)" } else { "" },
+            test.stderr.trim()
+        ));
+    }
+
+    Command::sudo("systemctl").arg("reload").arg("nginx").run().await.map_err(|e| format!("Failed to reload nginx: {}", e))?;
+    spec.save()?;
+    Ok(path)
+}
+
+/// Switches a site to HTTPS with the given certificate, keeping its other settings.
+/// `options` may carry `ssl_redirect`, `http2` and `hsts` (true, false or a max-age).
+pub async fn enable_ssl(domain: &str, certificate: &str, key: &str, options: &Value) -> Result<String, String> {
+    let mut spec = VhostSpec::load(domain)?;
+    spec.ssl = Some(SslSettings::from_params(certificate, key, options, spec.ssl.as_ref()));
+    install(&spec).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STUB: &str = include_str!("../../../resources/templates/system/nginx_vhost.conf.stub");
+
+    fn spec(hsts_max_age: Option<u64>) -> VhostSpec {
+        VhostSpec {
+            domain: "example.com".to_string(),
+            user: "alice".to_string(),
+            root: "/home/alice/example.com/public".to_string(),
+            php_version: "8.4".to_string(),
+            aliases: vec!["www.example.com".to_string()],
+            ssl: Some(SslSettings {
+                certificate: "/etc/supercp/ssl/certs/example.com/fullchain.pem".to_string(),
+                key: "/etc/supercp/ssl/certs/example.com/privkey.pem".to_string(),
+                redirect: true,
+                http2: true,
+                hsts_max_age,
+            }),
+        }
+    }
+
+    #[test]
+    fn hsts_reaches_locations_with_their_own_headers() {
+        let spec = spec(Some(600));
+        let config = spec.render(STUB);
+        assert!(!config.contains("{{"), "unreplaced placeholder in\n{}", config);
+        assert!(!config.contains("Strict-Transport-Security"));
+        assert_eq!(spec.hsts().unwrap(), "add_header Strict-Transport-Security \"max-age=600\" always;\n");
+
+        let include = format!("include {};", hsts_snippet("example.com"));
+        let locations: Vec<&str> = config.split("location ").skip(1).filter(|b| b.contains("add_header")).collect();
+        assert!(!locations.is_empty());
+        for block in &locations {
+            assert!(block.contains(&include), "location without HSTS:\n{}", block);
+        }
+        // Once more at server level, for the locations that inherit its headers
+        assert_eq!(config.matches(&include).count(), locations.len() + 1);
+    }
+
+    #[test]
+    fn no_hsts_without_a_max_age() {
+        let spec = spec(None);
+        assert!(spec.hsts().is_none());
+        assert!(!spec.render(STUB).contains("hsts"));
+    }
+}
@@ -78,7 +78,7 @@ public function test_can_restore_backup(): void
 
         $this->daemonMock->shouldReceive('restoreBackup')
             ->once()
-            ->andReturn('Success');
+            ->andReturn(['message' => 'Backup restored']);
 
         $response = $this->actingAs($this->user)
             ->post(route('backups.restore', $backup));
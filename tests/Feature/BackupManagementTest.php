@@ -21,8 +21,8 @@ protected function setUp(): void
             $mock->shouldReceive('createBackup')->andReturn('/tmp/mock_backup.tar.gz');
             $mock->shouldReceive('createDbBackup')->andReturn('/tmp/mock_db_backup.sql.gz');
             $mock->shouldReceive('deleteFile')->andReturn('success');
-            $mock->shouldReceive('restoreBackup')->andReturn('success');
-            $mock->shouldReceive('restoreDbBackup')->andReturn('success');
+            $mock->shouldReceive('restoreBackup')->andReturn(['message' => 'success']);
+            $mock->shouldReceive('restoreDbBackup')->andReturn(['message' => 'success']);
         });
     }
 
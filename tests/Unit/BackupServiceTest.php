@@ -102,7 +102,7 @@ public function test_can_restore_backup(): void
         $this->daemonMock->shouldReceive('restoreBackup')
             ->once()
             ->with('/path/to/backup.tar.gz', '/var/www/example.com')
-            ->andReturn('Success');
+            ->andReturn(['message' => 'Backup restored to /var/www/example.com']);
 
         $result = $this->backupService->restore($backup);
 